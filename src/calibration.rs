@@ -0,0 +1,226 @@
+//! Measurement of the pulse timing of a rotary dial.
+//!
+//! In calibration mode, `Dial` reports the timing of every dialed digit instead
+//! of dialing it, so that dials which drift away from the standard timing can
+//! be serviced before they start misdialing.
+
+use super::dial::DialRejection;
+
+use std::fmt;
+use std::time::Duration;
+
+/// Minimum speed of a dial within specification in pulses per second.
+const MIN_SPEED: f64 = 9.0;
+/// Maximum speed of a dial within specification in pulses per second.
+const MAX_SPEED: f64 = 11.0;
+/// Minimum break/make ratio within specification (nominal 60/40 = 1.5).
+const MIN_BREAK_MAKE_RATIO: f64 = 1.3;
+/// Maximum break/make ratio within specification.
+const MAX_BREAK_MAKE_RATIO: f64 = 1.9;
+/// Maximum standard deviation of the pulse period.
+const MAX_JITTER: Duration = Duration::from_millis(5);
+
+/// Timing of a single pulse.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PulseTiming {
+    /// Time for which the nsi contact was open ("break").
+    pub pulse: Duration,
+    /// Time for which the nsi contact was closed before the next pulse
+    /// ("make"), or `None` for the last pulse of a digit.
+    pub pause: Option<Duration>,
+}
+
+/// Timing measurement of a single digit.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CalibrationReport {
+    /// The digit as decoded with the configured limits.
+    pub result: Result<u32, DialRejection>,
+    pub pulses: Vec<PulseTiming>,
+    /// Average speed in pulses per second.
+    pub speed: Option<f64>,
+    /// Average ratio between the break and make times.
+    pub break_make_ratio: Option<f64>,
+    /// Standard deviation of the pulse period.
+    pub jitter: Option<Duration>,
+}
+
+impl CalibrationReport {
+    /// Calculates the statistics of a digit.
+    ///
+    /// Speed, ratio and jitter are only available if the digit consisted of at
+    /// least two pulses, as the last pulse has no pause.
+    pub fn new(result: Result<u32, DialRejection>, pulses: &[PulseTiming]) -> Self {
+        let periods = pulses
+            .iter()
+            .filter_map(|timing| timing.pause.map(|pause| (timing.pulse, pause)))
+            .map(|(pulse, pause)| (pulse.as_secs_f64(), pause.as_secs_f64()))
+            .collect::<Vec<_>>();
+        let (speed, break_make_ratio, jitter) = if periods.is_empty() {
+            (None, None, None)
+        } else {
+            let count = periods.len() as f64;
+            let mean_break = periods.iter().map(|(pulse, _)| pulse).sum::<f64>() / count;
+            let mean_make = periods.iter().map(|(_, pause)| pause).sum::<f64>() / count;
+            let mean_period = mean_break + mean_make;
+            let variance = periods
+                .iter()
+                .map(|(pulse, pause)| (pulse + pause - mean_period).powi(2))
+                .sum::<f64>()
+                / count;
+            (
+                Some(1.0 / mean_period),
+                Some(mean_break / mean_make),
+                Some(Duration::from_secs_f64(variance.sqrt())),
+            )
+        };
+        Self {
+            result,
+            pulses: pulses.to_vec(),
+            speed,
+            break_make_ratio,
+            jitter,
+        }
+    }
+
+    /// Returns a description of each deviation from the specification.
+    pub fn warnings(&self) -> Vec<String> {
+        let mut warnings = Vec::new();
+        if let Err(rejection) = &self.result {
+            warnings.push(format!("digit rejected: {:?}", rejection));
+        }
+        match self.speed {
+            Some(speed) if !(MIN_SPEED..=MAX_SPEED).contains(&speed) => warnings.push(format!(
+                "speed of {:.1} pulses/s is outside of {}-{} pulses/s",
+                speed, MIN_SPEED, MAX_SPEED
+            )),
+            Some(_) => {}
+            None => warnings.push("not enough pulses, dial 0 for a full measurement".to_string()),
+        }
+        if let Some(ratio) = self.break_make_ratio {
+            if !(MIN_BREAK_MAKE_RATIO..=MAX_BREAK_MAKE_RATIO).contains(&ratio) {
+                warnings.push(format!(
+                    "break/make ratio of {:.2} is outside of {}-{}",
+                    ratio, MIN_BREAK_MAKE_RATIO, MAX_BREAK_MAKE_RATIO
+                ));
+            }
+        }
+        if let Some(jitter) = self.jitter {
+            if jitter > MAX_JITTER {
+                warnings.push(format!(
+                    "jitter of {:.1}ms exceeds {}ms",
+                    jitter.as_secs_f64() * 1000.0,
+                    MAX_JITTER.as_millis()
+                ));
+            }
+        }
+        warnings
+    }
+}
+
+impl fmt::Display for CalibrationReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.result {
+            Ok(digit) => write!(f, "digit {}", digit)?,
+            Err(_) => write!(f, "invalid digit")?,
+        }
+        match self.pulses.len() {
+            1 => write!(f, " (1 pulse)")?,
+            count => write!(f, " ({} pulses)", count)?,
+        }
+        if let (Some(speed), Some(ratio), Some(jitter)) =
+            (self.speed, self.break_make_ratio, self.jitter)
+        {
+            let break_percent = ratio / (ratio + 1.0) * 100.0;
+            write!(
+                f,
+                ": {:.2} pulses/s, break/make {:.0}/{:.0}, jitter {:.1}ms",
+                speed,
+                break_percent,
+                100.0 - break_percent,
+                jitter.as_secs_f64() * 1000.0
+            )?;
+        }
+        for warning in self.warnings() {
+            write!(f, "\n  warning: {}", warning)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pulses(timing: &[(u64, u64)]) -> Vec<PulseTiming> {
+        let mut pulses = timing
+            .iter()
+            .map(|&(pulse, pause)| PulseTiming {
+                pulse: Duration::from_millis(pulse),
+                pause: Some(Duration::from_millis(pause)),
+            })
+            .collect::<Vec<_>>();
+        if let Some(last) = pulses.last_mut() {
+            last.pause = None;
+        }
+        pulses
+    }
+
+    fn assert_close(value: f64, expected: f64) {
+        assert!((value - expected).abs() < 1e-6, "{} != {}", value, expected);
+    }
+
+    #[test]
+    fn test_standard_dial() {
+        let report = CalibrationReport::new(Ok(0), &pulses(&[(60, 40); 10]));
+        assert_close(report.speed.unwrap(), 10.0);
+        assert_close(report.break_make_ratio.unwrap(), 1.5);
+        assert!(report.jitter.unwrap() < Duration::from_micros(1));
+        assert!(report.warnings().is_empty());
+        assert_eq!(
+            report.to_string(),
+            "digit 0 (10 pulses): 10.00 pulses/s, break/make 60/40, jitter 0.0ms"
+        );
+    }
+
+    #[test]
+    fn test_out_of_spec() {
+        // Slow dial.
+        let report = CalibrationReport::new(Ok(3), &pulses(&[(75, 50); 3]));
+        assert_close(report.speed.unwrap(), 8.0);
+        assert_eq!(report.warnings().len(), 1);
+        assert!(report.warnings()[0].contains("speed"));
+
+        // Wrong break/make ratio.
+        let report = CalibrationReport::new(Ok(3), &pulses(&[(70, 30); 3]));
+        assert_close(report.speed.unwrap(), 10.0);
+        assert_close(report.break_make_ratio.unwrap(), 70.0 / 30.0);
+        assert_eq!(report.warnings().len(), 1);
+        assert!(report.warnings()[0].contains("break/make"));
+
+        // Jitter.
+        let report = CalibrationReport::new(
+            Ok(5),
+            &pulses(&[(50, 40), (70, 40), (50, 40), (70, 40), (60, 40)]),
+        );
+        assert_close(report.speed.unwrap(), 10.0);
+        assert_close(report.jitter.unwrap().as_secs_f64(), 0.01);
+        assert_eq!(report.warnings().len(), 1);
+        assert!(report.warnings()[0].contains("jitter"));
+
+        // Rejected digits are reported as well.
+        let rejection = DialRejection::PulseTooLong(Duration::from_millis(120));
+        let report = CalibrationReport::new(Err(rejection), &pulses(&[(120, 40); 2]));
+        assert!(report.warnings()[0].contains("rejected"));
+        assert!(report.to_string().starts_with("invalid digit (2 pulses)"));
+    }
+
+    #[test]
+    fn test_single_pulse() {
+        let report = CalibrationReport::new(Ok(1), &pulses(&[(60, 40)]));
+        assert_eq!(report.speed, None);
+        assert_eq!(report.break_make_ratio, None);
+        assert_eq!(report.jitter, None);
+        assert_eq!(report.warnings().len(), 1);
+        assert!(report.to_string().starts_with("digit 1 (1 pulse)\n"));
+    }
+}
@@ -0,0 +1,77 @@
+//! Source of the current time for the threads which measure pin timing.
+//!
+//! The peripherals use a `Clock` instead of calling `Instant::now()` directly so
+//! that tests can run them in the virtual time of a `SimEnvironment` (see
+//! `SimEnvironment::with_virtual_time()`).
+
+use std::sync::{Condvar, Mutex};
+use std::time::Instant;
+
+/// Clock which returns the current time.
+///
+/// The timeouts passed to the pins are durations, so the pins have to wait
+/// according to the same clock.
+pub trait Clock: Send + 'static {
+    type Timer: Timer;
+
+    fn now(&self) -> Instant;
+    /// Creates a timer which waits according to this clock.
+    fn timer(&self) -> Self::Timer;
+}
+
+/// Object which a thread uses to sleep until a deadline of its clock, and
+/// which other threads use to wake it up early.
+pub trait Timer: Send + Sync + 'static {
+    /// Waits until the deadline has passed or `wake()` is called.
+    ///
+    /// The function can also return early for no reason, so the caller has to
+    /// check the time and its state again.
+    fn wait_until(&self, deadline: Option<Instant>);
+    /// Wakes up the thread waiting for the timer. If no thread is waiting,
+    /// the next wait returns immediately.
+    fn wake(&self);
+}
+
+/// Real time of the operating system.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    type Timer = SystemTimer;
+
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+    fn timer(&self) -> SystemTimer {
+        SystemTimer {
+            woken: Mutex::new(false),
+            condvar: Condvar::new(),
+        }
+    }
+}
+
+/// Timer of the `SystemClock`.
+pub struct SystemTimer {
+    woken: Mutex<bool>,
+    condvar: Condvar,
+}
+
+impl Timer for SystemTimer {
+    fn wait_until(&self, deadline: Option<Instant>) {
+        let mut woken = self.woken.lock().unwrap();
+        if !*woken {
+            woken = match deadline {
+                Some(deadline) => {
+                    let timeout = deadline.saturating_duration_since(Instant::now());
+                    self.condvar.wait_timeout(woken, timeout).unwrap().0
+                }
+                None => self.condvar.wait(woken).unwrap(),
+            };
+        }
+        *woken = false;
+    }
+    fn wake(&self) {
+        *self.woken.lock().unwrap() = true;
+        self.condvar.notify_all();
+    }
+}
@@ -1,24 +1,326 @@
 //! Alternative implementations to control the application via stdin.
 
+use super::calibration::{CalibrationReport, PulseTiming};
+use super::state::Bell;
 use super::Event;
 
+use std::io;
+use std::os::unix::io::AsRawFd;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::mpsc::Sender;
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
 
+/// Time for which the simulated dial opens the nsi contact for each pulse.
+const BREAK_DURATION: Duration = Duration::from_millis(60);
+/// Time for which the simulated dial closes the nsi contact between pulses.
+const MAKE_DURATION: Duration = Duration::from_millis(40);
+/// Duration of a single pulse of a rotary dial (10 pulses per second).
+const PULSE_DURATION: Duration = Duration::from_millis(100);
+/// Pause between two digits, i.e., the time to wind up the dial again.
+const INTER_DIGIT_PAUSE: Duration = Duration::from_millis(800);
+
+const HELP: &str = "Commands:
+  up          pick up the earpiece
+  down        put down the earpiece
+  flash       briefly press the hook switch (hold, swap, answer waiting call)
+  dial <num>  dial the digits of <num>
+  calibrate   switch dial calibration on or off (measure instead of dialing)
+  status      print the state of the phone
+  help        print this help";
+
+/// Command entered on the console.
+#[derive(Debug, PartialEq)]
+enum Command {
+    PickUp,
+    PutDown,
+    Flash,
+    Dial(Vec<u32>),
+    Calibrate,
+    Status,
+    Help,
+}
+
+fn parse_command(line: &str) -> Result<Option<Command>, String> {
+    let mut words = line.split_whitespace();
+    let command = match words.next() {
+        Some(command) => command,
+        None => return Ok(None),
+    };
+    let command = match command {
+        "up" => Command::PickUp,
+        "down" => Command::PutDown,
+        "flash" => Command::Flash,
+        "dial" => {
+            let number: String = words.by_ref().collect();
+            if number.is_empty() {
+                return Err("dial: missing number".to_string());
+            }
+            let digits = number
+                .chars()
+                .map(|c| c.to_digit(10))
+                .collect::<Option<Vec<_>>>()
+                .ok_or_else(|| format!("dial: invalid number \"{}\"", number))?;
+            Command::Dial(digits)
+        }
+        "calibrate" => Command::Calibrate,
+        "status" => Command::Status,
+        "help" => Command::Help,
+        other => return Err(format!("unknown command \"{}\", try \"help\"", other)),
+    };
+    if words.next().is_some() {
+        return Err(format!("{}: too many arguments", command_name(&command)));
+    }
+    Ok(Some(command))
+}
+
+fn command_name(command: &Command) -> &'static str {
+    match command {
+        Command::PickUp => "up",
+        Command::PutDown => "down",
+        Command::Flash => "flash",
+        Command::Dial(_) => "dial",
+        Command::Calibrate => "calibrate",
+        Command::Status => "status",
+        Command::Help => "help",
+    }
+}
+
+/// Returns the time a rotary dial needs to dial the digit.
+fn dial_duration(digit: u32) -> Duration {
+    let pulses = if digit == 0 { 10 } else { digit };
+    PULSE_DURATION * pulses + INTER_DIGIT_PAUSE
+}
+
+/// Returns the pulse timing of the digit as measured in calibration mode.
+fn pulse_timings(digit: u32) -> Vec<PulseTiming> {
+    let pulses = if digit == 0 { 10 } else { digit };
+    (1..=pulses)
+        .map(|pulse| PulseTiming {
+            pulse: BREAK_DURATION,
+            pause: if pulse < pulses {
+                Some(MAKE_DURATION)
+            } else {
+                None
+            },
+        })
+        .collect()
+}
+
+/// Sleeps for the specified duration, but returns early (with `false`) if the
+/// thread shall be stopped.
+fn sleep_unless_stopped(stop_thread: &AtomicBool, duration: Duration) -> bool {
+    let end = Instant::now() + duration;
+    loop {
+        if stop_thread.load(Ordering::SeqCst) {
+            return false;
+        }
+        let now = Instant::now();
+        if now >= end {
+            return true;
+        }
+        thread::sleep((end - now).min(Duration::from_millis(50)));
+    }
+}
+
+/// Interface which reads commands from stdin and converts them into events.
+///
+/// This type replaces the phone hardware and is mainly useful to debug the SIP
+/// call flow on a machine without GPIOs. The simulated dial has the nominal
+/// timing, and in calibration mode its measurement is reported instead of the
+/// digits.
 pub struct ConsoleInput {
-    // TODO
+    thread: Option<JoinHandle<()>>,
+    stop_thread: Arc<AtomicBool>,
 }
 
 impl ConsoleInput {
-    pub fn new(sender: Sender<Event>) -> ConsoleInput {
-        // TODO
+    pub fn new(sender: Sender<Event>, calibration: bool) -> ConsoleInput {
+        let stop_thread = Arc::new(AtomicBool::new(false));
+        let stop_copy = stop_thread.clone();
+        let thread = thread::spawn(move || {
+            println!("{}", HELP);
+            let mut calibration = calibration;
+            let stdin = io::stdin();
+            let mut line = Vec::new();
+            let mut buffer = [0u8; 4096];
+            loop {
+                // Wait with timeout to allow Drop to terminate the thread in a
+                // timely fashion.
+                let ready = crate::gpio::poll::poll(
+                    &[stdin.as_raw_fd()],
+                    libc::POLLIN,
+                    Some(Duration::from_millis(1000)),
+                )
+                .expect("could not wait for console input");
+                if stop_thread.load(Ordering::SeqCst) {
+                    return;
+                }
+                if ready == 0 {
+                    continue;
+                }
+                // The file descriptor is read directly, as data left in the
+                // buffer of `Stdin` would not be reported by `poll()`.
+                let length = unsafe {
+                    libc::read(
+                        stdin.as_raw_fd(),
+                        buffer.as_mut_ptr() as *mut libc::c_void,
+                        buffer.len(),
+                    )
+                };
+                if length < 0 && io::Error::last_os_error().kind() == io::ErrorKind::Interrupted {
+                    continue;
+                }
+                if length <= 0 {
+                    // End of file or read error.
+                    return;
+                }
+                line.extend_from_slice(&buffer[..length as usize]);
+
+                while let Some(end) = line.iter().position(|&c| c == b'\n') {
+                    let text = String::from_utf8_lossy(&line[..end]).to_string();
+                    line.drain(..=end);
+                    let command = match parse_command(&text) {
+                        Ok(Some(command)) => command,
+                        Ok(None) => continue,
+                        Err(e) => {
+                            println!("{}", e);
+                            continue;
+                        }
+                    };
+                    let result = match command {
+                        Command::PickUp => sender.send(Event::EarpiecePickedUp),
+                        Command::PutDown => sender.send(Event::EarpiecePutDown),
+                        Command::Flash => sender.send(Event::HookFlash),
+                        Command::Dial(digits) => {
+                            let mut result = Ok(());
+                            for digit in digits {
+                                // Simulate the time a rotary dial needs.
+                                if !sleep_unless_stopped(&stop_thread, dial_duration(digit)) {
+                                    return;
+                                }
+                                let event = if calibration {
+                                    Event::DialCalibrated(CalibrationReport::new(
+                                        Ok(digit),
+                                        &pulse_timings(digit),
+                                    ))
+                                } else {
+                                    Event::Dialed(digit)
+                                };
+                                result = sender.send(event);
+                                if result.is_err() {
+                                    break;
+                                }
+                            }
+                            result
+                        }
+                        Command::Calibrate => {
+                            calibration = !calibration;
+                            println!(
+                                "Dial calibration mode {}.",
+                                if calibration { "on" } else { "off" }
+                            );
+                            Ok(())
+                        }
+                        Command::Status => sender.send(Event::StatusRequested),
+                        Command::Help => {
+                            println!("{}", HELP);
+                            Ok(())
+                        }
+                    };
+                    if result.is_err() {
+                        return;
+                    }
+                }
+            }
+        });
         ConsoleInput {
-            // TODO
+            thread: Some(thread),
+            stop_thread: stop_copy,
         }
     }
 }
 
 impl Drop for ConsoleInput {
     fn drop(&mut self) {
-        // TODO
+        self.stop_thread.store(true, Ordering::SeqCst);
+        let thread = self.thread.take();
+        thread.unwrap().join().unwrap();
+    }
+}
+
+/// Bell replacement which prints to stdout.
+pub struct ConsoleBell;
+
+impl Bell for ConsoleBell {
+    fn start_ringing(&mut self) {
+        println!("*ring* *ring*");
+    }
+    fn stop_ringing(&mut self) {
+        println!("(the bell stops ringing)");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_command() {
+        assert_eq!(parse_command("up"), Ok(Some(Command::PickUp)));
+        assert_eq!(parse_command("  down \r"), Ok(Some(Command::PutDown)));
+        assert_eq!(parse_command("flash"), Ok(Some(Command::Flash)));
+        assert_eq!(parse_command("calibrate"), Ok(Some(Command::Calibrate)));
+        assert_eq!(parse_command("status"), Ok(Some(Command::Status)));
+        assert_eq!(parse_command("help"), Ok(Some(Command::Help)));
+        assert_eq!(parse_command(""), Ok(None));
+        assert_eq!(
+            parse_command("dial 0301234"),
+            Ok(Some(Command::Dial(vec![0, 3, 0, 1, 2, 3, 4])))
+        );
+        // Spaces within numbers are allowed.
+        assert_eq!(
+            parse_command("dial 030 12"),
+            Ok(Some(Command::Dial(vec![0, 3, 0, 1, 2])))
+        );
+        assert!(parse_command("dial").is_err());
+        assert!(parse_command("dial 12a").is_err());
+        assert!(parse_command("up now").is_err());
+        assert!(parse_command("calibrate now").is_err());
+        assert!(parse_command("ring").is_err());
+    }
+
+    #[test]
+    fn test_dial_duration() {
+        assert_eq!(dial_duration(1), Duration::from_millis(900));
+        assert_eq!(dial_duration(9), Duration::from_millis(1700));
+        assert_eq!(dial_duration(0), Duration::from_millis(1800));
+    }
+
+    #[test]
+    fn test_pulse_timings() {
+        let timings = pulse_timings(0);
+        assert_eq!(timings.len(), 10);
+        assert_eq!(timings[9].pause, None);
+
+        // The simulated dial is within the specification.
+        let report = CalibrationReport::new(Ok(0), &timings);
+        assert!(report.warnings().is_empty());
+        assert!((report.speed.unwrap() - 10.0).abs() < 1e-9);
+        assert!((report.break_make_ratio.unwrap() - 1.5).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_sleep_unless_stopped() {
+        let stop_thread = AtomicBool::new(false);
+        assert!(sleep_unless_stopped(
+            &stop_thread,
+            Duration::from_millis(10)
+        ));
+        stop_thread.store(true, Ordering::SeqCst);
+        let start = Instant::now();
+        assert!(!sleep_unless_stopped(&stop_thread, Duration::from_secs(10)));
+        assert!(start.elapsed() < Duration::from_secs(1));
     }
 }
@@ -0,0 +1,98 @@
+//! Filter for contact bounce of mechanical switches.
+
+use std::time::{Duration, Instant};
+
+/// Debouncer which only accepts a new value once it has been stable for the
+/// configured time.
+///
+/// The accepted change is reported with the time of the first edge, so that
+/// the durations measured by the caller are not shifted by the filter.
+pub struct Debouncer<T> {
+    window: Duration,
+    stable: T,
+    /// Changed value which has not been stable for long enough yet, and the
+    /// time of the change.
+    pending: Option<(T, Instant)>,
+}
+
+impl<T: Copy + PartialEq> Debouncer<T> {
+    pub fn new(window: Duration, initial: T) -> Self {
+        Self {
+            window,
+            stable: initial,
+            pending: None,
+        }
+    }
+
+    /// Records a value read from the input at the specified time.
+    pub fn update(&mut self, value: T, now: Instant) {
+        if value == self.stable {
+            // Contact bounce, the input returned to its previous state.
+            self.pending = None;
+        } else if self.pending.map(|(pending, _)| pending) != Some(value) {
+            self.pending = Some((value, now));
+        }
+    }
+
+    /// Returns the time at which a pending change will be accepted.
+    pub fn deadline(&self) -> Option<Instant> {
+        self.pending.map(|(_, since)| since + self.window)
+    }
+
+    /// Returns the time of the pending change, if any.
+    pub fn pending_since(&self) -> Option<Instant> {
+        self.pending.map(|(_, since)| since)
+    }
+
+    /// Returns the new value and the time of the change once a pending change
+    /// has been stable for the debounce window.
+    pub fn poll(&mut self, now: Instant) -> Option<(T, Instant)> {
+        match self.pending {
+            Some((value, since)) if now - since >= self.window => {
+                self.pending = None;
+                self.stable = value;
+                Some((value, since))
+            }
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_debouncer() {
+        let mut debouncer = Debouncer::new(Duration::from_millis(10), false);
+        let start = Instant::now();
+        let ms = |ms| start + Duration::from_millis(ms);
+        assert_eq!(debouncer.deadline(), None);
+        assert_eq!(debouncer.poll(ms(100)), None);
+
+        // Glitches are ignored.
+        debouncer.update(true, ms(0));
+        assert_eq!(debouncer.deadline(), Some(ms(10)));
+        debouncer.update(false, ms(2));
+        assert_eq!(debouncer.deadline(), None);
+        assert_eq!(debouncer.poll(ms(20)), None);
+
+        // Bouncing to the same value does not restart the window.
+        debouncer.update(true, ms(20));
+        debouncer.update(true, ms(25));
+        assert_eq!(debouncer.pending_since(), Some(ms(20)));
+        assert_eq!(debouncer.poll(ms(29)), None);
+        assert_eq!(debouncer.poll(ms(30)), Some((true, ms(20))));
+        assert_eq!(debouncer.poll(ms(40)), None);
+
+        debouncer.update(false, ms(50));
+        debouncer.update(true, ms(51));
+        debouncer.update(false, ms(52));
+        assert_eq!(debouncer.poll(ms(62)), Some((false, ms(52))));
+
+        // Without a window, changes are accepted immediately.
+        let mut debouncer = Debouncer::new(Duration::from_millis(0), 0u64);
+        debouncer.update(3, ms(0));
+        assert_eq!(debouncer.poll(ms(0)), Some((3, ms(0))));
+    }
+}
@@ -1,5 +1,8 @@
 //! Interface to a rotary dial connected via GPIOs.
 
+use super::calibration::{CalibrationReport, PulseTiming};
+use super::clock::Clock;
+use super::debounce::Debouncer;
 use super::gpio::{InputPin, InputPinGroup};
 use super::Event;
 
@@ -7,73 +10,327 @@ use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::mpsc::Sender;
 use std::sync::Arc;
 use std::thread::{self, JoinHandle};
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 /// Index of the nsa contact in the pin group.
 const NSA: usize = 0;
 /// Index of the nsi contact in the pin group.
 const NSI: usize = 1;
 
+/// Contacts of the dial which are connected.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum DialMode {
+    /// The nsa contact signals the start and end of each digit, the nsi
+    /// contact generates the pulses.
+    NsaNsi,
+    /// Only the nsi contact is connected, a digit ends when no further pulse
+    /// has been received for `digit_gap_ms`.
+    NsiOnly,
+    /// The phone has a push-button keypad instead of a rotary dial (see
+    /// `Keypad`).
+    Keypad,
+}
+
+/// Timing limits of the dial pulses.
+///
+/// A standard dial produces 10 pulses per second with a break/make ratio of
+/// 60/40, i.e., the nsi contact is open for 60ms and closed for 40ms.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(default)]
+pub struct DialConfig {
+    pub mode: DialMode,
+    /// Time without pulses in milliseconds after which a digit is complete in
+    /// `NsiOnly` mode. Has to be longer than the pause between two pulses.
+    pub digit_gap_ms: u64,
+    /// Minimum duration of a pulse (nsi open) in milliseconds.
+    pub pulse_min_ms: u64,
+    /// Maximum duration of a pulse (nsi open) in milliseconds.
+    pub pulse_max_ms: u64,
+    /// Minimum duration of the pause between two pulses (nsi closed) in
+    /// milliseconds.
+    pub pause_min_ms: u64,
+    /// Maximum duration of the pause between two pulses (nsi closed) in
+    /// milliseconds.
+    pub pause_max_ms: u64,
+    /// Time in milliseconds for which a contact has to be stable before a
+    /// change is accepted. Shorter glitches are treated as contact bounce.
+    pub debounce_ms: u64,
+    /// If true, the timing of each digit is reported as
+    /// `Event::DialCalibrated` instead of dialing the digit.
+    pub calibration: bool,
+}
+
+impl Default for DialConfig {
+    fn default() -> Self {
+        Self {
+            mode: DialMode::NsaNsi,
+            digit_gap_ms: 200,
+            pulse_min_ms: 40,
+            pulse_max_ms: 100,
+            pause_min_ms: 20,
+            pause_max_ms: 75,
+            debounce_ms: 5,
+            calibration: false,
+        }
+    }
+}
+
+/// Reason why a dialed digit was rejected.
+#[derive(Debug, Clone, PartialEq)]
+pub enum DialRejection {
+    /// A pulse was shorter than the configured minimum.
+    PulseTooShort(Duration),
+    /// A pulse was longer than the configured maximum.
+    PulseTooLong(Duration),
+    /// A pause between two pulses was shorter than the configured minimum.
+    PauseTooShort(Duration),
+    /// A pause between two pulses was longer than the configured maximum.
+    PauseTooLong(Duration),
+    /// More than 10 pulses were counted.
+    TooManyPulses(u32),
+}
+
+/// Decoder which converts the debounced contact states into digits.
+struct PulseDecoder {
+    config: DialConfig,
+    /// Time after the last pulse after which the digit is complete if the nsa
+    /// contact is not used.
+    digit_gap: Option<Duration>,
+    /// Whether the nsa contact is closed and pulses are counted.
+    counting: bool,
+    count: u32,
+    /// Start of the current pulse if the nsi contact is open.
+    pulse_start: Option<Instant>,
+    /// End of the last pulse of the current digit.
+    pulse_end: Option<Instant>,
+    /// First timing violation of the current digit.
+    rejection: Option<DialRejection>,
+    /// Timing of the completed pulses of the current or last digit.
+    timings: Vec<PulseTiming>,
+}
+
+impl PulseDecoder {
+    /// Creates a decoder. If `nsi_only` is true, the nsa contact is assumed to
+    /// be always closed and digits end after `DialConfig::digit_gap_ms`.
+    fn new(config: DialConfig, nsi_only: bool) -> Self {
+        let digit_gap = if nsi_only {
+            Some(Duration::from_millis(config.digit_gap_ms))
+        } else {
+            None
+        };
+        Self {
+            config,
+            digit_gap,
+            counting: false,
+            count: 0,
+            pulse_start: None,
+            pulse_end: None,
+            rejection: None,
+            timings: Vec::new(),
+        }
+    }
+
+    /// Processes the state of the contacts (`true` means closed) at the
+    /// specified time.
+    ///
+    /// Returns the digit or the reason for its rejection once the nsa contact
+    /// opens again. No result is returned if no pulse was counted.
+    fn update(&mut self, nsa: bool, nsi: bool, now: Instant) -> Option<Result<u32, DialRejection>> {
+        if nsa && !self.counting {
+            self.counting = true;
+            self.count = 0;
+            self.pulse_start = None;
+            self.pulse_end = None;
+            self.rejection = None;
+            self.timings.clear();
+        }
+        if self.counting {
+            if !nsi && self.pulse_start.is_none() {
+                self.pulse_start = Some(now);
+                self.count += 1;
+                if let Some(pulse_end) = self.pulse_end {
+                    let pause = now - pulse_end;
+                    if let Some(timing) = self.timings.last_mut() {
+                        timing.pause = Some(pause);
+                    }
+                    if pause < Duration::from_millis(self.config.pause_min_ms) {
+                        self.reject(DialRejection::PauseTooShort(pause));
+                    } else if pause > Duration::from_millis(self.config.pause_max_ms) {
+                        self.reject(DialRejection::PauseTooLong(pause));
+                    }
+                }
+            } else if nsi {
+                if let Some(pulse_start) = self.pulse_start.take() {
+                    let pulse = now - pulse_start;
+                    if pulse < Duration::from_millis(self.config.pulse_min_ms) {
+                        self.reject(DialRejection::PulseTooShort(pulse));
+                    } else if pulse > Duration::from_millis(self.config.pulse_max_ms) {
+                        self.reject(DialRejection::PulseTooLong(pulse));
+                    }
+                    self.pulse_end = Some(now);
+                    self.timings.push(PulseTiming { pulse, pause: None });
+                }
+            }
+        }
+        if !nsa && self.counting {
+            self.counting = false;
+            self.pulse_start = None;
+            return match self.count {
+                // The dial was moved without producing any pulses.
+                0 => None,
+                count if count > 10 => Some(Err(DialRejection::TooManyPulses(count))),
+                count => Some(match self.rejection.take() {
+                    Some(rejection) => Err(rejection),
+                    // 10 impulses = '0'
+                    None => Ok(count % 10),
+                }),
+            };
+        }
+        None
+    }
+
+    /// Returns the time at which the current digit is complete if no further
+    /// pulse is received (only without nsa contact).
+    fn deadline(&self) -> Option<Instant> {
+        match (self.digit_gap, self.pulse_end) {
+            (Some(gap), Some(pulse_end)) if self.counting && self.pulse_start.is_none() => {
+                Some(pulse_end + gap)
+            }
+            _ => None,
+        }
+    }
+
+    /// Completes the current digit after the deadline has expired.
+    fn expire(&mut self, now: Instant) -> Option<Result<u32, DialRejection>> {
+        self.update(false, true, now)
+    }
+
+    /// Returns the timing of the pulses of the last digit.
+    fn timings(&self) -> &[PulseTiming] {
+        &self.timings
+    }
+
+    fn reject(&mut self, rejection: DialRejection) {
+        if self.rejection.is_none() {
+            self.rejection = Some(rejection);
+        }
+    }
+}
+
 /// Interface to a rotary dial.
 ///
 /// See [Wikipedia](https://de.wikipedia.org/wiki/Nummernschalter) for a
 /// description of pulse dialing. The names used for the contacts are taken from
 /// that german description.
 ///
-/// The implementation assumes that the switches are active-low, meaning that a
-/// pin value of `false` signals that the switch has been closed.
+/// A pin value of `true` signals that the switch has been closed. Contacts which
+/// are wired active-low have to be wrapped in a `PolarityInputPin`.
+///
+/// Digits whose pulses do not match the timing limits in the configuration are
+/// reported as `Event::DialRejected`. The pulses are timed with the specified
+/// clock, which has to be the clock the pins use for their timeouts.
 pub struct Dial {
     thread: Option<JoinHandle<()>>,
     stop_thread: Arc<AtomicBool>,
 }
 
 impl Dial {
-    pub fn new<Pin: InputPin + Send + 'static>(nsa: Pin, nsi: Pin, sender: Sender<Event>) -> Self {
+    pub fn new<Pin: InputPin + Send + 'static, C: Clock>(
+        nsa: Pin,
+        nsi: Pin,
+        config: DialConfig,
+        clock: C,
+        sender: Sender<Event>,
+    ) -> Self {
+        Self::spawn(
+            vec![Box::new(nsa), Box::new(nsi)],
+            false,
+            config,
+            clock,
+            sender,
+        )
+    }
+
+    /// Creates a dial for which only the nsi contact is connected.
+    ///
+    /// The end of a digit is detected via `DialConfig::digit_gap_ms`.
+    pub fn new_nsi_only<Pin: InputPin + Send + 'static, C: Clock>(
+        nsi: Pin,
+        config: DialConfig,
+        clock: C,
+        sender: Sender<Event>,
+    ) -> Self {
+        Self::spawn(vec![Box::new(nsi)], true, config, clock, sender)
+    }
+
+    fn spawn<Pin: InputPin + Send + 'static, C: Clock>(
+        pins: Vec<Box<Pin>>,
+        nsi_only: bool,
+        config: DialConfig,
+        clock: C,
+        sender: Sender<Event>,
+    ) -> Self {
         let stop_thread = Arc::new(AtomicBool::new(false));
         let stop_copy = stop_thread.clone();
         let thread = thread::spawn(move || {
-            let pins = Pin::create_group(vec![Box::new(nsa), Box::new(nsi)]);
+            let mut pins = Pin::create_group(pins);
+            pins.set_stop_flag(stop_thread.clone());
+            let nsi_index = if nsi_only { 0 } else { NSI };
 
-            let mut counting = false;
-            let mut count = 0;
-            let mut impulse = false;
+            let debounce = Duration::from_millis(config.debounce_ms);
+            let calibration = config.calibration;
+            let mut decoder = PulseDecoder::new(config, nsi_only);
+            let mut debouncer = Debouncer::new(debounce, pins.read());
 
             loop {
                 // Wait with timeout to allow Drop to terminate the thread in a
                 // timely fashion.
-                let wait_result = pins.wait_timeout(Duration::from_millis(1000));
+                let timeout = match debouncer
+                    .deadline()
+                    .into_iter()
+                    .chain(decoder.deadline())
+                    .min()
+                {
+                    Some(deadline) => deadline.saturating_duration_since(clock.now()),
+                    None => Duration::from_millis(1000),
+                };
+                let wait_result = pins.wait_timeout(timeout.min(Duration::from_millis(1000)));
                 if stop_thread.load(Ordering::SeqCst) {
                     return;
                 }
+                let now = clock.now();
                 if wait_result.is_some() {
-                    let pin_state = pins.read();
-                    // "== 0" because the inputs are active-low
-                    let nsa = (pin_state & (1 << NSA)) == 0;
-                    let nsi = (pin_state & (1 << NSI)) == 0;
-                    if nsa && !counting {
-                        counting = true;
-                        count = 0;
-                    } else if !nsa && counting {
-                        counting = false;
-                        // 10 impulses = '0'
-                        let digit = match count {
-                            10 => 0,
-                            count => count,
-                        };
-                        // 0 impulses or more than 10 impulses = invalid
-                        if count != 0 && count <= 10 {
-                            let result = sender.send(Event::Dialed(digit));
-                            if result.is_err() {
-                                break;
-                            }
-                        }
+                    debouncer.update(pins.read(), now);
+                }
+
+                let mut result = None;
+                if let Some((pin_state, since)) = debouncer.poll(now) {
+                    let nsa = nsi_only || (pin_state & (1 << NSA)) != 0;
+                    let nsi = (pin_state & (1 << nsi_index)) != 0;
+                    result = decoder.update(nsa, nsi, since);
+                }
+                if let Some(deadline) = decoder.deadline() {
+                    // A pending pulse which started before the deadline still
+                    // belongs to the current digit.
+                    let pulse_pending = debouncer
+                        .pending_since()
+                        .is_some_and(|since| since < deadline);
+                    if now >= deadline && !pulse_pending {
+                        result = decoder.expire(deadline);
                     }
-                    if counting && !nsi && !impulse {
-                        impulse = true;
-                        count += 1;
-                    } else if counting && nsi && impulse {
-                        impulse = false;
+                }
+
+                let event = match result {
+                    Some(result) if calibration => {
+                        Event::DialCalibrated(CalibrationReport::new(result, decoder.timings()))
                     }
+                    Some(Ok(digit)) => Event::Dialed(digit),
+                    Some(Err(rejection)) => Event::DialRejected(rejection),
+                    None => continue,
+                };
+                if sender.send(event).is_err() {
+                    break;
                 }
             }
         });
@@ -95,13 +352,18 @@ impl Drop for Dial {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::gpio::polarity::{Polarity, PolarityInputPin};
     use crate::gpio::sim::{SimEnvironment, SimInputPin};
 
     use std::sync::mpsc::{channel, Receiver};
-    use std::thread::sleep;
 
-    fn create_test_dial() -> (SimEnvironment, Dial, Receiver<Event>) {
-        let env = SimEnvironment::new();
+    /// The contacts of the dial are wired active-low.
+    fn active_low(pin: SimInputPin) -> PolarityInputPin<SimInputPin> {
+        PolarityInputPin::new(pin, Polarity::ActiveLow)
+    }
+
+    fn create_test_dial(config: DialConfig) -> (SimEnvironment, Dial, Receiver<Event>) {
+        let env = SimEnvironment::with_virtual_time();
 
         // The NSA witch is initially open, the NSI switch is closed.
         let nsa = env.create_input_pin(NSA, false);
@@ -110,11 +372,48 @@ mod tests {
         env.write_input(NSI, false);
 
         let (send, recv) = channel();
-        let dial = Dial::new::<SimInputPin>(nsa, nsi, send);
+        let dial = Dial::new(active_low(nsa), active_low(nsi), config, env.clock(), send);
+
+        // Make sure the thread is ready.
+        env.wait_for_listeners(1);
 
         (env, dial, recv)
     }
 
+    /// Dials a digit with `count` pulses of the specified timing.
+    fn dial_digit(env: &SimEnvironment, count: u32, pulse_ms: u64, pause_ms: u64) {
+        env.write_input(NSA, false);
+        env.advance(Duration::from_millis(50));
+        send_impulses(
+            env,
+            count,
+            Duration::from_millis(pulse_ms),
+            Duration::from_millis(pause_ms),
+        );
+        env.write_input(NSA, true);
+        env.advance(Duration::from_millis(50));
+    }
+
+    /// Feeds a digit with `count` pulses of the specified timing into the
+    /// decoder.
+    fn decode_pulses(
+        decoder: &mut PulseDecoder,
+        count: u32,
+        pulse_ms: u64,
+        pause_ms: u64,
+    ) -> Option<Result<u32, DialRejection>> {
+        let mut now = Instant::now();
+        assert_eq!(decoder.update(true, true, now), None);
+        now += Duration::from_millis(50);
+        for _ in 0..count {
+            assert_eq!(decoder.update(true, false, now), None);
+            now += Duration::from_millis(pulse_ms);
+            assert_eq!(decoder.update(true, true, now), None);
+            now += Duration::from_millis(pause_ms);
+        }
+        decoder.update(false, true, now)
+    }
+
     fn send_impulses(
         env: &SimEnvironment,
         count: u32,
@@ -123,18 +422,15 @@ mod tests {
     ) {
         for _ in 0..count {
             env.write_input(NSI, true);
-            sleep(impulse_width);
+            env.advance(impulse_width);
             env.write_input(NSI, false);
-            sleep(pause_width);
+            env.advance(pause_width);
         }
     }
 
     #[test]
     fn test_dial() {
-        let (env, dial, recv) = create_test_dial();
-
-        // Make sure the thread is ready.
-        sleep(Duration::from_millis(50));
+        let (env, _dial, recv) = create_test_dial(DialConfig::default());
 
         // Add some bogus impulses which shall be ignored.
         send_impulses(
@@ -147,17 +443,7 @@ mod tests {
 
         // Dial some numbers.
         for i in 0..11 {
-            // Begin dialing.
-            env.write_input(NSA, false);
-            sleep(Duration::from_millis(50));
-            send_impulses(
-                &env,
-                i,
-                Duration::from_millis(60),
-                Duration::from_millis(40),
-            );
-            env.write_input(NSA, true);
-            sleep(Duration::from_millis(50));
+            dial_digit(&env, i, 60, 40);
             let result = recv.try_recv();
             if i == 0 || i > 10 {
                 // 0 impulses or more than 10 impulses are invalid
@@ -169,7 +455,258 @@ mod tests {
                 assert_eq!(result, Ok(Event::Dialed(i)));
             }
         }
+    }
+
+    #[test]
+    fn test_decoder() {
+        let mut decoder = PulseDecoder::new(DialConfig::default(), false);
+        for i in 1..10 {
+            assert_eq!(decode_pulses(&mut decoder, i, 60, 40), Some(Ok(i)));
+        }
+        assert_eq!(decode_pulses(&mut decoder, 10, 60, 40), Some(Ok(0)));
+        assert_eq!(decode_pulses(&mut decoder, 0, 60, 40), None);
+        assert_eq!(
+            decode_pulses(&mut decoder, 11, 60, 40),
+            Some(Err(DialRejection::TooManyPulses(11)))
+        );
+
+        // Pulses are only counted while nsa is closed.
+        let now = Instant::now();
+        assert_eq!(decoder.update(false, false, now), None);
+        assert_eq!(decoder.update(false, true, now), None);
+        assert_eq!(decode_pulses(&mut decoder, 2, 60, 40), Some(Ok(2)));
+    }
+
+    #[test]
+    fn test_decoder_timing() {
+        let mut decoder = PulseDecoder::new(DialConfig::default(), false);
+
+        // Slow and fast dials within the tolerance.
+        assert_eq!(decode_pulses(&mut decoder, 5, 75, 50), Some(Ok(5)));
+        assert_eq!(decode_pulses(&mut decoder, 5, 50, 30), Some(Ok(5)));
+
+        assert_eq!(
+            decode_pulses(&mut decoder, 5, 30, 40),
+            Some(Err(DialRejection::PulseTooShort(Duration::from_millis(30))))
+        );
+        assert_eq!(
+            decode_pulses(&mut decoder, 5, 120, 40),
+            Some(Err(DialRejection::PulseTooLong(Duration::from_millis(120))))
+        );
+        assert_eq!(
+            decode_pulses(&mut decoder, 5, 60, 10),
+            Some(Err(DialRejection::PauseTooShort(Duration::from_millis(10))))
+        );
+        assert_eq!(
+            decode_pulses(&mut decoder, 5, 60, 90),
+            Some(Err(DialRejection::PauseTooLong(Duration::from_millis(90))))
+        );
+
+        // The time after the last pulse is not a pause between pulses.
+        assert_eq!(decode_pulses(&mut decoder, 1, 60, 500), Some(Ok(1)));
+
+        // The limits are configurable.
+        let mut decoder = PulseDecoder::new(
+            DialConfig {
+                pulse_min_ms: 20,
+                pulse_max_ms: 40,
+                pause_min_ms: 10,
+                pause_max_ms: 30,
+                debounce_ms: 0,
+                ..DialConfig::default()
+            },
+            false,
+        );
+        assert_eq!(decode_pulses(&mut decoder, 3, 30, 20), Some(Ok(3)));
+        assert_eq!(
+            decode_pulses(&mut decoder, 3, 60, 40),
+            Some(Err(DialRejection::PulseTooLong(Duration::from_millis(60))))
+        );
+    }
+
+    #[test]
+    fn test_decoder_timings() {
+        let mut decoder = PulseDecoder::new(DialConfig::default(), false);
+        assert_eq!(decode_pulses(&mut decoder, 3, 60, 40), Some(Ok(3)));
+        let pulse = Duration::from_millis(60);
+        let pause = Duration::from_millis(40);
+        assert_eq!(
+            decoder.timings(),
+            &[
+                PulseTiming {
+                    pulse,
+                    pause: Some(pause)
+                },
+                PulseTiming {
+                    pulse,
+                    pause: Some(pause)
+                },
+                PulseTiming { pulse, pause: None },
+            ]
+        );
+        assert_eq!(decode_pulses(&mut decoder, 1, 70, 40), Some(Ok(1)));
+        assert_eq!(
+            decoder.timings(),
+            &[PulseTiming {
+                pulse: Duration::from_millis(70),
+                pause: None
+            }]
+        );
+    }
+
+    #[test]
+    fn test_calibration() {
+        let config = DialConfig {
+            calibration: true,
+            ..DialConfig::default()
+        };
+        let (env, _dial, recv) = create_test_dial(config);
+
+        dial_digit(&env, 4, 60, 40);
+        match recv.try_recv() {
+            Ok(Event::DialCalibrated(report)) => {
+                assert_eq!(report.result, Ok(4));
+                assert_eq!(report.pulses.len(), 4);
+                let speed = report.speed.unwrap();
+                assert!((speed - 10.0).abs() < 1e-6, "speed: {}", speed);
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
 
-        // TODO: Test different timing?
+    #[test]
+    fn test_decoder_nsi_only() {
+        let mut decoder = PulseDecoder::new(DialConfig::default(), true);
+        let mut now = Instant::now();
+        assert_eq!(decoder.deadline(), None);
+        for _ in 0..3 {
+            assert_eq!(decoder.update(true, false, now), None);
+            assert_eq!(decoder.deadline(), None);
+            now += Duration::from_millis(60);
+            assert_eq!(decoder.update(true, true, now), None);
+            assert_eq!(decoder.deadline(), Some(now + Duration::from_millis(200)));
+            now += Duration::from_millis(40);
+        }
+        let deadline = decoder.deadline().unwrap();
+        assert_eq!(decoder.expire(deadline), Some(Ok(3)));
+        assert_eq!(decoder.deadline(), None);
+
+        // The next pulse starts a new digit.
+        now = deadline + Duration::from_millis(500);
+        assert_eq!(decoder.update(true, false, now), None);
+        now += Duration::from_millis(60);
+        assert_eq!(decoder.update(true, true, now), None);
+        assert_eq!(
+            decoder.expire(now + Duration::from_millis(200)),
+            Some(Ok(1))
+        );
+    }
+
+    #[test]
+    fn test_nsi_only() {
+        let env = SimEnvironment::with_virtual_time();
+        let nsi = env.create_input_pin(NSI, false);
+        env.write_input(NSI, false);
+        let (send, recv) = channel();
+        let config = DialConfig {
+            mode: DialMode::NsiOnly,
+            digit_gap_ms: 150,
+            ..DialConfig::default()
+        };
+        let _dial = Dial::new_nsi_only(active_low(nsi), config, env.clock(), send);
+        env.wait_for_listeners(1);
+
+        for &digit in [3, 10, 1].iter() {
+            send_impulses(
+                &env,
+                digit,
+                Duration::from_millis(60),
+                Duration::from_millis(40),
+            );
+            // The digit is complete exactly when the gap has expired.
+            env.advance(Duration::from_millis(109));
+            assert!(recv.try_recv().is_err());
+            env.advance(Duration::from_millis(1));
+            assert_eq!(recv.try_recv(), Ok(Event::Dialed(digit % 10)));
+            env.advance(Duration::from_millis(500));
+        }
+    }
+
+    #[test]
+    fn test_bounce() {
+        let (env, _dial, recv) = create_test_dial(DialConfig::default());
+
+        env.write_input(NSA, false);
+        env.advance(Duration::from_millis(50));
+        for _ in 0..3 {
+            // Each edge of the nsi contact bounces a few times.
+            for _ in 0..3 {
+                env.write_input(NSI, true);
+                env.write_input(NSI, false);
+            }
+            env.write_input(NSI, true);
+            env.advance(Duration::from_millis(60));
+            for _ in 0..3 {
+                env.write_input(NSI, false);
+                env.advance(Duration::from_millis(1));
+                env.write_input(NSI, true);
+            }
+            env.write_input(NSI, false);
+            env.advance(Duration::from_millis(40));
+        }
+        env.write_input(NSA, true);
+        env.advance(Duration::from_millis(50));
+        assert_eq!(recv.try_recv(), Ok(Event::Dialed(3)));
+
+        // Digits with invalid timing are reported.
+        dial_digit(&env, 2, 200, 40);
+        assert_eq!(
+            recv.try_recv(),
+            Ok(Event::DialRejected(DialRejection::PulseTooLong(
+                Duration::from_millis(200)
+            )))
+        );
+    }
+
+    #[test]
+    fn test_timing_limits() {
+        let (env, _dial, recv) = create_test_dial(DialConfig::default());
+
+        // Pulses and pauses exactly at the limits are accepted.
+        dial_digit(&env, 3, 100, 75);
+        assert_eq!(recv.try_recv(), Ok(Event::Dialed(3)));
+        dial_digit(&env, 3, 40, 20);
+        assert_eq!(recv.try_recv(), Ok(Event::Dialed(3)));
+
+        // A single millisecond more or less is rejected.
+        dial_digit(&env, 3, 101, 40);
+        assert_eq!(
+            recv.try_recv(),
+            Ok(Event::DialRejected(DialRejection::PulseTooLong(
+                Duration::from_millis(101)
+            )))
+        );
+        dial_digit(&env, 3, 39, 40);
+        assert_eq!(
+            recv.try_recv(),
+            Ok(Event::DialRejected(DialRejection::PulseTooShort(
+                Duration::from_millis(39)
+            )))
+        );
+        dial_digit(&env, 3, 60, 76);
+        assert_eq!(
+            recv.try_recv(),
+            Ok(Event::DialRejected(DialRejection::PauseTooLong(
+                Duration::from_millis(76)
+            )))
+        );
+        dial_digit(&env, 3, 60, 19);
+        assert_eq!(
+            recv.try_recv(),
+            Ok(Event::DialRejected(DialRejection::PauseTooShort(
+                Duration::from_millis(19)
+            )))
+        );
+        assert!(recv.try_recv().is_err());
     }
 }
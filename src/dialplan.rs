@@ -0,0 +1,350 @@
+//! Dial plan which decides when a dialed number is complete.
+//!
+//! Rotary dials have no "send" key, so the number is considered complete
+//! either when it matches a rule (e.g., emergency numbers or numbers with a
+//! known length) or when no further digit has been dialed for some time.
+//!
+//! Patterns used in the rules consist of digits, which match themselves, and
+//! the placeholders `X` (any digit), `Z` (1-9), and `N` (2-9).
+
+use std::time::{Duration, Instant};
+
+/// Rule for numbers which are complete once they reach a fixed length.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct FixedLengthRule {
+    /// Pattern matching the beginning of the number.
+    pub prefix: String,
+    /// Number of digits of the complete number, including the prefix.
+    pub length: usize,
+}
+
+/// Rule which modifies the beginning of complete numbers.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct RewriteRule {
+    /// Pattern matching the beginning of the number.
+    pub prefix: String,
+    /// Number of digits removed from the beginning of the number.
+    #[serde(default)]
+    pub strip: usize,
+    /// Digits added to the beginning of the number after stripping.
+    #[serde(default)]
+    pub prepend: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(default)]
+pub struct DialPlanConfig {
+    /// Time in milliseconds after the last digit after which the number is
+    /// considered complete.
+    pub timeout_ms: u64,
+    /// Numbers which are dialed immediately and never rewritten.
+    pub emergency_numbers: Vec<String>,
+    /// Numbers which are dialed as soon as they have the specified length. The
+    /// first matching rule is used.
+    pub fixed_length: Vec<FixedLengthRule>,
+    /// Rules applied to complete numbers. The first matching rule is used.
+    pub rewrites: Vec<RewriteRule>,
+}
+
+impl Default for DialPlanConfig {
+    fn default() -> Self {
+        Self {
+            timeout_ms: 3000,
+            emergency_numbers: vec!["110".to_string(), "112".to_string()],
+            fixed_length: Vec::new(),
+            rewrites: Vec::new(),
+        }
+    }
+}
+
+/// State of the number which is currently being dialed.
+pub struct DialPlan {
+    config: DialPlanConfig,
+    digits: String,
+    deadline: Option<Instant>,
+}
+
+impl DialPlan {
+    pub fn new(config: DialPlanConfig) -> DialPlan {
+        DialPlan {
+            config,
+            digits: String::new(),
+            deadline: None,
+        }
+    }
+
+    /// Discards all digits dialed so far.
+    pub fn reset(&mut self) {
+        self.digits.clear();
+        self.deadline = None;
+    }
+
+    /// Returns the digits dialed so far.
+    pub fn digits(&self) -> &str {
+        &self.digits
+    }
+
+    /// Adds a digit to the number.
+    ///
+    /// If the number is complete, the function returns the (rewritten) number
+    /// and resets the dial plan for the next number.
+    pub fn push_digit(&mut self, digit: char, now: Instant) -> Option<String> {
+        self.digits.push(digit);
+        self.deadline = Some(now + Duration::from_millis(self.config.timeout_ms));
+
+        if self.config.emergency_numbers.contains(&self.digits) {
+            let number = self.digits.clone();
+            self.reset();
+            return Some(number);
+        }
+        let complete = self
+            .config
+            .fixed_length
+            .iter()
+            .find(|rule| matches_prefix(&rule.prefix, &self.digits))
+            .map(|rule| self.digits.len() >= rule.length)
+            .unwrap_or(false);
+        if complete {
+            return self.take_number();
+        }
+        None
+    }
+
+    /// Considers the number complete without waiting for the timeout, e.g.,
+    /// when `#` has been pressed on a keypad.
+    ///
+    /// Returns the (rewritten) number unless no digit has been dialed.
+    pub fn complete(&mut self) -> Option<String> {
+        if self.digits.is_empty() {
+            return None;
+        }
+        self.take_number()
+    }
+
+    /// Returns the time at which the number will be considered complete if no
+    /// further digit is dialed.
+    pub fn deadline(&self) -> Option<Instant> {
+        self.deadline
+    }
+
+    /// Checks whether the inter-digit timeout has expired.
+    ///
+    /// If so, the function returns the (rewritten) number and resets the dial
+    /// plan for the next number.
+    pub fn poll(&mut self, now: Instant) -> Option<String> {
+        match self.deadline {
+            Some(deadline) if now >= deadline => self.take_number(),
+            _ => None,
+        }
+    }
+
+    fn take_number(&mut self) -> Option<String> {
+        let number = self.rewrite(&self.digits);
+        self.reset();
+        Some(number)
+    }
+
+    fn rewrite(&self, number: &str) -> String {
+        match self
+            .config
+            .rewrites
+            .iter()
+            .find(|rule| matches_prefix(&rule.prefix, number))
+        {
+            Some(rule) => {
+                let strip = rule.strip.min(number.len());
+                format!("{}{}", rule.prepend, &number[strip..])
+            }
+            None => number.to_string(),
+        }
+    }
+}
+
+/// Checks whether the number starts with the pattern.
+fn matches_prefix(pattern: &str, number: &str) -> bool {
+    if number.len() < pattern.len() {
+        return false;
+    }
+    pattern.chars().zip(number.chars()).all(|(p, n)| match p {
+        'X' => n.is_ascii_digit(),
+        'Z' => ('1'..='9').contains(&n),
+        'N' => ('2'..='9').contains(&n),
+        p => p == n,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Dials the digits with one second between digits and returns the
+    /// completed numbers and the time of the last digit.
+    fn dial(plan: &mut DialPlan, digits: &str, start: Instant) -> (Vec<String>, Instant) {
+        let mut now = start;
+        let mut numbers = Vec::new();
+        for digit in digits.chars() {
+            now += Duration::from_secs(1);
+            if let Some(number) = plan.push_digit(digit, now) {
+                numbers.push(number);
+            }
+        }
+        (numbers, now)
+    }
+
+    #[test]
+    fn test_timeout() {
+        let mut plan = DialPlan::new(DialPlanConfig::default());
+        let start = Instant::now();
+        assert_eq!(plan.deadline(), None);
+        assert_eq!(plan.poll(start), None);
+
+        let (numbers, last) = dial(&mut plan, "0301234", start);
+        assert!(numbers.is_empty());
+        assert_eq!(plan.digits(), "0301234");
+        assert_eq!(plan.deadline(), Some(last + Duration::from_secs(3)));
+
+        assert_eq!(plan.poll(last + Duration::from_millis(2999)), None);
+        assert_eq!(
+            plan.poll(last + Duration::from_secs(3)),
+            Some("0301234".to_string())
+        );
+        assert_eq!(plan.digits(), "");
+        assert_eq!(plan.deadline(), None);
+        assert_eq!(plan.poll(last + Duration::from_secs(10)), None);
+    }
+
+    #[test]
+    fn test_emergency_numbers() {
+        let mut plan = DialPlan::new(DialPlanConfig {
+            rewrites: vec![RewriteRule {
+                prefix: "Z".to_string(),
+                strip: 0,
+                prepend: "030".to_string(),
+            }],
+            ..DialPlanConfig::default()
+        });
+        let start = Instant::now();
+
+        // Emergency numbers are dialed immediately and not rewritten.
+        let (numbers, _) = dial(&mut plan, "112", start);
+        assert_eq!(numbers, vec!["112".to_string()]);
+        assert_eq!(plan.deadline(), None);
+        let (numbers, _) = dial(&mut plan, "110", start);
+        assert_eq!(numbers, vec!["110".to_string()]);
+
+        // Other numbers starting with the same digits are not affected.
+        let (numbers, last) = dial(&mut plan, "11", start);
+        assert!(numbers.is_empty());
+        assert_eq!(
+            plan.poll(last + Duration::from_secs(3)),
+            Some("03011".to_string())
+        );
+    }
+
+    #[test]
+    fn test_fixed_length() {
+        let mut plan = DialPlan::new(DialPlanConfig {
+            fixed_length: vec![
+                FixedLengthRule {
+                    prefix: "0800".to_string(),
+                    length: 11,
+                },
+                FixedLengthRule {
+                    prefix: "0".to_string(),
+                    length: 12,
+                },
+                FixedLengthRule {
+                    prefix: "Z".to_string(),
+                    length: 7,
+                },
+            ],
+            ..DialPlanConfig::default()
+        });
+        let start = Instant::now();
+
+        let (numbers, _) = dial(&mut plan, "08001234567", start);
+        assert_eq!(numbers, vec!["08001234567".to_string()]);
+        let (numbers, _) = dial(&mut plan, "030123456789", start);
+        assert_eq!(numbers, vec!["030123456789".to_string()]);
+        let (numbers, _) = dial(&mut plan, "1234567", start);
+        assert_eq!(numbers, vec!["1234567".to_string()]);
+
+        // Shorter numbers still complete after the timeout.
+        let (numbers, last) = dial(&mut plan, "030123", start);
+        assert!(numbers.is_empty());
+        assert_eq!(
+            plan.poll(last + Duration::from_secs(3)),
+            Some("030123".to_string())
+        );
+    }
+
+    #[test]
+    fn test_rewrites() {
+        let mut plan = DialPlan::new(DialPlanConfig {
+            timeout_ms: 5000,
+            rewrites: vec![
+                // International numbers for Germany are dialed nationally.
+                RewriteRule {
+                    prefix: "0049".to_string(),
+                    strip: 4,
+                    prepend: "0".to_string(),
+                },
+                // Local numbers get the area code.
+                RewriteRule {
+                    prefix: "Z".to_string(),
+                    strip: 0,
+                    prepend: "030".to_string(),
+                },
+            ],
+            ..DialPlanConfig::default()
+        });
+        let start = Instant::now();
+
+        let (_, last) = dial(&mut plan, "1234567", start);
+        assert_eq!(
+            plan.poll(last + Duration::from_secs(5)),
+            Some("0301234567".to_string())
+        );
+        let (_, last) = dial(&mut plan, "0049891234", start);
+        assert_eq!(
+            plan.poll(last + Duration::from_secs(5)),
+            Some("0891234".to_string())
+        );
+        let (_, last) = dial(&mut plan, "0891234", start);
+        assert_eq!(
+            plan.poll(last + Duration::from_secs(5)),
+            Some("0891234".to_string())
+        );
+
+        // Numbers completed early are rewritten as well.
+        assert_eq!(plan.complete(), None);
+        dial(&mut plan, "1234", start);
+        assert_eq!(plan.complete(), Some("0301234".to_string()));
+        assert_eq!(plan.deadline(), None);
+        dial(&mut plan, "*31", start);
+        assert_eq!(plan.complete(), Some("*31".to_string()));
+    }
+
+    #[test]
+    fn test_matches_prefix() {
+        assert!(matches_prefix("", ""));
+        assert!(matches_prefix("0", "030"));
+        assert!(!matches_prefix("0", "130"));
+        assert!(matches_prefix("0X0", "030"));
+        assert!(matches_prefix("Z", "1"));
+        assert!(!matches_prefix("Z", "0"));
+        assert!(matches_prefix("N", "2"));
+        assert!(!matches_prefix("N", "1"));
+        assert!(!matches_prefix("030", "03"));
+    }
+
+    #[test]
+    fn test_reset() {
+        let mut plan = DialPlan::new(DialPlanConfig::default());
+        let start = Instant::now();
+        let (_, last) = dial(&mut plan, "030", start);
+        plan.reset();
+        assert_eq!(plan.digits(), "");
+        assert_eq!(plan.poll(last + Duration::from_secs(3)), None);
+    }
+}
@@ -1,5 +1,8 @@
 //! Type which generates events when the earpiece is picked up or dropped.
 
+use super::clock::Clock;
+use super::debounce::Debouncer;
+use super::dial::DialRejection;
 use super::gpio::InputPin;
 use super::Event;
 
@@ -7,43 +10,235 @@ use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::mpsc::Sender;
 use std::sync::Arc;
 use std::thread::{self, JoinHandle};
-use std::time::Duration;
+use std::time::{Duration, Instant};
+
+/// Thresholds used to classify short on-hook periods.
+///
+/// An on-hook period of up to `pulse_max_ms` is a dial pulse (the user dials by
+/// tapping the hook switch), one of up to `flash_max_ms` is a hook flash
+/// ("R key"), and everything longer means that the earpiece has been put down.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(default)]
+pub struct EarpieceConfig {
+    /// Maximum on-hook time of a dial pulse in milliseconds.
+    pub pulse_max_ms: u64,
+    /// Maximum on-hook time of a hook flash in milliseconds.
+    pub flash_max_ms: u64,
+    /// Off-hook time after the last dial pulse in milliseconds after which the
+    /// digit is complete.
+    pub digit_gap_ms: u64,
+    /// Time in milliseconds for which the hook switch has to be stable before
+    /// a change is accepted.
+    pub debounce_ms: u64,
+}
+
+impl Default for EarpieceConfig {
+    fn default() -> Self {
+        Self {
+            pulse_max_ms: 100,
+            flash_max_ms: 800,
+            digit_gap_ms: 300,
+            debounce_ms: 10,
+        }
+    }
+}
+
+/// Result of the classification of the hook switch state.
+#[derive(Debug, Clone, PartialEq)]
+enum HookEvent {
+    PickedUp,
+    PutDown,
+    Flash,
+    /// A digit was dialed via hook pulses.
+    Digit(Result<u32, DialRejection>),
+}
+
+/// Classifier which tells apart hang-ups, hook flashes, and dial pulses.
+///
+/// As the meaning of an on-hook period is only known once it has ended or has
+/// become too long for a flash, putting down the earpiece is reported with a
+/// delay of `flash_max_ms`.
+struct HookClassifier {
+    config: EarpieceConfig,
+    /// Whether the earpiece has been reported as picked up.
+    picked_up: bool,
+    /// Start of the current on-hook period while the earpiece is picked up.
+    on_hook_since: Option<Instant>,
+    /// Number of dial pulses of the current digit.
+    pulses: u32,
+    /// End of the last dial pulse.
+    pulse_end: Option<Instant>,
+}
+
+impl HookClassifier {
+    fn new(config: EarpieceConfig) -> Self {
+        Self {
+            config,
+            picked_up: false,
+            on_hook_since: None,
+            pulses: 0,
+            pulse_end: None,
+        }
+    }
+
+    /// Processes the state of the hook switch at the specified time.
+    fn update(&mut self, off_hook: bool, now: Instant) -> Vec<HookEvent> {
+        let mut events = Vec::new();
+        if !self.picked_up {
+            if off_hook {
+                self.picked_up = true;
+                events.push(HookEvent::PickedUp);
+            }
+            return events;
+        }
+        if !off_hook {
+            if self.on_hook_since.is_none() {
+                self.on_hook_since = Some(now);
+            }
+            return events;
+        }
+        let on_hook_since = match self.on_hook_since.take() {
+            Some(on_hook_since) => on_hook_since,
+            None => return events,
+        };
+        let duration = now - on_hook_since;
+        if duration <= Duration::from_millis(self.config.pulse_max_ms) {
+            self.pulses += 1;
+            self.pulse_end = Some(now);
+        } else if duration <= Duration::from_millis(self.config.flash_max_ms) {
+            events.extend(self.finish_digit());
+            events.push(HookEvent::Flash);
+        } else {
+            // The deadline was missed, the earpiece has been put down and
+            // picked up again.
+            self.finish_digit();
+            events.push(HookEvent::PutDown);
+            events.push(HookEvent::PickedUp);
+        }
+        events
+    }
+
+    /// Returns the time at which `expire()` has to be called if the hook
+    /// switch does not change.
+    fn deadline(&self) -> Option<Instant> {
+        if let Some(on_hook_since) = self.on_hook_since {
+            Some(on_hook_since + Duration::from_millis(self.config.flash_max_ms))
+        } else if self.pulses != 0 {
+            self.pulse_end
+                .map(|end| end + Duration::from_millis(self.config.digit_gap_ms))
+        } else {
+            None
+        }
+    }
+
+    /// Handles the expiry of the deadline returned by `deadline()`.
+    fn expire(&mut self, now: Instant) -> Vec<HookEvent> {
+        let mut events = Vec::new();
+        match self.deadline() {
+            Some(deadline) if now >= deadline => {}
+            _ => return events,
+        }
+        if self.on_hook_since.is_some() {
+            // Pulses are discarded if the user hangs up while dialing.
+            self.finish_digit();
+            self.on_hook_since = None;
+            self.picked_up = false;
+            events.push(HookEvent::PutDown);
+        } else {
+            events.extend(self.finish_digit());
+        }
+        events
+    }
+
+    /// Completes the digit dialed via hook pulses, if any.
+    fn finish_digit(&mut self) -> Option<HookEvent> {
+        let pulses = self.pulses;
+        self.pulses = 0;
+        self.pulse_end = None;
+        match pulses {
+            0 => None,
+            pulses if pulses > 10 => {
+                Some(HookEvent::Digit(Err(DialRejection::TooManyPulses(pulses))))
+            }
+            // 10 impulses = '0'
+            pulses => Some(HookEvent::Digit(Ok(pulses % 10))),
+        }
+    }
+}
 
 /// Interface to the earpiece hook switch.
 ///
-/// The implementation assumes that the switch is active-low. If the GPIO value
-/// is `false`, the implementation assumes that the earpiece is on the hook,
-/// whereas `true` signals that the earpiece has been picked up.
+/// If the pin value is `false`, the implementation assumes that the earpiece is
+/// on the hook, whereas `true` signals that the earpiece has been picked up.
+/// Switches with the opposite wiring have to be wrapped in a
+/// `PolarityInputPin`.
+///
+/// Short on-hook periods are not reported as `EarpiecePutDown`, but are
+/// classified as hook flashes or dial pulses (see `EarpieceConfig`), using
+/// the time of the specified clock.
 pub struct Earpiece {
     thread: Option<JoinHandle<()>>,
     stop_thread: Arc<AtomicBool>,
 }
 
 impl Earpiece {
-    pub fn new<Pin: InputPin + Send + 'static>(hook: Pin, sender: Sender<Event>) -> Self {
+    pub fn new<Pin: InputPin + Send + 'static, C: Clock>(
+        mut hook: Pin,
+        config: EarpieceConfig,
+        clock: C,
+        sender: Sender<Event>,
+    ) -> Self {
         let stop_thread = Arc::new(AtomicBool::new(false));
         let stop_copy = stop_thread.clone();
+        hook.set_stop_flag(stop_thread.clone());
         let thread = thread::spawn(move || {
-            let mut picked_up = false;
+            let debounce = Duration::from_millis(config.debounce_ms);
+            let mut classifier = HookClassifier::new(config);
+            let mut debouncer = Debouncer::new(debounce, false);
             loop {
                 // Wait with timeout to allow Drop to terminate the thread in a
                 // timely fashion.
-                let wait_result = hook.wait_timeout(Duration::from_millis(1000));
+                let timeout = match debouncer
+                    .deadline()
+                    .into_iter()
+                    .chain(classifier.deadline())
+                    .min()
+                {
+                    Some(deadline) => deadline.saturating_duration_since(clock.now()),
+                    None => Duration::from_millis(1000),
+                };
+                let wait_result = hook.wait_timeout(timeout.min(Duration::from_millis(1000)));
                 if stop_thread.load(Ordering::SeqCst) {
                     return;
                 }
+                let now = clock.now();
                 if wait_result {
-                    let pin_state = hook.read();
-                    if pin_state != picked_up {
-                        let result = sender.send(if pin_state {
-                            Event::EarpiecePickedUp
-                        } else {
-                            Event::EarpiecePutDown
-                        });
-                        if result.is_err() {
-                            break;
-                        }
-                        picked_up = pin_state;
+                    debouncer.update(hook.read(), now);
+                }
+                let mut events = Vec::new();
+                if let Some((off_hook, since)) = debouncer.poll(now) {
+                    events.extend(classifier.expire(since));
+                    events.extend(classifier.update(off_hook, since));
+                }
+                // A pending change which happened before the deadline has to
+                // be processed first.
+                let change_pending = match (debouncer.pending_since(), classifier.deadline()) {
+                    (Some(since), Some(deadline)) => since < deadline,
+                    _ => false,
+                };
+                if !change_pending {
+                    events.extend(classifier.expire(now));
+                }
+                for event in events {
+                    let event = match event {
+                        HookEvent::PickedUp => Event::EarpiecePickedUp,
+                        HookEvent::PutDown => Event::EarpiecePutDown,
+                        HookEvent::Flash => Event::HookFlash,
+                        HookEvent::Digit(Ok(digit)) => Event::Dialed(digit),
+                        HookEvent::Digit(Err(rejection)) => Event::DialRejected(rejection),
+                    };
+                    if sender.send(event).is_err() {
+                        return;
                     }
                 }
             }
@@ -66,32 +261,198 @@ impl Drop for Earpiece {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::gpio::sim::{SimEnvironment, SimInputPin};
+    use crate::gpio::sim::SimEnvironment;
 
     use std::sync::mpsc::{channel, Receiver};
-    use std::thread::sleep;
 
-    #[test]
-    fn test_earpiece() {
-        const HOOK_PIN: usize = 0;
+    const HOOK_PIN: usize = 0;
 
-        let env = SimEnvironment::new();
+    fn create_test_earpiece(config: EarpieceConfig) -> (SimEnvironment, Earpiece, Receiver<Event>) {
+        let env = SimEnvironment::with_virtual_time();
         let hook = env.create_input_pin(HOOK_PIN, false);
         env.write_input(HOOK_PIN, false);
 
         let (send, recv) = channel();
-        let earpiece = Earpiece::new::<SimInputPin>(hook, send);
+        let earpiece = Earpiece::new(hook, config, env.clock(), send);
 
         // Make sure the thread is ready.
-        sleep(Duration::from_millis(10));
+        env.wait_for_listeners(1);
         assert!(recv.try_recv().is_err());
 
+        (env, earpiece, recv)
+    }
+
+    /// Lets the classifier see an on-hook period of the specified length,
+    /// followed by the specified off-hook time.
+    fn tap(
+        classifier: &mut HookClassifier,
+        now: &mut Instant,
+        on_hook_ms: u64,
+        off_hook_ms: u64,
+    ) -> Vec<HookEvent> {
+        let mut events = classifier.update(false, *now);
+        *now += Duration::from_millis(on_hook_ms);
+        events.extend(classifier.expire(*now - Duration::from_millis(1)));
+        events.extend(classifier.update(true, *now));
+        *now += Duration::from_millis(off_hook_ms);
+        events
+    }
+
+    #[test]
+    fn test_earpiece() {
+        let config = EarpieceConfig {
+            pulse_max_ms: 20,
+            flash_max_ms: 50,
+            digit_gap_ms: 100,
+            debounce_ms: 0,
+        };
+        let (env, _earpiece, recv) = create_test_earpiece(config);
+
         env.write_input(HOOK_PIN, true);
-        sleep(Duration::from_millis(10));
+        env.advance(Duration::from_millis(10));
         assert_eq!(recv.try_recv(), Ok(Event::EarpiecePickedUp));
 
+        // Putting down the earpiece is only reported after the flash time.
         env.write_input(HOOK_PIN, false);
-        sleep(Duration::from_millis(10));
+        env.advance(Duration::from_millis(49));
+        assert!(recv.try_recv().is_err());
+        env.advance(Duration::from_millis(1));
         assert_eq!(recv.try_recv(), Ok(Event::EarpiecePutDown));
     }
+
+    #[test]
+    fn test_hook_pulses() {
+        let config = EarpieceConfig {
+            pulse_max_ms: 40,
+            flash_max_ms: 200,
+            digit_gap_ms: 150,
+            debounce_ms: 5,
+        };
+        let (env, _earpiece, recv) = create_test_earpiece(config);
+        env.write_input(HOOK_PIN, true);
+        env.advance(Duration::from_millis(10));
+        assert_eq!(recv.try_recv(), Ok(Event::EarpiecePickedUp));
+
+        // On-hook periods up to exactly `pulse_max_ms` are pulses.
+        for _ in 0..4 {
+            env.write_input(HOOK_PIN, false);
+            env.advance(Duration::from_millis(40));
+            env.write_input(HOOK_PIN, true);
+            env.advance(Duration::from_millis(20));
+        }
+        assert!(recv.try_recv().is_err());
+        env.advance(Duration::from_millis(129));
+        assert!(recv.try_recv().is_err());
+        env.advance(Duration::from_millis(1));
+        assert_eq!(recv.try_recv(), Ok(Event::Dialed(4)));
+        assert!(recv.try_recv().is_err());
+
+        // A single millisecond more is a hook flash.
+        env.write_input(HOOK_PIN, false);
+        env.advance(Duration::from_millis(41));
+        env.write_input(HOOK_PIN, true);
+        env.advance(Duration::from_millis(10));
+        assert_eq!(recv.try_recv(), Ok(Event::HookFlash));
+        assert!(recv.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_hook_flash() {
+        let (env, _earpiece, recv) = create_test_earpiece(EarpieceConfig::default());
+        env.write_input(HOOK_PIN, true);
+        env.advance(Duration::from_millis(20));
+        assert_eq!(recv.try_recv(), Ok(Event::EarpiecePickedUp));
+
+        // Short glitches of the switch are ignored.
+        env.write_input(HOOK_PIN, false);
+        env.advance(Duration::from_millis(2));
+        env.write_input(HOOK_PIN, true);
+        env.advance(Duration::from_millis(200));
+        assert!(recv.try_recv().is_err());
+
+        // The switch bounces when it is released.
+        env.write_input(HOOK_PIN, false);
+        env.advance(Duration::from_millis(300));
+        for _ in 0..3 {
+            env.write_input(HOOK_PIN, true);
+            env.write_input(HOOK_PIN, false);
+        }
+        env.write_input(HOOK_PIN, true);
+        env.advance(Duration::from_millis(30));
+        assert_eq!(recv.try_recv(), Ok(Event::HookFlash));
+        env.advance(Duration::from_millis(400));
+        assert!(recv.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_classifier() {
+        let mut classifier = HookClassifier::new(EarpieceConfig::default());
+        let mut now = Instant::now();
+
+        // On-hook periods before the earpiece has been picked up are ignored.
+        assert!(classifier.update(false, now).is_empty());
+        assert_eq!(classifier.deadline(), None);
+        assert_eq!(classifier.update(true, now), vec![HookEvent::PickedUp]);
+        assert!(classifier.update(true, now).is_empty());
+
+        // Hook flash.
+        assert_eq!(
+            tap(&mut classifier, &mut now, 300, 1000),
+            vec![HookEvent::Flash]
+        );
+        assert_eq!(classifier.deadline(), None);
+
+        // Hook pulses.
+        for _ in 0..3 {
+            assert!(tap(&mut classifier, &mut now, 60, 40).is_empty());
+        }
+        let deadline = classifier.deadline().unwrap();
+        assert!(classifier
+            .expire(deadline - Duration::from_millis(1))
+            .is_empty());
+        assert_eq!(classifier.expire(deadline), vec![HookEvent::Digit(Ok(3))]);
+        now = deadline;
+        for _ in 0..10 {
+            assert!(tap(&mut classifier, &mut now, 60, 40).is_empty());
+        }
+        assert_eq!(
+            classifier.expire(now + Duration::from_secs(1)),
+            vec![HookEvent::Digit(Ok(0))]
+        );
+        for _ in 0..11 {
+            assert!(tap(&mut classifier, &mut now, 60, 40).is_empty());
+        }
+        assert_eq!(
+            classifier.expire(now + Duration::from_secs(1)),
+            vec![HookEvent::Digit(Err(DialRejection::TooManyPulses(11)))]
+        );
+        now += Duration::from_secs(1);
+
+        // A flash directly after some pulses completes the digit.
+        assert!(tap(&mut classifier, &mut now, 60, 40).is_empty());
+        assert_eq!(
+            tap(&mut classifier, &mut now, 200, 40),
+            vec![HookEvent::Digit(Ok(1)), HookEvent::Flash]
+        );
+
+        // Hanging up.
+        assert!(classifier.update(false, now).is_empty());
+        let deadline = classifier.deadline().unwrap();
+        assert_eq!(deadline, now + Duration::from_millis(800));
+        assert!(classifier
+            .expire(deadline - Duration::from_millis(1))
+            .is_empty());
+        assert_eq!(classifier.expire(deadline), vec![HookEvent::PutDown]);
+        assert_eq!(classifier.deadline(), None);
+        assert!(classifier.update(false, now).is_empty());
+        assert_eq!(classifier.update(true, now), vec![HookEvent::PickedUp]);
+
+        // Pulses are discarded when hanging up.
+        assert!(tap(&mut classifier, &mut now, 60, 40).is_empty());
+        assert!(classifier.update(false, now).is_empty());
+        assert_eq!(
+            classifier.expire(now + Duration::from_secs(1)),
+            vec![HookEvent::PutDown]
+        );
+    }
 }
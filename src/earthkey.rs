@@ -0,0 +1,126 @@
+//! Type which generates events when the earth key ("Erdtaste") is pressed.
+
+use super::debounce::Debouncer;
+use super::gpio::InputPin;
+use super::Event;
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::Sender;
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// Configuration of the earth key.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(default)]
+pub struct EarthKeyConfig {
+    /// Time in milliseconds for which the key has to be stable before a change
+    /// is accepted.
+    pub debounce_ms: u64,
+}
+
+impl Default for EarthKeyConfig {
+    fn default() -> Self {
+        Self { debounce_ms: 20 }
+    }
+}
+
+/// Interface to the earth key found on many FeTAp 611 models.
+///
+/// The key has the same function as a hook flash: Each press generates a
+/// `FlashKey` event. If the pin value is `true`, the key is pressed.
+pub struct EarthKey {
+    thread: Option<JoinHandle<()>>,
+    stop_thread: Arc<AtomicBool>,
+}
+
+impl EarthKey {
+    pub fn new<Pin: InputPin + Send + 'static>(
+        key: Pin,
+        config: EarthKeyConfig,
+        sender: Sender<Event>,
+    ) -> Self {
+        let stop_thread = Arc::new(AtomicBool::new(false));
+        let stop_copy = stop_thread.clone();
+        let thread = thread::spawn(move || {
+            let mut debouncer = Debouncer::new(Duration::from_millis(config.debounce_ms), false);
+            loop {
+                // Wait with timeout to allow Drop to terminate the thread in a
+                // timely fashion.
+                let timeout = match debouncer.deadline() {
+                    Some(deadline) => deadline.saturating_duration_since(Instant::now()),
+                    None => Duration::from_millis(1000),
+                };
+                let wait_result = key.wait_timeout(timeout.min(Duration::from_millis(1000)));
+                if stop_thread.load(Ordering::SeqCst) {
+                    return;
+                }
+                let now = Instant::now();
+                if wait_result {
+                    debouncer.update(key.read(), now);
+                }
+                if let Some((true, _)) = debouncer.poll(now) {
+                    if sender.send(Event::FlashKey).is_err() {
+                        return;
+                    }
+                }
+            }
+        });
+        Self {
+            thread: Some(thread),
+            stop_thread: stop_copy,
+        }
+    }
+}
+
+impl Drop for EarthKey {
+    fn drop(&mut self) {
+        self.stop_thread.store(true, Ordering::SeqCst);
+        let thread = self.thread.take();
+        thread.unwrap().join().unwrap();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gpio::sim::{SimEnvironment, SimInputPin};
+
+    use std::sync::mpsc::channel;
+    use std::thread::sleep;
+
+    const KEY_PIN: usize = 0;
+
+    #[test]
+    fn test_earth_key() {
+        let env = SimEnvironment::new();
+        let key = env.create_input_pin(KEY_PIN, false);
+        env.write_input(KEY_PIN, false);
+        let (send, recv) = channel();
+        let _earth_key = EarthKey::new::<SimInputPin>(key, EarthKeyConfig::default(), send);
+        sleep(Duration::from_millis(10));
+
+        // Short glitches are ignored.
+        env.write_input(KEY_PIN, true);
+        sleep(Duration::from_millis(2));
+        env.write_input(KEY_PIN, false);
+        sleep(Duration::from_millis(50));
+        assert!(recv.try_recv().is_err());
+
+        // A bouncing key is reported once when it is pressed.
+        for _ in 0..3 {
+            env.write_input(KEY_PIN, true);
+            env.write_input(KEY_PIN, false);
+        }
+        env.write_input(KEY_PIN, true);
+        sleep(Duration::from_millis(50));
+        assert_eq!(recv.try_recv(), Ok(Event::FlashKey));
+        env.write_input(KEY_PIN, false);
+        sleep(Duration::from_millis(50));
+        assert!(recv.try_recv().is_err());
+
+        env.write_input(KEY_PIN, true);
+        sleep(Duration::from_millis(50));
+        assert_eq!(recv.try_recv(), Ok(Event::FlashKey));
+    }
+}
@@ -0,0 +1,465 @@
+//! Types for GPIO input/output using the Linux GPIO character device
+//! interface (`/dev/gpiochipN`, uAPI v2).
+//!
+//! In contrast to the deprecated sysfs interface, the kernel reports edge
+//! events with timestamps, and a group of pins is requested as a single
+//! multi-line request so that all pins can be read atomically.
+
+use super::{Bias, Edge};
+
+use std::fs::File;
+use std::io::{self, Read};
+use std::mem;
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Default GPIO chip used by the application.
+pub const DEFAULT_CHIP: &str = "/dev/gpiochip0";
+
+const CONSUMER: &[u8] = b"fernsprechapparat";
+
+const GPIO_V2_LINES_MAX: usize = 64;
+const GPIO_MAX_NAME_SIZE: usize = 32;
+const GPIO_V2_LINE_NUM_ATTRS_MAX: usize = 10;
+
+const GPIO_V2_LINE_FLAG_INPUT: u64 = 1 << 2;
+const GPIO_V2_LINE_FLAG_OUTPUT: u64 = 1 << 3;
+const GPIO_V2_LINE_FLAG_EDGE_RISING: u64 = 1 << 4;
+const GPIO_V2_LINE_FLAG_EDGE_FALLING: u64 = 1 << 5;
+const GPIO_V2_LINE_FLAG_BIAS_PULL_UP: u64 = 1 << 8;
+const GPIO_V2_LINE_FLAG_BIAS_PULL_DOWN: u64 = 1 << 9;
+const GPIO_V2_LINE_FLAG_BIAS_DISABLED: u64 = 1 << 10;
+
+const GPIO_V2_LINE_ATTR_ID_FLAGS: u32 = 1;
+
+const GPIO_V2_LINE_EVENT_RISING_EDGE: u32 = 1;
+
+const INPUT_FLAGS: u64 =
+    GPIO_V2_LINE_FLAG_INPUT | GPIO_V2_LINE_FLAG_EDGE_RISING | GPIO_V2_LINE_FLAG_EDGE_FALLING;
+const OUTPUT_FLAGS: u64 = GPIO_V2_LINE_FLAG_OUTPUT;
+
+/// Number of events read from the kernel at once.
+const EVENT_BUFFER_SIZE: usize = 16;
+
+#[repr(C)]
+struct GpioV2LineAttribute {
+    id: u32,
+    padding: u32,
+    /// Union of `flags`, `values` and `debounce_period_us`.
+    value: u64,
+}
+
+#[repr(C)]
+struct GpioV2LineConfigAttribute {
+    attr: GpioV2LineAttribute,
+    mask: u64,
+}
+
+#[repr(C)]
+struct GpioV2LineConfig {
+    flags: u64,
+    num_attrs: u32,
+    padding: [u32; 5],
+    attrs: [GpioV2LineConfigAttribute; GPIO_V2_LINE_NUM_ATTRS_MAX],
+}
+
+#[repr(C)]
+struct GpioV2LineRequest {
+    offsets: [u32; GPIO_V2_LINES_MAX],
+    consumer: [u8; GPIO_MAX_NAME_SIZE],
+    config: GpioV2LineConfig,
+    num_lines: u32,
+    event_buffer_size: u32,
+    padding: [u32; 5],
+    fd: i32,
+}
+
+#[repr(C)]
+struct GpioV2LineValues {
+    bits: u64,
+    mask: u64,
+}
+
+#[repr(C)]
+struct GpioV2LineEvent {
+    timestamp_ns: u64,
+    id: u32,
+    offset: u32,
+    seqno: u32,
+    line_seqno: u32,
+    padding: [u32; 6],
+}
+
+const fn iowr<T>(nr: u64) -> u64 {
+    (3 << 30) | ((mem::size_of::<T>() as u64) << 16) | (0xb4 << 8) | nr
+}
+
+const GPIO_V2_GET_LINE_IOCTL: u64 = iowr::<GpioV2LineRequest>(0x07);
+const GPIO_V2_LINE_SET_CONFIG_IOCTL: u64 = iowr::<GpioV2LineConfig>(0x0d);
+const GPIO_V2_LINE_GET_VALUES_IOCTL: u64 = iowr::<GpioV2LineValues>(0x0e);
+const GPIO_V2_LINE_SET_VALUES_IOCTL: u64 = iowr::<GpioV2LineValues>(0x0f);
+
+fn bias_flags(bias: Bias) -> u64 {
+    match bias {
+        Bias::AsIs => 0,
+        Bias::PullUp => GPIO_V2_LINE_FLAG_BIAS_PULL_UP,
+        Bias::PullDown => GPIO_V2_LINE_FLAG_BIAS_PULL_DOWN,
+        Bias::Disabled => GPIO_V2_LINE_FLAG_BIAS_DISABLED,
+    }
+}
+
+/// Creates the configuration for lines with the specified flags and the bias
+/// of each line.
+///
+/// The bias of the first line is part of the default flags, lines with a
+/// different bias are configured via attributes.
+fn line_config(flags: u64, biases: &[Bias]) -> GpioV2LineConfig {
+    let mut config: GpioV2LineConfig = unsafe { mem::zeroed() };
+    config.flags = flags | bias_flags(biases[0]);
+    let mut num_attrs = 0;
+    for (i, &bias) in biases.iter().enumerate() {
+        let line_flags = flags | bias_flags(bias);
+        if line_flags == config.flags {
+            continue;
+        }
+        let attr = match config.attrs[..num_attrs]
+            .iter()
+            .position(|attr| attr.attr.value == line_flags)
+        {
+            Some(attr) => attr,
+            None => {
+                num_attrs += 1;
+                let attr = &mut config.attrs[num_attrs - 1];
+                attr.attr.id = GPIO_V2_LINE_ATTR_ID_FLAGS;
+                attr.attr.value = line_flags;
+                num_attrs - 1
+            }
+        };
+        config.attrs[attr].mask |= 1 << i;
+    }
+    config.num_attrs = num_attrs as u32;
+    config
+}
+
+fn ioctl<T>(fd: RawFd, request: u64, arg: &mut T) -> io::Result<()> {
+    let result = unsafe { libc::ioctl(fd, request as _, arg as *mut T) };
+    if result < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
+
+/// Set of lines of a single GPIO chip requested together.
+struct LineRequest {
+    chip: PathBuf,
+    offsets: Vec<u32>,
+    /// Bias of each line, which is kept when the line is reconfigured.
+    biases: Vec<Bias>,
+    fd: File,
+}
+
+impl LineRequest {
+    fn new(chip: &Path, offsets: &[u32], biases: &[Bias], flags: u64) -> io::Result<Self> {
+        assert!(!offsets.is_empty() && offsets.len() <= GPIO_V2_LINES_MAX);
+        assert_eq!(offsets.len(), biases.len());
+        let chip_file = File::open(chip)?;
+        let mut request: GpioV2LineRequest = unsafe { mem::zeroed() };
+        request.offsets[..offsets.len()].copy_from_slice(offsets);
+        request.consumer[..CONSUMER.len()].copy_from_slice(CONSUMER);
+        request.config = line_config(flags, biases);
+        request.num_lines = offsets.len() as u32;
+        ioctl(chip_file.as_raw_fd(), GPIO_V2_GET_LINE_IOCTL, &mut request)?;
+        Ok(Self {
+            chip: chip.to_owned(),
+            offsets: offsets.to_vec(),
+            biases: biases.to_vec(),
+            fd: unsafe { File::from_raw_fd(request.fd) },
+        })
+    }
+
+    fn all_lines(&self) -> u64 {
+        if self.offsets.len() == 64 {
+            !0
+        } else {
+            (1 << self.offsets.len()) - 1
+        }
+    }
+
+    fn reconfigure(&self, flags: u64) -> io::Result<()> {
+        let mut config = line_config(flags, &self.biases);
+        ioctl(
+            self.fd.as_raw_fd(),
+            GPIO_V2_LINE_SET_CONFIG_IOCTL,
+            &mut config,
+        )
+    }
+
+    fn get_values(&self) -> io::Result<u64> {
+        let mut values = GpioV2LineValues {
+            bits: 0,
+            mask: self.all_lines(),
+        };
+        ioctl(
+            self.fd.as_raw_fd(),
+            GPIO_V2_LINE_GET_VALUES_IOCTL,
+            &mut values,
+        )?;
+        Ok(values.bits)
+    }
+
+    fn set_values(&self, bits: u64) -> io::Result<()> {
+        let mut values = GpioV2LineValues {
+            bits,
+            mask: self.all_lines(),
+        };
+        ioctl(
+            self.fd.as_raw_fd(),
+            GPIO_V2_LINE_SET_VALUES_IOCTL,
+            &mut values,
+        )
+    }
+
+    /// Waits for edge events and returns all events which are available.
+    ///
+    /// An empty list is returned if the timeout expired.
+    fn wait_events(&self, timeout: Option<Duration>) -> io::Result<Vec<Edge>> {
+        let ready = super::poll::poll(&[self.fd.as_raw_fd()], libc::POLLIN, timeout)?;
+        if ready == 0 {
+            return Ok(Vec::new());
+        }
+
+        let event_size = mem::size_of::<GpioV2LineEvent>();
+        let mut buffer = [0u8; EVENT_BUFFER_SIZE * mem::size_of::<GpioV2LineEvent>()];
+        let length = (&self.fd).read(&mut buffer)?;
+        let mut edges = Vec::new();
+        for chunk in buffer[..length].chunks_exact(event_size) {
+            let event: GpioV2LineEvent =
+                unsafe { std::ptr::read_unaligned(chunk.as_ptr() as *const GpioV2LineEvent) };
+            let pin = match self.offsets.iter().position(|&o| o == event.offset) {
+                Some(pin) => pin,
+                None => continue,
+            };
+            edges.push(Edge {
+                pin,
+                value: event.id == GPIO_V2_LINE_EVENT_RISING_EDGE,
+                timestamp: Some(Duration::from_nanos(event.timestamp_ns)),
+            });
+        }
+        Ok(edges)
+    }
+}
+
+pub struct CdevInputPin {
+    request: LineRequest,
+}
+
+impl CdevInputPin {
+    /// Requests a line of the specified GPIO chip as an input.
+    pub fn open(chip: &Path, offset: u32, bias: Bias) -> Result<Self, io::Error> {
+        Ok(Self {
+            request: LineRequest::new(chip, &[offset], &[bias], INPUT_FLAGS)?,
+        })
+    }
+}
+
+impl super::InputPin for CdevInputPin {
+    type Output = CdevOutputPin;
+    type Group = CdevInputPinGroup;
+
+    fn read(&self) -> bool {
+        self.request
+            .get_values()
+            .expect("could not read GPIO value")
+            != 0
+    }
+    fn wait(&self) {
+        self.request
+            .wait_events(None)
+            .expect("could not read GPIO events");
+    }
+    fn wait_timeout(&self, timeout: Duration) -> bool {
+        !self.wait_edges_timeout(timeout).is_empty()
+    }
+    fn wait_edges(&self) -> Vec<Edge> {
+        self.request
+            .wait_events(None)
+            .expect("could not read GPIO events")
+    }
+    fn wait_edges_timeout(&self, timeout: Duration) -> Vec<Edge> {
+        self.request
+            .wait_events(Some(timeout))
+            .expect("could not read GPIO events")
+    }
+
+    fn create_group(pins: Vec<Box<Self>>) -> Self::Group {
+        assert!(!pins.is_empty());
+        let chip = pins[0].request.chip.clone();
+        let mut offsets = Vec::new();
+        let mut biases = Vec::new();
+        for pin in pins {
+            assert!(
+                pin.request.chip == chip,
+                "all pins of a group must belong to the same chip"
+            );
+            offsets.push(pin.request.offsets[0]);
+            biases.push(pin.request.biases[0]);
+            // The single-line request is released here so that the line can
+            // be requested again as part of the group.
+        }
+        CdevInputPinGroup {
+            request: LineRequest::new(&chip, &offsets, &biases, INPUT_FLAGS)
+                .expect("could not request GPIO lines"),
+        }
+    }
+    fn into_output(self) -> io::Result<Self::Output> {
+        self.request.reconfigure(OUTPUT_FLAGS)?;
+        Ok(CdevOutputPin {
+            request: self.request,
+        })
+    }
+}
+
+pub struct CdevInputPinGroup {
+    request: LineRequest,
+}
+
+impl super::InputPinGroup for CdevInputPinGroup {
+    type Pin = CdevInputPin;
+
+    fn read(&self) -> u64 {
+        self.request
+            .get_values()
+            .expect("could not read GPIO values")
+    }
+    fn wait(&self) {
+        self.request
+            .wait_events(None)
+            .expect("could not read GPIO events");
+    }
+    fn wait_timeout(&self, timeout: Duration) -> Option<u64> {
+        let edges = self.wait_edges_timeout(timeout);
+        if edges.is_empty() {
+            None
+        } else {
+            Some(
+                edges
+                    .iter()
+                    .fold(0, |changed, edge| changed | 1 << edge.pin),
+            )
+        }
+    }
+    fn wait_edges(&self) -> Vec<Edge> {
+        self.request
+            .wait_events(None)
+            .expect("could not read GPIO events")
+    }
+    fn wait_edges_timeout(&self, timeout: Duration) -> Vec<Edge> {
+        self.request
+            .wait_events(Some(timeout))
+            .expect("could not read GPIO events")
+    }
+    fn len(&self) -> usize {
+        self.request.offsets.len()
+    }
+
+    fn split(self) -> Vec<Box<Self::Pin>> {
+        let chip = self.request.chip.clone();
+        let offsets = self.request.offsets.clone();
+        let biases = self.request.biases.clone();
+        drop(self.request);
+        offsets
+            .into_iter()
+            .zip(biases)
+            .map(|(offset, bias)| {
+                Box::new(
+                    CdevInputPin::open(&chip, offset, bias).expect("could not request GPIO line"),
+                )
+            })
+            .collect()
+    }
+}
+
+pub struct CdevOutputPin {
+    request: LineRequest,
+}
+
+impl CdevOutputPin {
+    /// Requests a line of the specified GPIO chip as an output.
+    ///
+    /// The pin is initially set to `false`.
+    pub fn open(chip: &Path, offset: u32, bias: Bias) -> Result<Self, io::Error> {
+        Ok(Self {
+            request: LineRequest::new(chip, &[offset], &[bias], OUTPUT_FLAGS)?,
+        })
+    }
+}
+
+impl super::OutputPin for CdevOutputPin {
+    type Input = CdevInputPin;
+
+    fn write(&self, value: bool) {
+        self.request
+            .set_values(value as u64)
+            .expect("could not write GPIO value");
+    }
+    fn into_input(self) -> io::Result<Self::Input> {
+        self.request.reconfigure(INPUT_FLAGS)?;
+        Ok(CdevInputPin {
+            request: self.request,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_abi() {
+        // Sizes and ioctl numbers as defined in linux/gpio.h.
+        assert_eq!(mem::size_of::<GpioV2LineConfig>(), 272);
+        assert_eq!(mem::size_of::<GpioV2LineRequest>(), 592);
+        assert_eq!(mem::size_of::<GpioV2LineEvent>(), 48);
+        assert_eq!(GPIO_V2_GET_LINE_IOCTL, 0xc250_b407);
+        assert_eq!(GPIO_V2_LINE_SET_CONFIG_IOCTL, 0xc110_b40d);
+        assert_eq!(GPIO_V2_LINE_GET_VALUES_IOCTL, 0xc010_b40e);
+        assert_eq!(GPIO_V2_LINE_SET_VALUES_IOCTL, 0xc010_b40f);
+    }
+
+    #[test]
+    fn test_missing_chip() {
+        let chip = Path::new("/dev/fernsprechapparat-no-such-gpiochip");
+        assert!(CdevInputPin::open(chip, 0, Bias::AsIs).is_err());
+        assert!(CdevOutputPin::open(chip, 0, Bias::PullUp).is_err());
+    }
+
+    #[test]
+    fn test_line_config() {
+        let config = line_config(INPUT_FLAGS, &[Bias::AsIs]);
+        assert_eq!(config.flags, INPUT_FLAGS);
+        assert_eq!(config.num_attrs, 0);
+
+        let config = line_config(
+            INPUT_FLAGS,
+            &[
+                Bias::PullUp,
+                Bias::AsIs,
+                Bias::PullUp,
+                Bias::PullDown,
+                Bias::AsIs,
+            ],
+        );
+        assert_eq!(config.flags, INPUT_FLAGS | GPIO_V2_LINE_FLAG_BIAS_PULL_UP);
+        assert_eq!(config.num_attrs, 2);
+        assert_eq!(config.attrs[0].attr.id, GPIO_V2_LINE_ATTR_ID_FLAGS);
+        assert_eq!(config.attrs[0].attr.value, INPUT_FLAGS);
+        assert_eq!(config.attrs[0].mask, 0b10010);
+        assert_eq!(
+            config.attrs[1].attr.value,
+            INPUT_FLAGS | GPIO_V2_LINE_FLAG_BIAS_PULL_DOWN
+        );
+        assert_eq!(config.attrs[1].mask, 0b01000);
+    }
+}
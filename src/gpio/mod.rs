@@ -1,10 +1,47 @@
 //! Types for GPIO input/output.
 
+use std::io;
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
 use std::time::Duration;
 
+pub mod cdev;
+pub mod polarity;
+pub mod poll;
+pub mod remote;
 #[allow(dead_code)]
 pub mod sim;
 pub mod sysfs;
+#[allow(dead_code)]
+pub mod trace;
+
+/// Internal pull resistor of a pin.
+///
+/// Only the cdev backend can configure the bias, with sysfs it has to be set
+/// up via the device tree.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Bias {
+    /// The bias is left as configured by the kernel.
+    #[default]
+    AsIs,
+    PullUp,
+    PullDown,
+    /// Both pull resistors are disabled.
+    Disabled,
+}
+
+/// Change of the value of an input pin.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Edge {
+    /// Index of the pin within the group (always 0 for single pins).
+    pub pin: usize,
+    /// Value of the pin after the edge.
+    pub value: bool,
+    /// Time of the edge according to the monotonic clock (`CLOCK_MONOTONIC`),
+    /// if the backend reports it.
+    pub timestamp: Option<Duration>,
+}
 
 /// Single GPIO input pin with support for interrupts.
 pub trait InputPin {
@@ -22,12 +59,49 @@ pub trait InputPin {
     /// The function returns false if no interrupt arrived within the specified
     /// duration.
     fn wait_timeout(&self, timeout: Duration) -> bool;
+    /// Waits like `wait()` and returns the reported edges.
+    ///
+    /// Backends which do not report individual edges return a single edge
+    /// without timestamp with the value read after the wait, so edges in
+    /// between are lost.
+    fn wait_edges(&self) -> Vec<Edge> {
+        self.wait();
+        vec![Edge {
+            pin: 0,
+            value: self.read(),
+            timestamp: None,
+        }]
+    }
+    /// Waits like `wait_timeout()` and returns the reported edges (see
+    /// `wait_edges()`).
+    ///
+    /// An empty list is returned if the timeout expired.
+    fn wait_edges_timeout(&self, timeout: Duration) -> Vec<Edge> {
+        if !self.wait_timeout(timeout) {
+            return Vec::new();
+        }
+        vec![Edge {
+            pin: 0,
+            value: self.read(),
+            timestamp: None,
+        }]
+    }
+    /// Sets a flag which ends all waits once it is set, so that the thread
+    /// waiting for the pin can be stopped.
+    ///
+    /// Only the simulated pins with virtual time use the flag, as their
+    /// timeouts do not expire unless the test advances the time. Other
+    /// backends return after the timeout, and the thread checks its flag.
+    fn set_stop_flag(&mut self, _stop: Arc<AtomicBool>) {}
 
     /// Groups multiple input pins into an input pin set so that a single call
     /// can be used to wait for changes of multiple pins.
     fn create_group(pins: Vec<Box<Self>>) -> Self::Group;
-    /// Converts this input pin into an output pin.
-    fn into_output(self) -> Self::Output;
+    /// Converts this input pin into an output pin which is initially set to
+    /// `false`.
+    ///
+    /// The function fails if the direction of the pin cannot be changed.
+    fn into_output(self) -> io::Result<Self::Output>;
 }
 
 /// Group of multiple input pins which supports reading multiple pin values and
@@ -38,8 +112,8 @@ pub trait InputPinGroup {
 
     /// Reads the input of all the pins in the set.
     ///
-    /// Note that the call is not atomic and the individual pins are evaluated
-    /// sequentially.
+    /// Note that the call is not necessarily atomic, as some implementations
+    /// evaluate the individual pins sequentially.
     fn read(&self) -> u64;
     /// Waits for a value change of one of the pins.
     fn wait(&self);
@@ -48,6 +122,45 @@ pub trait InputPinGroup {
     /// The function either returns the number of the pin which triggered the
     /// interrupt or `None` if the timeout expired.
     fn wait_timeout(&self, timeout: Duration) -> Option<u64>;
+    /// Waits like `wait()` and returns the reported edges.
+    ///
+    /// Backends which do not report individual edges return one edge without
+    /// timestamp for each pin with its value after the wait, so edges in
+    /// between are lost.
+    fn wait_edges(&self) -> Vec<Edge> {
+        self.wait();
+        let values = self.read();
+        (0..self.len())
+            .map(|pin| Edge {
+                pin,
+                value: values & (1 << pin) != 0,
+                timestamp: None,
+            })
+            .collect()
+    }
+    /// Waits like `wait_timeout()` and returns the reported edges (see
+    /// `wait_edges()`).
+    ///
+    /// Without timestamps, only the pins which have changed are reported. An
+    /// empty list is returned if the timeout expired.
+    fn wait_edges_timeout(&self, timeout: Duration) -> Vec<Edge> {
+        let changed = match self.wait_timeout(timeout) {
+            Some(changed) => changed,
+            None => return Vec::new(),
+        };
+        let values = self.read();
+        (0..self.len())
+            .filter(|pin| changed & (1 << pin) != 0)
+            .map(|pin| Edge {
+                pin,
+                value: values & (1 << pin) != 0,
+                timestamp: None,
+            })
+            .collect()
+    }
+    /// Sets a flag which ends all waits once it is set (see
+    /// `InputPin::set_stop_flag()`).
+    fn set_stop_flag(&mut self, _stop: Arc<AtomicBool>) {}
     /// Returns the number of pins in this group.
     fn len(&self) -> usize;
 
@@ -64,5 +177,7 @@ pub trait OutputPin {
     fn write(&self, value: bool);
 
     /// Converts this output pin into an input pin.
-    fn into_input(self) -> Self::Input;
+    ///
+    /// The function fails if the direction of the pin cannot be changed.
+    fn into_input(self) -> io::Result<Self::Input>;
 }
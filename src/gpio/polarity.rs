@@ -0,0 +1,244 @@
+//! Wrappers which invert the values of active-low pins.
+//!
+//! The types using the pins only deal with logical values, where `true` means
+//! that the contact is active (closed, pressed, ...). Whether that corresponds
+//! to a high or low level depends on how the phone has been wired and is
+//! configured per pin.
+
+use super::{Edge, InputPin, InputPinGroup, OutputPin};
+
+use std::io;
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Level at which a pin is active.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Polarity {
+    /// A high level (GPIO value `true`) means that the pin is active.
+    ActiveHigh,
+    /// A low level (GPIO value `false`) means that the pin is active.
+    ActiveLow,
+}
+
+impl Polarity {
+    fn inverted(self) -> bool {
+        self == Polarity::ActiveLow
+    }
+}
+
+/// Input pin which returns logical instead of physical values.
+pub struct PolarityInputPin<Pin: InputPin> {
+    pin: Pin,
+    polarity: Polarity,
+}
+
+impl<Pin: InputPin> PolarityInputPin<Pin> {
+    pub fn new(pin: Pin, polarity: Polarity) -> Self {
+        Self { pin, polarity }
+    }
+}
+
+impl<Pin: InputPin> InputPin for PolarityInputPin<Pin> {
+    type Output = PolarityOutputPin<Pin::Output>;
+    type Group = PolarityInputPinGroup<Pin::Group>;
+
+    fn read(&self) -> bool {
+        self.pin.read() != self.polarity.inverted()
+    }
+    fn wait(&self) {
+        self.pin.wait();
+    }
+    fn wait_timeout(&self, timeout: Duration) -> bool {
+        self.pin.wait_timeout(timeout)
+    }
+    fn wait_edges(&self) -> Vec<Edge> {
+        let inverted = self.polarity.inverted();
+        invert_edges(self.pin.wait_edges(), |_| inverted)
+    }
+    fn wait_edges_timeout(&self, timeout: Duration) -> Vec<Edge> {
+        let inverted = self.polarity.inverted();
+        invert_edges(self.pin.wait_edges_timeout(timeout), |_| inverted)
+    }
+    fn set_stop_flag(&mut self, stop: Arc<AtomicBool>) {
+        self.pin.set_stop_flag(stop);
+    }
+
+    fn create_group(pins: Vec<Box<Self>>) -> Self::Group {
+        let polarities = pins.iter().map(|pin| pin.polarity).collect::<Vec<_>>();
+        let pins = pins.into_iter().map(|pin| Box::new(pin.pin)).collect();
+        PolarityInputPinGroup::new(Pin::create_group(pins), polarities)
+    }
+    fn into_output(self) -> io::Result<Self::Output> {
+        let output = PolarityOutputPin::new(self.pin.into_output()?, self.polarity);
+        // The new output is physically low, which activates active-low pins.
+        output.write(false);
+        Ok(output)
+    }
+}
+
+/// Input pin group which returns logical instead of physical values.
+pub struct PolarityInputPinGroup<Group: InputPinGroup> {
+    group: Group,
+    /// Bits of the pins which are active-low.
+    inverted: u64,
+}
+
+impl<Group: InputPinGroup> PolarityInputPinGroup<Group> {
+    /// Wraps a group, with one entry in `polarities` for each pin of the group.
+    pub fn new(group: Group, polarities: Vec<Polarity>) -> Self {
+        assert_eq!(group.len(), polarities.len());
+        let inverted = polarities
+            .iter()
+            .enumerate()
+            .filter(|(_, polarity)| polarity.inverted())
+            .fold(0, |mask, (i, _)| mask | (1 << i));
+        Self { group, inverted }
+    }
+}
+
+impl<Group: InputPinGroup> InputPinGroup for PolarityInputPinGroup<Group> {
+    type Pin = PolarityInputPin<Group::Pin>;
+
+    fn read(&self) -> u64 {
+        self.group.read() ^ self.inverted
+    }
+    fn wait(&self) {
+        self.group.wait();
+    }
+    fn wait_timeout(&self, timeout: Duration) -> Option<u64> {
+        self.group.wait_timeout(timeout)
+    }
+    fn wait_edges(&self) -> Vec<Edge> {
+        invert_edges(self.group.wait_edges(), |pin| {
+            self.inverted & (1 << pin) != 0
+        })
+    }
+    fn wait_edges_timeout(&self, timeout: Duration) -> Vec<Edge> {
+        invert_edges(self.group.wait_edges_timeout(timeout), |pin| {
+            self.inverted & (1 << pin) != 0
+        })
+    }
+    fn set_stop_flag(&mut self, stop: Arc<AtomicBool>) {
+        self.group.set_stop_flag(stop);
+    }
+    fn len(&self) -> usize {
+        self.group.len()
+    }
+
+    fn split(self) -> Vec<Box<Self::Pin>> {
+        let inverted = self.inverted;
+        self.group
+            .split()
+            .into_iter()
+            .enumerate()
+            .map(|(i, pin)| {
+                let polarity = if inverted & (1 << i) != 0 {
+                    Polarity::ActiveLow
+                } else {
+                    Polarity::ActiveHigh
+                };
+                Box::new(PolarityInputPin::new(*pin, polarity))
+            })
+            .collect()
+    }
+}
+
+/// Converts the values of edges of the pins for which `inverted` returns true.
+fn invert_edges(edges: Vec<Edge>, inverted: impl Fn(usize) -> bool) -> Vec<Edge> {
+    edges
+        .into_iter()
+        .map(|edge| Edge {
+            value: edge.value != inverted(edge.pin),
+            ..edge
+        })
+        .collect()
+}
+
+/// Output pin which is set to logical instead of physical values.
+pub struct PolarityOutputPin<Pin: OutputPin> {
+    pin: Pin,
+    polarity: Polarity,
+}
+
+impl<Pin: OutputPin> PolarityOutputPin<Pin> {
+    pub fn new(pin: Pin, polarity: Polarity) -> Self {
+        Self { pin, polarity }
+    }
+}
+
+impl<Pin: OutputPin> OutputPin for PolarityOutputPin<Pin> {
+    type Input = PolarityInputPin<Pin::Input>;
+
+    fn write(&self, value: bool) {
+        self.pin.write(value != self.polarity.inverted());
+    }
+
+    fn into_input(self) -> io::Result<Self::Input> {
+        Ok(PolarityInputPin::new(self.pin.into_input()?, self.polarity))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gpio::sim::SimEnvironment;
+
+    #[test]
+    fn test_polarity() {
+        let env = SimEnvironment::new();
+        let high = PolarityInputPin::new(env.create_input_pin(0, false), Polarity::ActiveHigh);
+        let low = PolarityInputPin::new(env.create_input_pin(1, false), Polarity::ActiveLow);
+        assert!(!high.read());
+        assert!(low.read());
+        env.write_input(0, true);
+        env.write_input(1, true);
+        assert!(high.read());
+        assert!(!low.read());
+        assert!(low.wait_timeout(Duration::from_millis(0)));
+
+        // Groups keep the polarity of the individual pins.
+        let group = PolarityInputPin::create_group(vec![Box::new(low), Box::new(high)]);
+        assert_eq!(group.read(), 0b10);
+        env.write_input(1, false);
+        assert_eq!(group.read(), 0b11);
+        let pins = group.split();
+        assert!(pins[0].read());
+        assert!(pins[1].read());
+        env.write_input(0, false);
+        assert!(!pins[1].read());
+
+        let output = PolarityOutputPin::new(env.create_output_pin(2, false), Polarity::ActiveLow);
+        output.write(true);
+        assert!(!env.read_output(2));
+        output.write(false);
+        assert!(env.read_output(2));
+    }
+
+    #[test]
+    fn test_edges() {
+        let env = SimEnvironment::new();
+        let high = PolarityInputPin::new(env.create_input_pin(0, false), Polarity::ActiveHigh);
+        let low = PolarityInputPin::new(env.create_input_pin(1, false), Polarity::ActiveLow);
+        env.write_input(1, true);
+        assert_eq!(
+            low.wait_edges_timeout(Duration::from_millis(0)),
+            vec![Edge {
+                pin: 0,
+                value: false,
+                timestamp: None,
+            }]
+        );
+
+        let group = PolarityInputPin::create_group(vec![Box::new(high), Box::new(low)]);
+        env.write_input(0, true);
+        env.write_input(1, false);
+        let edges = group.wait_edges_timeout(Duration::from_millis(0));
+        let values = edges
+            .iter()
+            .map(|edge| (edge.pin, edge.value))
+            .collect::<Vec<_>>();
+        assert_eq!(values, vec![(0, true), (1, true)]);
+    }
+}
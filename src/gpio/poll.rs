@@ -0,0 +1,59 @@
+//! Helper to wait for events on multiple file descriptors.
+
+use std::io;
+use std::os::unix::io::RawFd;
+use std::time::{Duration, Instant};
+
+/// Waits until one of the file descriptors signals one of the specified
+/// events.
+///
+/// The function returns a bitmap of the file descriptors which signalled an
+/// event, or 0 if the timeout expired.
+pub fn poll(fds: &[RawFd], events: libc::c_short, timeout: Option<Duration>) -> io::Result<u64> {
+    assert!(fds.len() <= 64);
+    let mut pollfds: Vec<libc::pollfd> = fds
+        .iter()
+        .map(|&fd| libc::pollfd {
+            fd,
+            events,
+            revents: 0,
+        })
+        .collect();
+    // Interrupted calls are repeated with the remaining time, so that signals
+    // do not extend the timeout.
+    let deadline = timeout.map(|timeout| Instant::now() + timeout);
+    loop {
+        let timeout_ms = match deadline {
+            // Round up so that we never return before the timeout has expired.
+            Some(deadline) => deadline
+                .saturating_duration_since(Instant::now())
+                .as_micros()
+                .div_ceil(1000)
+                .min(libc::c_int::MAX as u128) as libc::c_int,
+            None => -1,
+        };
+        let result = unsafe {
+            libc::poll(
+                pollfds.as_mut_ptr(),
+                pollfds.len() as libc::nfds_t,
+                timeout_ms,
+            )
+        };
+        if result < 0 {
+            let error = io::Error::last_os_error();
+            if error.kind() == io::ErrorKind::Interrupted {
+                continue;
+            }
+            return Err(error);
+        }
+        break;
+    }
+
+    let mut ready = 0;
+    for (i, pollfd) in pollfds.iter().enumerate() {
+        if pollfd.revents != 0 {
+            ready |= 1 << i;
+        }
+    }
+    Ok(ready)
+}
@@ -0,0 +1,407 @@
+//! Simulated GPIO pins which are controlled by another process.
+//!
+//! The pins are backed by a `SimEnvironment` which is exposed via a Unix
+//! domain socket, so that integration tests can run the complete application
+//! with a simulated phone. Clients send one command per line and receive one
+//! reply per command:
+//!
+//! ```text
+//! set <line> <0|1>   sets an input pin, the reply is "ok"
+//! get <line>         reads an output pin, the reply is "value <line> <0|1>"
+//! subscribe          the reply is "ok", afterwards every change of an output
+//!                    is sent as "output <line> <0|1>"
+//! ```
+//!
+//! Invalid commands are answered with "error <message>". Pins are identified
+//! by the `line` numbers of the `gpio` configuration. The values are physical
+//! levels like those of real pins, so the configured polarity applies.
+
+use super::sim::{SimEnvironment, SimInputPin, SimOutputPin};
+
+use std::fs;
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::unix::fs::FileTypeExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// Default path of the socket.
+pub const DEFAULT_SOCKET: &str = "/tmp/fernsprechapparat-gpio.sock";
+
+/// Number of pins supported by the simulated environment.
+const MAX_PINS: u32 = 64;
+
+/// Interval in which the server threads check whether they have been stopped.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Simulated environment which accepts clients on a Unix domain socket.
+///
+/// The socket is removed when the environment is dropped.
+pub struct RemoteEnvironment {
+    env: SimEnvironment,
+    path: PathBuf,
+    thread: Option<JoinHandle<()>>,
+    stop_thread: Arc<AtomicBool>,
+}
+
+impl RemoteEnvironment {
+    /// Creates the socket and starts accepting clients.
+    ///
+    /// A socket left behind by a previous run is replaced, other files at the
+    /// path are not. The function fails if another instance still accepts
+    /// clients on the socket.
+    pub fn bind(path: &Path) -> io::Result<Self> {
+        if let Ok(metadata) = fs::symlink_metadata(path) {
+            if metadata.file_type().is_socket() {
+                match UnixStream::connect(path) {
+                    Ok(_) => {
+                        return Err(io::Error::new(
+                            io::ErrorKind::AddrInUse,
+                            format!("{} is used by a running instance", path.display()),
+                        ))
+                    }
+                    Err(e) if e.kind() == io::ErrorKind::ConnectionRefused => {
+                        fs::remove_file(path)?
+                    }
+                    Err(e) => return Err(e),
+                }
+            }
+        }
+        let listener = UnixListener::bind(path)?;
+        // The listener is polled so that the thread can be stopped.
+        listener.set_nonblocking(true)?;
+
+        let env = SimEnvironment::new();
+        let stop_thread = Arc::new(AtomicBool::new(false));
+        let env_copy = env.clone();
+        let stop_thread_copy = stop_thread.clone();
+        let thread = thread::spawn(move || {
+            accept_clients(listener, env_copy, stop_thread_copy);
+        });
+        Ok(Self {
+            env,
+            path: path.to_owned(),
+            thread: Some(thread),
+            stop_thread,
+        })
+    }
+
+    pub fn create_input_pin(&self, line: u32) -> io::Result<SimInputPin> {
+        Ok(self.env.create_input_pin(check_line(line)?, false))
+    }
+
+    pub fn create_output_pin(&self, line: u32) -> io::Result<SimOutputPin> {
+        Ok(self.env.create_output_pin(check_line(line)?, false))
+    }
+}
+
+impl Drop for RemoteEnvironment {
+    fn drop(&mut self) {
+        self.stop_thread.store(true, Ordering::SeqCst);
+        self.thread.take().unwrap().join().unwrap();
+        fs::remove_file(&self.path).ok();
+    }
+}
+
+fn check_line(line: u32) -> io::Result<usize> {
+    if line >= MAX_PINS {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("the remote backend only supports lines below {}", MAX_PINS),
+        ));
+    }
+    Ok(line as usize)
+}
+
+fn accept_clients(listener: UnixListener, env: SimEnvironment, stop_thread: Arc<AtomicBool>) {
+    let mut clients: Vec<JoinHandle<()>> = Vec::new();
+    while !stop_thread.load(Ordering::SeqCst) {
+        match listener.accept() {
+            Ok((stream, _)) => {
+                let env = env.clone();
+                let stop_thread = stop_thread.clone();
+                clients.push(thread::spawn(move || {
+                    if let Err(e) = serve_client(stream, env, stop_thread) {
+                        println!("Remote GPIO client failed: {}", e);
+                    }
+                }));
+            }
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => thread::sleep(POLL_INTERVAL),
+            Err(e) => {
+                println!("Could not accept remote GPIO client: {}", e);
+                thread::sleep(POLL_INTERVAL);
+            }
+        }
+        clients.retain(|client| !client.is_finished());
+    }
+    for client in clients {
+        client.join().unwrap();
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Command {
+    Set(usize, bool),
+    Get(usize),
+    Subscribe,
+}
+
+fn parse_command(line: &str) -> Result<Command, String> {
+    let words = line.split_whitespace().collect::<Vec<_>>();
+    let pin = |word: &str| match word.parse::<u32>() {
+        Ok(line) if line < MAX_PINS => Ok(line as usize),
+        _ => Err(format!("invalid pin \"{}\"", word)),
+    };
+    match words[..] {
+        ["set", line, value] => {
+            let value = match value {
+                "0" => false,
+                "1" => true,
+                _ => return Err(format!("invalid value \"{}\"", value)),
+            };
+            Ok(Command::Set(pin(line)?, value))
+        }
+        ["get", line] => Ok(Command::Get(pin(line)?)),
+        ["subscribe"] => Ok(Command::Subscribe),
+        _ => Err(format!("invalid command \"{}\"", line)),
+    }
+}
+
+fn send(stream: &mut UnixStream, line: &str) -> io::Result<()> {
+    stream.write_all(format!("{}\n", line).as_bytes())
+}
+
+fn serve_client(
+    stream: UnixStream,
+    env: SimEnvironment,
+    stop_thread: Arc<AtomicBool>,
+) -> io::Result<()> {
+    stream.set_nonblocking(false)?;
+    stream.set_read_timeout(Some(POLL_INTERVAL))?;
+    // Replies and output changes are sent from different threads.
+    let writer = Arc::new(Mutex::new(stream.try_clone()?));
+    let mut reader = BufReader::new(stream);
+    let closed = Arc::new(AtomicBool::new(false));
+    let mut subscription = None;
+
+    let mut line = Vec::new();
+    let result = loop {
+        if stop_thread.load(Ordering::SeqCst) {
+            break Ok(());
+        }
+        // Incomplete lines are kept in the buffer when the read times out.
+        match reader.read_until(b'\n', &mut line) {
+            Ok(0) => break Ok(()),
+            Ok(_) => {}
+            Err(ref e)
+                if e.kind() == io::ErrorKind::WouldBlock || e.kind() == io::ErrorKind::TimedOut =>
+            {
+                continue
+            }
+            Err(e) => break Err(e),
+        }
+        let text = String::from_utf8_lossy(&line).trim().to_string();
+        line.clear();
+        if text.is_empty() {
+            continue;
+        }
+
+        // The writer stays locked until the reply has been sent, so that no
+        // output change is sent in between.
+        let mut stream = writer.lock().unwrap();
+        let reply = match parse_command(&text) {
+            Ok(Command::Set(line, _)) if env.is_output(line) => {
+                format!("error pin {} is not an input", line)
+            }
+            Ok(Command::Set(line, value)) => {
+                env.write_input(line, value);
+                "ok".to_string()
+            }
+            Ok(Command::Get(line)) if !env.is_output(line) => {
+                format!("error pin {} is not an output", line)
+            }
+            Ok(Command::Get(line)) => format!("value {} {}", line, env.read_output(line) as u8),
+            Ok(Command::Subscribe) => {
+                if subscription.is_none() {
+                    let changes = env.subscribe_outputs();
+                    let writer = writer.clone();
+                    let closed = closed.clone();
+                    subscription = Some(thread::spawn(move || {
+                        forward_outputs(changes, writer, closed);
+                    }));
+                }
+                "ok".to_string()
+            }
+            Err(message) => format!("error {}", message),
+        };
+        if let Err(e) = send(&mut stream, &reply) {
+            break Err(e);
+        }
+    };
+
+    closed.store(true, Ordering::SeqCst);
+    if let Some(subscription) = subscription {
+        subscription.join().unwrap();
+    }
+    result
+}
+
+/// Sends the output changes to a subscribed client until the connection is
+/// closed.
+fn forward_outputs(
+    changes: Receiver<(usize, bool)>,
+    writer: Arc<Mutex<UnixStream>>,
+    closed: Arc<AtomicBool>,
+) {
+    while !closed.load(Ordering::SeqCst) {
+        match changes.recv_timeout(POLL_INTERVAL) {
+            Ok((line, value)) => {
+                let mut stream = writer.lock().unwrap();
+                if send(&mut stream, &format!("output {} {}", line, value as u8)).is_err() {
+                    return;
+                }
+            }
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => return,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::SystemClock;
+    use crate::earpiece::{Earpiece, EarpieceConfig};
+    use crate::gpio::{InputPin, OutputPin};
+    use crate::ringer::{Cadence, Ringer};
+    use crate::Event;
+
+    use std::process;
+    use std::sync::mpsc::channel;
+
+    const TIMEOUT: Duration = Duration::from_secs(2);
+
+    /// Test driver which controls the pins via the socket.
+    struct Client {
+        reader: BufReader<UnixStream>,
+        writer: UnixStream,
+    }
+
+    impl Client {
+        fn connect(path: &Path) -> Self {
+            let stream = UnixStream::connect(path).unwrap();
+            stream.set_read_timeout(Some(TIMEOUT)).unwrap();
+            Self {
+                writer: stream.try_clone().unwrap(),
+                reader: BufReader::new(stream),
+            }
+        }
+
+        fn receive(&mut self) -> String {
+            let mut line = String::new();
+            self.reader.read_line(&mut line).unwrap();
+            line.trim_end().to_string()
+        }
+
+        fn command(&mut self, command: &str) -> String {
+            send(&mut self.writer, command).unwrap();
+            self.receive()
+        }
+    }
+
+    fn socket_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("fernsprechapparat-{}-{}.sock", name, process::id()))
+    }
+
+    #[test]
+    fn test_parse_command() {
+        assert_eq!(parse_command("set 4 1"), Ok(Command::Set(4, true)));
+        assert_eq!(parse_command("set 63 0"), Ok(Command::Set(63, false)));
+        assert_eq!(parse_command("get 3"), Ok(Command::Get(3)));
+        assert_eq!(parse_command(" subscribe "), Ok(Command::Subscribe));
+        assert!(parse_command("set 64 1").is_err());
+        assert!(parse_command("set 4 high").is_err());
+        assert!(parse_command("get").is_err());
+        assert!(parse_command("reset").is_err());
+    }
+
+    #[test]
+    fn test_remote() {
+        let path = socket_path("remote");
+        let remote = RemoteEnvironment::bind(&path).unwrap();
+        let hook = remote.create_input_pin(4).unwrap();
+        let bell = remote.create_output_pin(3).unwrap();
+        assert!(remote.create_input_pin(64).is_err());
+
+        let mut client = Client::connect(&path);
+        assert_eq!(client.command("set 4 1"), "ok");
+        assert!(hook.read());
+        assert_eq!(client.command("get 3"), "value 3 0");
+        bell.write(true);
+        assert_eq!(client.command("get 3"), "value 3 1");
+
+        assert_eq!(client.command("subscribe"), "ok");
+        bell.write(false);
+        assert_eq!(client.receive(), "output 3 0");
+
+        assert_eq!(client.command("set 3 1"), "error pin 3 is not an input");
+        assert_eq!(client.command("get 4"), "error pin 4 is not an output");
+        assert_eq!(client.command("ring"), "error invalid command \"ring\"");
+
+        // A second client does not receive the output changes.
+        let mut other = Client::connect(&path);
+        assert_eq!(other.command("set 4 0"), "ok");
+        assert!(!hook.read());
+        bell.write(true);
+        assert_eq!(client.receive(), "output 3 1");
+
+        // The socket of a running instance is not taken over.
+        let error = RemoteEnvironment::bind(&path).err().unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::AddrInUse);
+        assert_eq!(client.command("get 3"), "value 3 1");
+
+        drop(client);
+        drop(other);
+        drop(remote);
+        assert!(!path.exists());
+    }
+
+    /// Picks up the earpiece and checks that the bell rings, as an external
+    /// test driver would.
+    #[test]
+    fn test_phone() {
+        let path = socket_path("phone");
+        let remote = RemoteEnvironment::bind(&path).unwrap();
+        // A stale socket is replaced.
+        drop(remote);
+        UnixListener::bind(&path).unwrap();
+        let remote = RemoteEnvironment::bind(&path).unwrap();
+
+        let (sender, events) = channel();
+        let _earpiece = Earpiece::new(
+            remote.create_input_pin(4).unwrap(),
+            EarpieceConfig::default(),
+            SystemClock,
+            sender,
+        );
+        let ringer = Ringer::new(
+            remote.create_output_pin(3).unwrap(),
+            Cadence::german(),
+            SystemClock,
+        );
+
+        let mut client = Client::connect(&path);
+        assert_eq!(client.command("subscribe"), "ok");
+        ringer.start();
+        assert_eq!(client.receive(), "output 3 1");
+        ringer.stop();
+        assert_eq!(client.receive(), "output 3 0");
+
+        assert_eq!(client.command("set 4 1"), "ok");
+        assert_eq!(events.recv_timeout(TIMEOUT), Ok(Event::EarpiecePickedUp));
+    }
+}
@@ -1,14 +1,35 @@
 //! Types for GPIO input/output simulated in software.
 //!
 //! These types can be used to simulate GPIO for unit tests.
+//!
+//! The environment either uses real time or a virtual time which only advances
+//! when the test calls `SimEnvironment::advance()`. In the latter case, all
+//! functions which change the simulated state wait until the threads waiting
+//! for the pins have processed the change, so that the tests are deterministic.
+
+use crate::clock::{Clock, Timer};
+
+use std::io;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+/// Real time after which a thread waiting in virtual time checks whether it
+/// has been stopped (see `InputPin::set_stop_flag()`).
+const STOP_CHECK_INTERVAL: Duration = Duration::from_millis(100);
 
-use std::sync::atomic::{AtomicU64, Ordering};
-use std::sync::{Arc, Condvar, Mutex};
-use std::time::Duration;
+/// Real time after which waiting for the waiting threads to become idle is
+/// considered a deadlock.
+const SETTLE_LIMIT: Duration = Duration::from_secs(10);
 
 pub struct SimInputPin {
     env: Arc<SimEnvironmentState>,
     index: usize,
+    /// Whether the pin has been registered as a listener (see
+    /// `SimEnvironmentState::wait_timeout()`).
+    listening: AtomicBool,
+    stop: Option<Arc<AtomicBool>>,
 }
 
 impl super::InputPin for SimInputPin {
@@ -19,14 +40,23 @@ impl super::InputPin for SimInputPin {
         get_atomic_bit(&self.env.input_state, self.index)
     }
     fn wait(&self) {
-        self.env.wait(1u64 << self.index);
+        self.env
+            .wait(1u64 << self.index, &self.listening, self.stop.as_deref());
     }
     fn wait_timeout(&self, timeout: Duration) -> bool {
-        match self.env.wait_timeout(timeout, 1u64 << self.index) {
+        match self.env.wait_timeout(
+            timeout,
+            1u64 << self.index,
+            &self.listening,
+            self.stop.as_deref(),
+        ) {
             Some(_) => true,
             None => false,
         }
     }
+    fn set_stop_flag(&mut self, stop: Arc<AtomicBool>) {
+        self.stop = Some(stop);
+    }
 
     fn create_group(pins: Vec<Box<Self>>) -> Self::Group {
         assert!(pins.len() > 0);
@@ -37,11 +67,26 @@ impl super::InputPin for SimInputPin {
             indices.push(pin.index);
             mask |= 1 << pin.index;
         }
-        SimInputPinGroup { env, indices, mask }
+        SimInputPinGroup {
+            env,
+            indices,
+            mask,
+            listening: AtomicBool::new(false),
+            stop: None,
+        }
+    }
+    fn into_output(self) -> io::Result<Self::Output> {
+        self.env.change_type(self.index, true)?;
+        Ok(SimOutputPin {
+            env: self.env.clone(),
+            index: self.index,
+        })
     }
-    fn into_output(self) -> Self::Output {
-        // TODO
-        panic!("Not yet implemented.");
+}
+
+impl Drop for SimInputPin {
+    fn drop(&mut self) {
+        self.env.unregister(&self.listening);
     }
 }
 
@@ -49,6 +94,8 @@ pub struct SimInputPinGroup {
     env: Arc<SimEnvironmentState>,
     indices: Vec<usize>,
     mask: u64,
+    listening: AtomicBool,
+    stop: Option<Arc<AtomicBool>>,
 }
 
 impl SimInputPinGroup {
@@ -77,30 +124,41 @@ impl super::InputPinGroup for SimInputPinGroup {
         result
     }
     fn wait(&self) {
-        self.env.wait(self.mask);
+        self.env
+            .wait(self.mask, &self.listening, self.stop.as_deref());
     }
     fn wait_timeout(&self, timeout: Duration) -> Option<u64> {
         self.env
-            .wait_timeout(timeout, self.mask)
+            .wait_timeout(timeout, self.mask, &self.listening, self.stop.as_deref())
             .map(|changed| self.indices_to_pin_bitmap(changed))
     }
+    fn set_stop_flag(&mut self, stop: Arc<AtomicBool>) {
+        self.stop = Some(stop);
+    }
     fn len(&self) -> usize {
         self.indices.len()
     }
 
     fn split(self) -> Vec<Box<Self::Pin>> {
-        let env = self.env.clone();
         let mut pins = Vec::new();
-        for index in self.indices {
+        for &index in self.indices.iter() {
             pins.push(Box::new(SimInputPin {
-                env: env.clone(),
+                env: self.env.clone(),
                 index,
+                listening: AtomicBool::new(false),
+                stop: None,
             }));
         }
         pins
     }
 }
 
+impl Drop for SimInputPinGroup {
+    fn drop(&mut self) {
+        self.env.unregister(&self.listening);
+    }
+}
+
 pub struct SimOutputPin {
     env: Arc<SimEnvironmentState>,
     index: usize,
@@ -110,78 +168,289 @@ impl super::OutputPin for SimOutputPin {
     type Input = SimInputPin;
 
     fn write(&self, value: bool) {
-        set_atomic_bit(&self.env.output_state, self.index, value);
+        self.env.write_output(self.index, value);
     }
-    fn into_input(self) -> Self::Input {
-        // TODO
-        panic!("Not yet implemented.");
+    fn into_input(self) -> io::Result<Self::Input> {
+        self.env.change_type(self.index, false)?;
+        Ok(SimInputPin {
+            env: self.env,
+            index: self.index,
+            listening: AtomicBool::new(false),
+            stop: None,
+        })
     }
 }
 
 struct SimEnvironmentState {
     input_state: AtomicU64,
-    input_changed: Mutex<u64>,
+    wait_state: Mutex<SimWaitState>,
     input_condvar: Condvar,
+    /// Notified whenever a thread starts waiting (see `settle()`).
+    idle_condvar: Condvar,
     output_state: AtomicU64,
     is_output: AtomicU64,
     can_change_type: AtomicU64,
+    /// Channels to which changes of the outputs are sent.
+    output_subscribers: Mutex<Vec<Sender<(usize, bool)>>>,
+    /// Start of the virtual time, or `None` if the environment uses real time.
+    virtual_start: Option<Instant>,
+}
+
+/// State protected by the mutex which is used to wait for input changes.
+struct SimWaitState {
+    /// Inputs which have changed since the last wait.
+    changed: u64,
+    /// Virtual time since `SimEnvironmentState::virtual_start`.
+    time: Duration,
+    /// Number of pins and pin groups which have been waited on.
+    listeners: usize,
+    /// Threads currently blocked in a virtual wait.
+    waiters: Vec<SimWaiter>,
+    next_waiter: u64,
+    /// Timers which have been woken up but have not yet noticed.
+    signaled: Vec<u64>,
+    next_timer: u64,
+}
+
+struct SimWaiter {
+    id: u64,
+    mask: u64,
+    /// Virtual time at which the wait times out.
+    deadline: Option<Duration>,
+    /// Timer whose `wake()` ends the wait.
+    timer: Option<u64>,
+}
+
+impl SimWaitState {
+    /// Returns true if all listeners are blocked without having a reason to
+    /// wake up.
+    fn is_idle(&self) -> bool {
+        self.waiters.len() == self.listeners
+            && self.waiters.iter().all(|waiter| {
+                (self.changed & waiter.mask) == 0
+                    && waiter.deadline.is_none_or(|deadline| deadline > self.time)
+                    && waiter
+                        .timer
+                        .is_none_or(|timer| !self.signaled.contains(&timer))
+            })
+    }
 }
 
 impl SimEnvironmentState {
-    fn wait(&self, mask: u64) {
-        let mut input_changed = self.input_changed.lock().unwrap();
+    fn wait(&self, mask: u64, listening: &AtomicBool, stop: Option<&AtomicBool>) {
+        if self.virtual_start.is_some() {
+            self.wait_virtual(None, mask, None, listening, stop);
+            return;
+        }
+        let mut input_changed = self.wait_state.lock().unwrap();
         loop {
-            if (*input_changed & mask) != 0 {
-                *input_changed &= !mask;
+            if (input_changed.changed & mask) != 0 {
+                input_changed.changed &= !mask;
                 return;
             }
             input_changed = self.input_condvar.wait(input_changed).unwrap();
         }
     }
-    fn wait_timeout(&self, timeout: Duration, mask: u64) -> Option<u64> {
-        let mut input_changed = self.input_changed.lock().unwrap();
-        if (*input_changed & mask) != 0 {
-            let changed = *input_changed & mask;
-            *input_changed &= !mask;
-            return Some(changed);
+    fn wait_timeout(
+        &self,
+        timeout: Duration,
+        mask: u64,
+        listening: &AtomicBool,
+        stop: Option<&AtomicBool>,
+    ) -> Option<u64> {
+        if self.virtual_start.is_some() {
+            return self.wait_virtual(Some(timeout), mask, None, listening, stop);
         }
-        let (mut input_changed, timeout_res) = self
-            .input_condvar
-            .wait_timeout(input_changed, timeout)
-            .unwrap();
-        if timeout_res.timed_out() {
-            return None;
+        // Other pins can wake up the condition variable, so the wait has to be
+        // repeated until the deadline.
+        let deadline = Instant::now() + timeout;
+        let mut input_changed = self.wait_state.lock().unwrap();
+        loop {
+            if (input_changed.changed & mask) != 0 {
+                let changed = input_changed.changed & mask;
+                input_changed.changed &= !mask;
+                return Some(changed);
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return None;
+            }
+            input_changed = self
+                .input_condvar
+                .wait_timeout(input_changed, deadline - now)
+                .unwrap()
+                .0;
         }
+    }
 
-        if (*input_changed & mask) != 0 {
-            let changed = *input_changed & mask;
-            *input_changed &= !mask;
-            return Some(changed);
+    /// Waits for an input change, for the timer to be woken up, until the
+    /// virtual timeout expires or until the stop flag is set.
+    ///
+    /// The real time only matters for checking the stop flag, which is not
+    /// protected by the mutex.
+    fn wait_virtual(
+        &self,
+        timeout: Option<Duration>,
+        mask: u64,
+        timer: Option<u64>,
+        listening: &AtomicBool,
+        stop: Option<&AtomicBool>,
+    ) -> Option<u64> {
+        let mut state = self.wait_state.lock().unwrap();
+        if !listening.swap(true, Ordering::SeqCst) {
+            state.listeners += 1;
         }
+        let id = state.next_waiter;
+        state.next_waiter += 1;
+        let deadline = timeout.map(|timeout| state.time + timeout);
+        state.waiters.push(SimWaiter {
+            id,
+            mask,
+            deadline,
+            timer,
+        });
+        let result = loop {
+            if (state.changed & mask) != 0 {
+                let changed = state.changed & mask;
+                state.changed &= !mask;
+                break Some(changed);
+            }
+            if let Some(timer) = timer {
+                if state.signaled.contains(&timer) {
+                    state.signaled.retain(|&signaled| signaled != timer);
+                    break Some(0);
+                }
+            }
+            if deadline.is_some_and(|deadline| state.time >= deadline) {
+                break None;
+            }
+            if stop.is_some_and(|stop| stop.load(Ordering::SeqCst)) {
+                break None;
+            }
+            self.idle_condvar.notify_all();
+            state = self
+                .input_condvar
+                .wait_timeout(state, STOP_CHECK_INTERVAL)
+                .unwrap()
+                .0;
+        };
+        state.waiters.retain(|waiter| waiter.id != id);
+        result
+    }
+
+    /// Sets an output and notifies the subscribers if its value changed.
+    fn write_output(&self, index: usize, value: bool) {
+        // The lock also ensures that the changes are sent in order.
+        let mut subscribers = self.output_subscribers.lock().unwrap();
+        let mask = 1u64 << index;
+        let previous = if value {
+            self.output_state.fetch_or(mask, Ordering::SeqCst)
+        } else {
+            self.output_state.fetch_and(!mask, Ordering::SeqCst)
+        };
+        if (previous & mask != 0) != value {
+            subscribers.retain(|subscriber| subscriber.send((index, value)).is_ok());
+        }
+    }
+
+    /// Changes the direction of a pin if it was created with
+    /// `can_change_type`.
+    fn change_type(&self, index: usize, output: bool) -> io::Result<()> {
+        if !get_atomic_bit(&self.can_change_type, index) {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                format!("the direction of pin {} cannot be changed", index),
+            ));
+        }
+        set_atomic_bit(&self.is_output, index, output);
+        set_atomic_bit(&self.output_state, index, false);
+        if !output {
+            // Changes while the pin was an output must not wake up waits.
+            self.wait_state.lock().unwrap().changed &= !(1 << index);
+        }
+        Ok(())
+    }
+
+    /// Removes a pin or pin group from the listeners when it is dropped.
+    fn unregister(&self, listening: &AtomicBool) {
+        if listening.load(Ordering::SeqCst) {
+            self.wait_state.lock().unwrap().listeners -= 1;
+            self.idle_condvar.notify_all();
+        }
+    }
 
-        // TODO: We do not repeat the call. Therefore, this function causes
-        // spurious wakeups. Repeating the call requires more complex timeout
-        // calculation.
-        return None;
+    /// Waits until all threads waiting for the pins have processed all input
+    /// changes and expired timeouts.
+    fn settle<'a>(&self, mut state: MutexGuard<'a, SimWaitState>) -> MutexGuard<'a, SimWaitState> {
+        let limit = Instant::now() + SETTLE_LIMIT;
+        while !state.is_idle() {
+            let now = Instant::now();
+            assert!(
+                now < limit,
+                "threads waiting for simulated pins did not settle"
+            );
+            state = self
+                .idle_condvar
+                .wait_timeout(state, limit - now)
+                .unwrap()
+                .0;
+        }
+        state
+    }
+
+    fn now(&self) -> Instant {
+        match self.virtual_start {
+            Some(start) => start + self.wait_state.lock().unwrap().time,
+            None => Instant::now(),
+        }
     }
 }
 
 /// Simulated device environment which can be used to generate input and output
 /// pins.
+///
+/// Clones refer to the same simulated pins.
+#[derive(Clone)]
 pub struct SimEnvironment {
     state: Arc<SimEnvironmentState>,
 }
 
 impl SimEnvironment {
     pub fn new() -> Self {
+        Self::create(None)
+    }
+
+    /// Creates an environment whose time only advances when `advance()` is
+    /// called.
+    ///
+    /// The threads using the pins have to use the clock returned by `clock()`.
+    /// Every input pin or pin group which has been waited on is expected to be
+    /// waited on again after each change, as changes are only complete once
+    /// all of them are waiting again.
+    pub fn with_virtual_time() -> Self {
+        Self::create(Some(Instant::now()))
+    }
+
+    fn create(virtual_start: Option<Instant>) -> Self {
         SimEnvironment {
             state: Arc::new(SimEnvironmentState {
                 input_state: AtomicU64::new(0),
-                input_changed: Mutex::new(0),
+                wait_state: Mutex::new(SimWaitState {
+                    changed: 0,
+                    time: Duration::from_secs(0),
+                    listeners: 0,
+                    waiters: Vec::new(),
+                    next_waiter: 0,
+                    signaled: Vec::new(),
+                    next_timer: 0,
+                }),
                 input_condvar: Condvar::new(),
+                idle_condvar: Condvar::new(),
                 output_state: AtomicU64::new(0),
                 is_output: AtomicU64::new(0),
                 can_change_type: AtomicU64::new(0),
+                output_subscribers: Mutex::new(Vec::new()),
+                virtual_start,
             }),
         }
     }
@@ -191,10 +460,12 @@ impl SimEnvironment {
         set_atomic_bit(&self.state.is_output, index, false);
         set_atomic_bit(&self.state.input_state, index, false);
         set_atomic_bit(&self.state.output_state, index, false);
-        *self.state.input_changed.lock().unwrap() &= !(1 << index);
+        self.state.wait_state.lock().unwrap().changed &= !(1 << index);
         SimInputPin {
             env: self.state.clone(),
             index,
+            listening: AtomicBool::new(false),
+            stop: None,
         }
     }
     pub fn create_output_pin(&self, index: usize, can_change_type: bool) -> SimOutputPin {
@@ -202,22 +473,204 @@ impl SimEnvironment {
         set_atomic_bit(&self.state.is_output, index, true);
         set_atomic_bit(&self.state.input_state, index, false);
         set_atomic_bit(&self.state.output_state, index, false);
-        *self.state.input_changed.lock().unwrap() &= !(1 << index);
+        self.state.wait_state.lock().unwrap().changed &= !(1 << index);
         SimOutputPin {
             env: self.state.clone(),
             index,
         }
     }
 
+    /// Sets the value of an input pin.
+    ///
+    /// With virtual time, the function returns once the waiting threads have
+    /// processed the change.
     pub fn write_input(&self, index: usize, value: bool) {
         set_atomic_bit(&self.state.input_state, index, value);
-        let mut input_changed = self.state.input_changed.lock().unwrap();
-        *input_changed |= 1 << index;
+        let mut input_changed = self.state.wait_state.lock().unwrap();
+        input_changed.changed |= 1 << index;
         self.state.input_condvar.notify_all();
+        if self.state.virtual_start.is_some() {
+            drop(self.state.settle(input_changed));
+        }
     }
     pub fn read_output(&self, index: usize) -> bool {
         get_atomic_bit(&self.state.output_state, index)
     }
+    /// Returns whether the pin is currently configured as an output.
+    pub fn is_output(&self, index: usize) -> bool {
+        get_atomic_bit(&self.state.is_output, index)
+    }
+    /// Returns a channel which receives the index and the new value whenever
+    /// an output pin changes its value.
+    pub fn subscribe_outputs(&self) -> Receiver<(usize, bool)> {
+        let (sender, receiver) = channel();
+        self.state.output_subscribers.lock().unwrap().push(sender);
+        receiver
+    }
+    /// Returns whether the environment was created with `with_virtual_time()`.
+    pub fn has_virtual_time(&self) -> bool {
+        self.state.virtual_start.is_some()
+    }
+
+    /// Returns a clock which returns the time of the environment.
+    pub fn clock(&self) -> SimClock {
+        SimClock {
+            state: self.state.clone(),
+        }
+    }
+
+    /// Advances the virtual time.
+    ///
+    /// Timeouts which expire in between wake up the waiting threads at exactly
+    /// their deadline, and the function returns once the threads are waiting
+    /// again.
+    pub fn advance(&self, duration: Duration) {
+        assert!(
+            self.state.virtual_start.is_some(),
+            "the environment does not use virtual time"
+        );
+        let mut state = self.state.wait_state.lock().unwrap();
+        let target = state.time + duration;
+        loop {
+            state = self.state.settle(state);
+            let next = state
+                .waiters
+                .iter()
+                .filter_map(|waiter| waiter.deadline)
+                .filter(|&deadline| deadline <= target)
+                .min();
+            match next {
+                Some(next) => {
+                    state.time = next;
+                    self.state.input_condvar.notify_all();
+                }
+                None => {
+                    state.time = target;
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Waits until all waiting threads have processed the changes so far.
+    ///
+    /// Changes of the inputs already wait for the threads, but tests have to
+    /// call this function after waking up a timer (see `SimTimer`), e.g., via
+    /// a peripheral's methods.
+    pub fn settle(&self) {
+        assert!(
+            self.state.virtual_start.is_some(),
+            "the environment does not use virtual time"
+        );
+        drop(self.state.settle(self.state.wait_state.lock().unwrap()));
+    }
+
+    /// Waits until the specified number of pins or pin groups are waited on
+    /// and all waiting threads are idle.
+    ///
+    /// Tests use this function to make sure that the threads they have started
+    /// are ready before changing the inputs.
+    pub fn wait_for_listeners(&self, count: usize) {
+        let mut state = self.state.wait_state.lock().unwrap();
+        let limit = Instant::now() + SETTLE_LIMIT;
+        while state.listeners < count {
+            let now = Instant::now();
+            assert!(now < limit, "pins are not waited on");
+            state = self
+                .state
+                .idle_condvar
+                .wait_timeout(state, limit - now)
+                .unwrap()
+                .0;
+        }
+        drop(self.state.settle(state));
+    }
+}
+
+/// Clock which returns the (possibly virtual) time of a `SimEnvironment`.
+pub struct SimClock {
+    state: Arc<SimEnvironmentState>,
+}
+
+impl Clock for SimClock {
+    type Timer = SimTimer;
+
+    fn now(&self) -> Instant {
+        self.state.now()
+    }
+    fn timer(&self) -> SimTimer {
+        let mut state = self.state.wait_state.lock().unwrap();
+        let id = state.next_timer;
+        state.next_timer += 1;
+        SimTimer {
+            env: self.state.clone(),
+            id,
+            listening: AtomicBool::new(false),
+        }
+    }
+}
+
+/// Timer of a `SimClock`.
+///
+/// With virtual time, a thread waiting for the timer counts as a listener just
+/// like a thread waiting for a pin, so `advance()` wakes it up at its deadline.
+pub struct SimTimer {
+    env: Arc<SimEnvironmentState>,
+    id: u64,
+    listening: AtomicBool,
+}
+
+impl Timer for SimTimer {
+    fn wait_until(&self, deadline: Option<Instant>) {
+        if self.env.virtual_start.is_some() {
+            let timeout =
+                deadline.map(|deadline| deadline.saturating_duration_since(self.env.now()));
+            self.env
+                .wait_virtual(timeout, 0, Some(self.id), &self.listening, None);
+            return;
+        }
+        let mut state = self.env.wait_state.lock().unwrap();
+        loop {
+            if state.signaled.contains(&self.id) {
+                state.signaled.retain(|&signaled| signaled != self.id);
+                return;
+            }
+            match deadline {
+                Some(deadline) => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        return;
+                    }
+                    state = self
+                        .env
+                        .input_condvar
+                        .wait_timeout(state, deadline - now)
+                        .unwrap()
+                        .0;
+                }
+                None => state = self.env.input_condvar.wait(state).unwrap(),
+            }
+        }
+    }
+    fn wake(&self) {
+        // Unlike `write_input()`, this function does not wait for the woken
+        // thread, as it is also called when the thread is stopped.
+        let mut state = self.env.wait_state.lock().unwrap();
+        if !state.signaled.contains(&self.id) {
+            state.signaled.push(self.id);
+        }
+        self.env.input_condvar.notify_all();
+    }
+}
+
+impl Drop for SimTimer {
+    fn drop(&mut self) {
+        let id = self.id;
+        let mut state = self.env.wait_state.lock().unwrap();
+        state.signaled.retain(|&signaled| signaled != id);
+        drop(state);
+        self.env.unregister(&self.listening);
+    }
 }
 
 fn set_atomic_bit(a: &AtomicU64, bit: usize, value: bool) {
@@ -232,3 +685,168 @@ fn set_atomic_bit(a: &AtomicU64, bit: usize, value: bool) {
 fn get_atomic_bit(a: &AtomicU64, bit: usize) -> bool {
     (a.load(Ordering::SeqCst) & (1 << bit)) != 0
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gpio::{InputPin, InputPinGroup, OutputPin};
+
+    use std::thread::{self, sleep, JoinHandle};
+
+    /// Toggles an input pin every millisecond until the returned flag is set.
+    fn toggle(env: &SimEnvironment, index: usize) -> (Arc<AtomicBool>, JoinHandle<()>) {
+        let stop = Arc::new(AtomicBool::new(false));
+        let env = env.clone();
+        let stop_copy = stop.clone();
+        let thread = thread::spawn(move || {
+            let mut value = false;
+            while !stop_copy.load(Ordering::SeqCst) {
+                value = !value;
+                env.write_input(index, value);
+                sleep(Duration::from_millis(1));
+            }
+        });
+        (stop, thread)
+    }
+
+    #[test]
+    fn test_wait_timeout() {
+        let env = SimEnvironment::new();
+        let pin = env.create_input_pin(0, false);
+        let _other = env.create_input_pin(1, false);
+        let writers = vec![toggle(&env, 1), toggle(&env, 1)];
+
+        // Changes of other pins do not end the wait.
+        let start = Instant::now();
+        assert!(!pin.wait_timeout(Duration::from_millis(100)));
+        assert!(start.elapsed() >= Duration::from_millis(100));
+
+        // A change of the pin itself does.
+        let env_copy = env.clone();
+        let writer = thread::spawn(move || {
+            sleep(Duration::from_millis(50));
+            env_copy.write_input(0, true);
+        });
+        let start = Instant::now();
+        assert!(pin.wait_timeout(Duration::from_secs(10)));
+        assert!(start.elapsed() < Duration::from_secs(10));
+        assert!(pin.read());
+        writer.join().unwrap();
+
+        for (stop, thread) in writers {
+            stop.store(true, Ordering::SeqCst);
+            thread.join().unwrap();
+        }
+    }
+
+    #[test]
+    fn test_group_wait_timeout() {
+        let env = SimEnvironment::new();
+        let group = SimInputPin::create_group(vec![
+            Box::new(env.create_input_pin(2, false)),
+            Box::new(env.create_input_pin(3, false)),
+        ]);
+        let _other = env.create_input_pin(1, false);
+        let (stop, thread) = toggle(&env, 1);
+
+        let start = Instant::now();
+        assert_eq!(group.wait_timeout(Duration::from_millis(100)), None);
+        assert!(start.elapsed() >= Duration::from_millis(100));
+
+        // The result contains the changed pins of the group.
+        env.write_input(3, true);
+        assert_eq!(group.wait_timeout(Duration::from_millis(100)), Some(0b10));
+        assert_eq!(group.read(), 0b10);
+
+        stop.store(true, Ordering::SeqCst);
+        thread.join().unwrap();
+    }
+
+    #[test]
+    fn test_virtual_wait_timeout() {
+        let env = SimEnvironment::with_virtual_time();
+        let mut pin = env.create_input_pin(0, false);
+        let stop = Arc::new(AtomicBool::new(false));
+        pin.set_stop_flag(stop.clone());
+        let (sender, results) = channel();
+        let thread = thread::spawn(move || {
+            sender
+                .send(pin.wait_timeout(Duration::from_secs(1)))
+                .unwrap();
+            sender
+                .send(pin.wait_timeout(Duration::from_secs(1)))
+                .unwrap();
+        });
+        env.wait_for_listeners(1);
+
+        // The timeout only expires when the virtual time is advanced, no
+        // matter how much real time passes.
+        sleep(STOP_CHECK_INTERVAL * 3);
+        assert!(results.try_recv().is_err());
+        env.advance(Duration::from_millis(999));
+        assert!(results.try_recv().is_err());
+        env.advance(Duration::from_millis(1));
+        assert!(!results.recv().unwrap());
+
+        // Setting the stop flag ends the wait.
+        sleep(STOP_CHECK_INTERVAL * 3);
+        assert!(results.try_recv().is_err());
+        stop.store(true, Ordering::SeqCst);
+        assert!(!results.recv().unwrap());
+        thread.join().unwrap();
+    }
+
+    #[test]
+    fn test_change_type() {
+        let env = SimEnvironment::new();
+
+        // A shared line which senses the bell circuit and drives it.
+        let pin = env.create_input_pin(0, true);
+        env.write_input(0, true);
+        assert!(!env.is_output(0));
+        let pin = pin.into_output().unwrap();
+        assert!(env.is_output(0));
+        assert!(!env.read_output(0));
+        pin.write(true);
+        assert!(env.read_output(0));
+
+        let pin = pin.into_input().unwrap();
+        assert!(!env.is_output(0));
+        assert!(!env.read_output(0));
+        assert!(pin.read());
+        // The change before the conversion is not reported.
+        assert!(!pin.wait_timeout(Duration::from_millis(0)));
+
+        let fixed_input = env.create_input_pin(1, false);
+        let error = fixed_input.into_output().err().unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::PermissionDenied);
+        assert!(!env.is_output(1));
+        let fixed_output = env.create_output_pin(2, false);
+        assert!(fixed_output.into_input().is_err());
+        assert!(env.is_output(2));
+    }
+
+    #[test]
+    fn test_subscribe_outputs() {
+        let env = SimEnvironment::new();
+        let bell = env.create_output_pin(3, false);
+        let light = env.create_output_pin(5, false);
+        let changes = env.subscribe_outputs();
+
+        // Only changes of the value are reported.
+        bell.write(true);
+        bell.write(true);
+        light.write(false);
+        light.write(true);
+        bell.write(false);
+        assert_eq!(
+            changes.try_iter().collect::<Vec<_>>(),
+            vec![(3, true), (5, true), (3, false)]
+        );
+
+        // Dropped subscribers are removed.
+        drop(changes);
+        bell.write(true);
+        assert!(env.read_output(3));
+    }
+}
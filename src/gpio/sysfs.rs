@@ -1,16 +1,49 @@
 //! Types for GPIO input/output using the Linux sysfs interface.
+//!
+//! All types default to the standard sysfs location at `/sys/class/gpio`, but
+//! the `*_at` constructors accept a different root directory so that the
+//! implementation can be tested against a fake directory tree.
 
-use std::io;
-use std::time::Duration;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
 
-pub struct SysfsInputPin {}
+/// Default location of the sysfs GPIO interface.
+pub const SYSFS_GPIO_ROOT: &str = "/sys/class/gpio";
+
+/// Maximum time to wait for the kernel (and udev) to make the files of a newly
+/// exported pin available.
+const EXPORT_TIMEOUT: Duration = Duration::from_millis(1000);
+
+pub struct SysfsInputPin {
+    root: PathBuf,
+    pin: usize,
+    value: File,
+}
 
 impl SysfsInputPin {
     pub fn open(pin: usize) -> Result<Self, io::Error> {
-        // TODO
-        Ok(Self {
-            // TODO
-        })
+        Self::open_at(Path::new(SYSFS_GPIO_ROOT), pin)
+    }
+
+    /// Opens a pin below the specified sysfs GPIO directory.
+    pub fn open_at(root: &Path, pin: usize) -> Result<Self, io::Error> {
+        export(root, pin)?;
+        Self::configure(root.to_path_buf(), pin)
+    }
+
+    /// Configures an exported pin as an input.
+    fn configure(root: PathBuf, pin: usize) -> Result<Self, io::Error> {
+        write_attribute(&root, pin, "direction", "in")?;
+        write_attribute(&root, pin, "edge", "both")?;
+        let value = File::open(pin_path(&root, pin).join("value"))?;
+        // The value has to be read once, otherwise the first poll() returns
+        // immediately.
+        read_value(&value)?;
+        Ok(Self { root, pin, value })
     }
 }
 
@@ -19,76 +52,353 @@ impl super::InputPin for SysfsInputPin {
     type Group = SysfsInputPinGroup;
 
     fn read(&self) -> bool {
-        // TODO
-        panic!("Not yet implemented.");
+        read_value(&self.value).expect("could not read GPIO value")
     }
     fn wait(&self) {
-        // TODO
-        panic!("Not yet implemented.");
+        poll_values(&[&self.value], None).expect("could not wait for GPIO interrupt");
     }
     fn wait_timeout(&self, timeout: Duration) -> bool {
-        // TODO
-        panic!("Not yet implemented.");
+        let changed =
+            poll_values(&[&self.value], Some(timeout)).expect("could not wait for GPIO interrupt");
+        changed != 0
     }
 
-    fn create_group(_pins: Vec<Box<Self>>) -> Self::Group {
-        // TODO
-        panic!("Not yet implemented.");
+    fn create_group(pins: Vec<Box<Self>>) -> Self::Group {
+        assert!(!pins.is_empty() && pins.len() <= 64);
+        SysfsInputPinGroup {
+            pins: pins.into_iter().map(|pin| *pin).collect(),
+        }
     }
-    fn into_output(self) -> Self::Output {
-        // TODO
-        panic!("Not yet implemented.");
+    fn into_output(self) -> io::Result<Self::Output> {
+        // The kernel refuses to change the direction while the interrupt is
+        // enabled.
+        write_attribute(&self.root, self.pin, "edge", "none")?;
+        SysfsOutputPin::configure(self.root, self.pin)
     }
 }
 
-pub struct SysfsInputPinGroup {}
+pub struct SysfsInputPinGroup {
+    pins: Vec<SysfsInputPin>,
+}
+
+impl SysfsInputPinGroup {
+    fn files(&self) -> Vec<&File> {
+        self.pins.iter().map(|pin| &pin.value).collect()
+    }
+}
 
 impl super::InputPinGroup for SysfsInputPinGroup {
     type Pin = SysfsInputPin;
 
     fn read(&self) -> u64 {
-        // TODO
-        panic!("Not yet implemented.");
+        let mut result = 0;
+        for (i, pin) in self.pins.iter().enumerate() {
+            if read_value(&pin.value).expect("could not read GPIO value") {
+                result |= 1 << i;
+            }
+        }
+        result
     }
     fn wait(&self) {
-        // TODO
-        panic!("Not yet implemented.");
+        poll_values(&self.files(), None).expect("could not wait for GPIO interrupt");
     }
     fn wait_timeout(&self, timeout: Duration) -> Option<u64> {
-        // TODO
-        panic!("Not yet implemented.");
+        let changed =
+            poll_values(&self.files(), Some(timeout)).expect("could not wait for GPIO interrupt");
+        if changed != 0 {
+            Some(changed)
+        } else {
+            None
+        }
     }
     fn len(&self) -> usize {
-        // TODO
-        panic!("Not yet implemented.");
+        self.pins.len()
     }
 
     fn split(self) -> Vec<Box<Self::Pin>> {
-        // TODO
-        panic!("Not yet implemented.");
+        self.pins.into_iter().map(Box::new).collect()
     }
 }
 
-pub struct SysfsOutputPin {}
+pub struct SysfsOutputPin {
+    root: PathBuf,
+    pin: usize,
+    value: File,
+}
 
 impl SysfsOutputPin {
     pub fn open(pin: usize) -> Result<Self, io::Error> {
-        // TODO
-        Ok(Self {
-            // TODO
-        })
+        Self::open_at(Path::new(SYSFS_GPIO_ROOT), pin)
+    }
+
+    /// Opens a pin below the specified sysfs GPIO directory.
+    ///
+    /// The pin is initially set to `false`.
+    pub fn open_at(root: &Path, pin: usize) -> Result<Self, io::Error> {
+        export(root, pin)?;
+        Self::configure(root.to_path_buf(), pin)
+    }
+
+    /// Configures an exported pin as an output.
+    fn configure(root: PathBuf, pin: usize) -> Result<Self, io::Error> {
+        // "low" configures the pin as an output without glitches.
+        write_attribute(&root, pin, "direction", "low")?;
+        let value = OpenOptions::new()
+            .write(true)
+            .open(pin_path(&root, pin).join("value"))?;
+        Ok(Self { root, pin, value })
     }
 }
 
 impl super::OutputPin for SysfsOutputPin {
     type Input = SysfsInputPin;
 
-    fn write(&self, _value: bool) {
-        // TODO
-        panic!("Not yet implemented.");
+    fn write(&self, value: bool) {
+        write_value(&self.value, value).expect("could not write GPIO value");
+    }
+    fn into_input(self) -> io::Result<Self::Input> {
+        SysfsInputPin::configure(self.root, self.pin)
+    }
+}
+
+fn pin_path(root: &Path, pin: usize) -> PathBuf {
+    root.join(format!("gpio{}", pin))
+}
+
+/// Exports the pin if it has not been exported yet and waits until its
+/// attributes can be written.
+fn export(root: &Path, pin: usize) -> Result<(), io::Error> {
+    let direction = pin_path(root, pin).join("direction");
+    if direction.exists() {
+        return Ok(());
+    }
+    let mut export = OpenOptions::new().write(true).open(root.join("export"))?;
+    export.write_all(pin.to_string().as_bytes())?;
+
+    // udev might need some time to create the files and to fix their
+    // permissions.
+    let start = Instant::now();
+    loop {
+        match OpenOptions::new().write(true).open(&direction) {
+            Ok(_) => return Ok(()),
+            Err(e) => {
+                if start.elapsed() > EXPORT_TIMEOUT {
+                    return Err(e);
+                }
+            }
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+}
+
+fn write_attribute(root: &Path, pin: usize, name: &str, value: &str) -> Result<(), io::Error> {
+    let mut file = OpenOptions::new()
+        .write(true)
+        .truncate(true)
+        .open(pin_path(root, pin).join(name))?;
+    file.write_all(value.as_bytes())
+}
+
+fn read_value(mut file: &File) -> Result<bool, io::Error> {
+    let mut buffer = [0u8; 1];
+    file.seek(SeekFrom::Start(0))?;
+    file.read_exact(&mut buffer)?;
+    match buffer[0] {
+        b'0' => Ok(false),
+        b'1' => Ok(true),
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "invalid GPIO value",
+        )),
+    }
+}
+
+fn write_value(mut file: &File, value: bool) -> Result<(), io::Error> {
+    file.seek(SeekFrom::Start(0))?;
+    file.write_all(if value { b"1" } else { b"0" })
+}
+
+/// Waits for an interrupt on any of the value files.
+///
+/// The function returns a bitmap of the files which signalled an interrupt, or
+/// 0 if the timeout expired.
+fn poll_values(files: &[&File], timeout: Option<Duration>) -> Result<u64, io::Error> {
+    let fds: Vec<_> = files.iter().map(|file| file.as_raw_fd()).collect();
+    let changed = super::poll::poll(&fds, libc::POLLPRI | libc::POLLERR, timeout)?;
+    for (i, file) in files.iter().enumerate() {
+        if (changed & (1 << i)) != 0 {
+            // Reading the value acknowledges the interrupt.
+            read_value(file)?;
+        }
+    }
+    Ok(changed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{InputPin, InputPinGroup, OutputPin};
+    use super::*;
+
+    use std::fs;
+    use std::process;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    static FAKE_SYSFS_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+    /// Temporary directory with the same layout as `/sys/class/gpio`.
+    struct FakeSysfs {
+        root: PathBuf,
+    }
+
+    impl FakeSysfs {
+        fn new() -> Self {
+            let root = std::env::temp_dir().join(format!(
+                "fernsprechapparat-sysfs-{}-{}",
+                process::id(),
+                FAKE_SYSFS_COUNT.fetch_add(1, Ordering::SeqCst)
+            ));
+            fs::create_dir_all(&root).unwrap();
+            fs::write(root.join("export"), "").unwrap();
+            fs::write(root.join("unexport"), "").unwrap();
+            FakeSysfs { root }
+        }
+
+        /// Creates the files which the kernel would create when exporting the
+        /// pin.
+        fn create_pin(&self, pin: usize) {
+            let path = pin_path(&self.root, pin);
+            fs::create_dir_all(&path).unwrap();
+            fs::write(path.join("direction"), "in").unwrap();
+            fs::write(path.join("edge"), "none").unwrap();
+            fs::write(path.join("value"), "0\n").unwrap();
+        }
+
+        fn read(&self, pin: usize, name: &str) -> String {
+            fs::read_to_string(pin_path(&self.root, pin).join(name)).unwrap()
+        }
+
+        fn write(&self, pin: usize, name: &str, value: &str) {
+            fs::write(pin_path(&self.root, pin).join(name), value).unwrap();
+        }
+    }
+
+    impl Drop for FakeSysfs {
+        fn drop(&mut self) {
+            fs::remove_dir_all(&self.root).ok();
+        }
     }
-    fn into_input(self) -> Self::Input {
-        // TODO
-        panic!("Not yet implemented.");
+
+    #[test]
+    fn test_input_pin() {
+        let sysfs = FakeSysfs::new();
+        sysfs.create_pin(3);
+
+        let pin = SysfsInputPin::open_at(&sysfs.root, 3).unwrap();
+        assert_eq!(sysfs.read(3, "direction"), "in");
+        assert_eq!(sysfs.read(3, "edge"), "both");
+
+        assert!(!pin.read());
+        sysfs.write(3, "value", "1\n");
+        assert!(pin.read());
+
+        // Regular files never signal an interrupt.
+        let start = Instant::now();
+        assert!(!pin.wait_timeout(Duration::from_millis(20)));
+        assert!(start.elapsed() >= Duration::from_millis(20));
+    }
+
+    #[test]
+    fn test_export() {
+        let sysfs = FakeSysfs::new();
+
+        // Simulate the kernel creating the pin files some time after the pin
+        // has been exported.
+        let root = sysfs.root.clone();
+        let creator = thread::spawn(move || {
+            let start = Instant::now();
+            while fs::read_to_string(root.join("export")).unwrap() != "7" {
+                assert!(start.elapsed() < EXPORT_TIMEOUT);
+                thread::sleep(Duration::from_millis(1));
+            }
+            thread::sleep(Duration::from_millis(20));
+            let path = pin_path(&root, 7);
+            fs::create_dir_all(&path).unwrap();
+            fs::write(path.join("direction"), "in").unwrap();
+            fs::write(path.join("value"), "0\n").unwrap();
+        });
+
+        let pin = SysfsOutputPin::open_at(&sysfs.root, 7).unwrap();
+        creator.join().unwrap();
+        assert_eq!(sysfs.read(7, "direction"), "low");
+        pin.write(true);
+        assert_eq!(sysfs.read(7, "value"), "1\n");
+    }
+
+    #[test]
+    fn test_export_timeout() {
+        let sysfs = FakeSysfs::new();
+        assert!(SysfsInputPin::open_at(&sysfs.root, 2).is_err());
+    }
+
+    #[test]
+    fn test_output_pin() {
+        let sysfs = FakeSysfs::new();
+        sysfs.create_pin(4);
+
+        let pin = SysfsOutputPin::open_at(&sysfs.root, 4).unwrap();
+        assert_eq!(sysfs.read(4, "direction"), "low");
+        pin.write(true);
+        assert_eq!(&sysfs.read(4, "value")[..1], "1");
+        pin.write(false);
+        assert_eq!(&sysfs.read(4, "value")[..1], "0");
+    }
+
+    #[test]
+    fn test_change_direction() {
+        let sysfs = FakeSysfs::new();
+        sysfs.create_pin(5);
+
+        let pin = SysfsInputPin::open_at(&sysfs.root, 5).unwrap();
+        let pin = pin.into_output().unwrap();
+        assert_eq!(sysfs.read(5, "direction"), "low");
+        assert_eq!(sysfs.read(5, "edge"), "none");
+        pin.write(true);
+        assert_eq!(&sysfs.read(5, "value")[..1], "1");
+
+        let pin = pin.into_input().unwrap();
+        assert_eq!(sysfs.read(5, "direction"), "in");
+        assert_eq!(sysfs.read(5, "edge"), "both");
+        assert!(pin.read());
+
+        // Pins with a fixed direction do not have a direction attribute.
+        fs::remove_file(pin_path(&sysfs.root, 5).join("direction")).unwrap();
+        assert!(pin.into_output().is_err());
+    }
+
+    #[test]
+    fn test_group() {
+        let sysfs = FakeSysfs::new();
+        sysfs.create_pin(1);
+        sysfs.create_pin(2);
+
+        let pins = vec![
+            Box::new(SysfsInputPin::open_at(&sysfs.root, 1).unwrap()),
+            Box::new(SysfsInputPin::open_at(&sysfs.root, 2).unwrap()),
+        ];
+        let group = SysfsInputPin::create_group(pins);
+        assert_eq!(group.len(), 2);
+        assert_eq!(group.read(), 0);
+        sysfs.write(2, "value", "1\n");
+        assert_eq!(group.read(), 2);
+        sysfs.write(1, "value", "1\n");
+        assert_eq!(group.read(), 3);
+
+        assert_eq!(group.wait_timeout(Duration::from_millis(10)), None);
+
+        let pins = group.split();
+        assert_eq!(pins.len(), 2);
+        sysfs.write(1, "value", "0\n");
+        assert!(!pins[0].read());
+        assert!(pins[1].read());
     }
 }
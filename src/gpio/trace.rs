@@ -0,0 +1,529 @@
+//! Recording of input pin traces and their replay in a simulated environment.
+//!
+//! A trace is a text file with one edge per line, consisting of the time in
+//! microseconds since the start of the recording, the name of the pin, and the
+//! new value (`0` or `1`):
+//!
+//! ```text
+//! # fernsprechapparat GPIO trace
+//! 0 nsa 0
+//! 0 nsi 1
+//! 1534201 nsa 1
+//! ```
+//!
+//! Edges of pins which are waited on by different threads may be written
+//! slightly out of order, so they are sorted when the trace is loaded. Traces
+//! of real phones can be attached to bug reports and replayed in tests with
+//! `Trace::play()`.
+
+use super::sim::SimEnvironment;
+use super::{Edge, InputPin, InputPinGroup};
+
+use std::fmt::Write as _;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::sync::atomic::AtomicBool;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const HEADER: &str = "# fernsprechapparat GPIO trace";
+
+/// File to which the edges of one or more input pins are written.
+pub struct TraceRecorder {
+    /// Monotonic time at which the recording was started.
+    start: Duration,
+    file: Mutex<BufWriter<File>>,
+}
+
+impl TraceRecorder {
+    /// Creates the trace file, replacing any existing file.
+    pub fn create(path: &Path) -> io::Result<Arc<Self>> {
+        let mut file = BufWriter::new(File::create(path)?);
+        writeln!(file, "{}", HEADER)?;
+        file.flush()?;
+        Ok(Arc::new(Self {
+            start: monotonic_now(),
+            file: Mutex::new(file),
+        }))
+    }
+
+    /// Writes an edge to the trace. Edges without a timestamp are recorded
+    /// with the current time.
+    fn record(&self, pin: &str, value: bool, timestamp: Option<Duration>) {
+        let time = timestamp
+            .unwrap_or_else(monotonic_now)
+            .saturating_sub(self.start)
+            .as_micros();
+        let mut file = self.file.lock().unwrap();
+        // The trace is flushed after every edge so that it is complete even if
+        // the application is killed.
+        let result = writeln!(file, "{} {} {}", time, pin, value as u8).and_then(|_| file.flush());
+        if let Err(e) = result {
+            println!("Could not write GPIO trace: {}", e);
+        }
+    }
+
+    fn record_edges<'a>(&self, edges: &[Edge], name: impl Fn(usize) -> &'a str) {
+        for edge in edges {
+            self.record(name(edge.pin), edge.value, edge.timestamp);
+        }
+    }
+}
+
+/// Returns the time of the monotonic clock used for the timestamps of edges.
+fn monotonic_now() -> Duration {
+    let mut time = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    unsafe {
+        libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut time);
+    }
+    Duration::new(time.tv_sec as u64, time.tv_nsec as u32)
+}
+
+/// Input pin which records the changes of its value in a trace.
+///
+/// The initial value is recorded when the pin is created, and all edges
+/// reported to `wait()` and `wait_timeout()` afterwards. The backend
+/// determines how exact the trace is: The cdev backend reports every edge
+/// with the timestamp of the kernel, whereas the other backends only report
+/// the value after the wait at the time at which the waiting thread woke up.
+///
+/// Without a recorder, the pin only passes the calls through, so that the
+/// pins can be wrapped regardless of whether tracing is enabled.
+pub struct TraceInputPin<Pin: InputPin> {
+    pin: Pin,
+    recorder: Option<Arc<TraceRecorder>>,
+    name: String,
+}
+
+impl<Pin: InputPin> TraceInputPin<Pin> {
+    pub fn new(pin: Pin, recorder: Option<Arc<TraceRecorder>>, name: &str) -> Self {
+        if let Some(recorder) = &recorder {
+            recorder.record(name, pin.read(), None);
+        }
+        Self {
+            pin,
+            recorder,
+            name: name.to_string(),
+        }
+    }
+}
+
+impl<Pin: InputPin> InputPin for TraceInputPin<Pin> {
+    type Output = Pin::Output;
+    type Group = TraceInputPinGroup<Pin::Group>;
+
+    fn read(&self) -> bool {
+        self.pin.read()
+    }
+    fn wait(&self) {
+        match &self.recorder {
+            Some(_) => drop(self.wait_edges()),
+            None => self.pin.wait(),
+        }
+    }
+    fn wait_timeout(&self, timeout: Duration) -> bool {
+        match &self.recorder {
+            Some(_) => !self.wait_edges_timeout(timeout).is_empty(),
+            None => self.pin.wait_timeout(timeout),
+        }
+    }
+    fn wait_edges(&self) -> Vec<Edge> {
+        let edges = self.pin.wait_edges();
+        if let Some(recorder) = &self.recorder {
+            recorder.record_edges(&edges, |_| &self.name);
+        }
+        edges
+    }
+    fn wait_edges_timeout(&self, timeout: Duration) -> Vec<Edge> {
+        let edges = self.pin.wait_edges_timeout(timeout);
+        if let Some(recorder) = &self.recorder {
+            recorder.record_edges(&edges, |_| &self.name);
+        }
+        edges
+    }
+    fn set_stop_flag(&mut self, stop: Arc<AtomicBool>) {
+        self.pin.set_stop_flag(stop);
+    }
+
+    fn create_group(pins: Vec<Box<Self>>) -> Self::Group {
+        let recorder = pins[0].recorder.clone();
+        let names = pins.iter().map(|pin| pin.name.clone()).collect();
+        let pins = pins.into_iter().map(|pin| Box::new(pin.pin)).collect();
+        TraceInputPinGroup {
+            group: Pin::create_group(pins),
+            recorder,
+            names,
+        }
+    }
+    fn into_output(self) -> io::Result<Self::Output> {
+        self.pin.into_output()
+    }
+}
+
+/// Input pin group which records the changes of the values of its pins (see
+/// `TraceInputPin`).
+pub struct TraceInputPinGroup<Group: InputPinGroup> {
+    group: Group,
+    recorder: Option<Arc<TraceRecorder>>,
+    names: Vec<String>,
+}
+
+impl<Group: InputPinGroup> InputPinGroup for TraceInputPinGroup<Group> {
+    type Pin = TraceInputPin<Group::Pin>;
+
+    fn read(&self) -> u64 {
+        self.group.read()
+    }
+    fn wait(&self) {
+        match &self.recorder {
+            Some(_) => drop(self.wait_edges()),
+            None => self.group.wait(),
+        }
+    }
+    fn wait_timeout(&self, timeout: Duration) -> Option<u64> {
+        match &self.recorder {
+            Some(_) => {
+                let edges = self.wait_edges_timeout(timeout);
+                if edges.is_empty() {
+                    None
+                } else {
+                    Some(
+                        edges
+                            .iter()
+                            .fold(0, |changed, edge| changed | 1 << edge.pin),
+                    )
+                }
+            }
+            None => self.group.wait_timeout(timeout),
+        }
+    }
+    fn wait_edges(&self) -> Vec<Edge> {
+        let edges = self.group.wait_edges();
+        if let Some(recorder) = &self.recorder {
+            recorder.record_edges(&edges, |pin| &self.names[pin]);
+        }
+        edges
+    }
+    fn wait_edges_timeout(&self, timeout: Duration) -> Vec<Edge> {
+        let edges = self.group.wait_edges_timeout(timeout);
+        if let Some(recorder) = &self.recorder {
+            recorder.record_edges(&edges, |pin| &self.names[pin]);
+        }
+        edges
+    }
+    fn set_stop_flag(&mut self, stop: Arc<AtomicBool>) {
+        self.group.set_stop_flag(stop);
+    }
+    fn len(&self) -> usize {
+        self.group.len()
+    }
+
+    fn split(self) -> Vec<Box<Self::Pin>> {
+        let recorder = self.recorder;
+        self.group
+            .split()
+            .into_iter()
+            .zip(self.names)
+            .map(|(pin, name)| {
+                // The values have already been recorded.
+                Box::new(TraceInputPin {
+                    pin: *pin,
+                    recorder: recorder.clone(),
+                    name,
+                })
+            })
+            .collect()
+    }
+}
+
+/// Edge of a recorded trace.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TraceEdge {
+    /// Time since the start of the recording.
+    pub time: Duration,
+    pub pin: String,
+    pub value: bool,
+}
+
+/// Recorded trace which can be replayed in a simulated environment.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Trace {
+    pub edges: Vec<TraceEdge>,
+}
+
+impl Trace {
+    pub fn load(path: &Path) -> io::Result<Self> {
+        Self::parse(&fs::read_to_string(path)?)
+    }
+
+    pub fn parse(text: &str) -> io::Result<Self> {
+        let mut edges = Vec::new();
+        for (i, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let invalid = || {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("line {}: invalid edge \"{}\"", i + 1, line),
+                )
+            };
+            let words = line.split_whitespace().collect::<Vec<_>>();
+            let (time, pin, value) = match words[..] {
+                [time, pin, value] => (time, pin, value),
+                _ => return Err(invalid()),
+            };
+            let time = time.parse().map_err(|_| invalid())?;
+            let value = match value {
+                "0" => false,
+                "1" => true,
+                _ => return Err(invalid()),
+            };
+            edges.push(TraceEdge {
+                time: Duration::from_micros(time),
+                pin: pin.to_string(),
+                value,
+            });
+        }
+        // Pins used by different threads can be recorded slightly out of order.
+        edges.sort_by_key(|edge| edge.time);
+        Ok(Self { edges })
+    }
+
+    /// Returns the trace in the file format.
+    pub fn to_text(&self) -> String {
+        let mut text = format!("{}\n", HEADER);
+        for edge in self.edges.iter() {
+            writeln!(
+                text,
+                "{} {} {}",
+                edge.time.as_micros(),
+                edge.pin,
+                edge.value as u8
+            )
+            .unwrap();
+        }
+        text
+    }
+
+    /// Replays the trace by writing the inputs of the simulated environment.
+    ///
+    /// `pins` maps the pin names of the trace to the indices of the simulated
+    /// pins, edges of other pins are ignored. The trace is played `speed` times
+    /// faster than it was recorded. With virtual time, the function advances the
+    /// time of the environment instead of sleeping, so the original timing is
+    /// replayed exactly without taking any real time.
+    pub fn play(&self, env: &SimEnvironment, pins: &[(&str, usize)], speed: f64) {
+        assert!(speed > 0.0, "invalid playback speed");
+        let start = Instant::now();
+        let mut elapsed = Duration::from_secs(0);
+        for edge in self.edges.iter() {
+            let index = match pins.iter().find(|(name, _)| *name == edge.pin) {
+                Some(&(_, index)) => index,
+                None => continue,
+            };
+            let time = edge.time.div_f64(speed);
+            if env.has_virtual_time() {
+                env.advance(time - elapsed);
+                elapsed = time;
+            } else if let Some(delay) = (start + time).checked_duration_since(Instant::now()) {
+                thread::sleep(delay);
+            }
+            env.write_input(index, edge.value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::Clock;
+    use crate::dial::{Dial, DialConfig};
+    use crate::gpio::sim::{SimInputPinGroup, SimOutputPin};
+    use crate::Event;
+
+    use std::path::PathBuf;
+    use std::process;
+    use std::sync::mpsc::channel;
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!(
+            "fernsprechapparat-{}-{}.trace",
+            name,
+            process::id()
+        ))
+    }
+
+    #[test]
+    fn test_record() {
+        let path = temp_path("record");
+        let env = SimEnvironment::new();
+        let recorder = TraceRecorder::create(&path).unwrap();
+        let recorder = Some(recorder);
+        let hook = TraceInputPin::new(env.create_input_pin(0, false), recorder.clone(), "hook");
+        let group = TraceInputPin::create_group(vec![
+            Box::new(TraceInputPin::new(
+                env.create_input_pin(1, false),
+                recorder.clone(),
+                "nsa",
+            )),
+            Box::new(TraceInputPin::new(
+                env.create_input_pin(2, false),
+                recorder,
+                "nsi",
+            )),
+        ]);
+
+        // Edges are recorded when the pins are waited on, not when they are
+        // read.
+        env.write_input(0, true);
+        assert!(hook.read());
+        assert!(hook.wait_timeout(Duration::from_millis(0)));
+        assert!(!hook.wait_timeout(Duration::from_millis(0)));
+        env.write_input(2, true);
+        assert_eq!(group.wait_timeout(Duration::from_millis(0)), Some(0b10));
+        let pins = group.split();
+        env.write_input(1, true);
+        pins[0].wait();
+
+        let trace = Trace::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        let edges = trace
+            .edges
+            .iter()
+            .map(|edge| (edge.pin.as_str(), edge.value))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            edges,
+            vec![
+                ("hook", false),
+                ("nsa", false),
+                ("nsi", false),
+                ("hook", true),
+                ("nsi", true),
+                ("nsa", true)
+            ]
+        );
+        assert_eq!(Trace::parse(&trace.to_text()).unwrap(), trace);
+    }
+
+    /// Pin which reports edges with timestamps like the cdev backend.
+    struct TimestampPin {
+        edges: Mutex<Vec<Edge>>,
+    }
+
+    impl InputPin for TimestampPin {
+        type Output = SimOutputPin;
+        type Group = SimInputPinGroup;
+
+        fn read(&self) -> bool {
+            false
+        }
+        fn wait(&self) {
+            unreachable!("not used by the trace tests")
+        }
+        fn wait_timeout(&self, _timeout: Duration) -> bool {
+            unreachable!("not used by the trace tests")
+        }
+        fn wait_edges_timeout(&self, _timeout: Duration) -> Vec<Edge> {
+            self.edges.lock().unwrap().drain(..).collect()
+        }
+
+        fn create_group(_pins: Vec<Box<Self>>) -> Self::Group {
+            unreachable!("not used by the trace tests")
+        }
+        fn into_output(self) -> io::Result<Self::Output> {
+            unreachable!("not used by the trace tests")
+        }
+    }
+
+    #[test]
+    fn test_record_timestamps() {
+        let path = temp_path("timestamps");
+        let recorder = TraceRecorder::create(&path).unwrap();
+        let start = recorder.start;
+        let edge = |value, micros| Edge {
+            pin: 0,
+            value,
+            timestamp: Some(start + Duration::from_micros(micros)),
+        };
+        // A bounce reported by the kernel is recorded completely.
+        let pin = TimestampPin {
+            edges: Mutex::new(vec![edge(true, 1000), edge(false, 1250), edge(true, 1900)]),
+        };
+        let pin = TraceInputPin::new(pin, Some(recorder), "nsi");
+        assert!(pin.wait_timeout(Duration::from_millis(0)));
+
+        let text = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        let lines = text.lines().skip(2).collect::<Vec<_>>();
+        assert_eq!(lines, vec!["1000 nsi 1", "1250 nsi 0", "1900 nsi 1"]);
+    }
+
+    #[test]
+    fn test_parse() {
+        let trace = Trace::parse("# comment\n\n0 nsa 1\n1500 nsi 0 # edge\n").unwrap();
+        assert_eq!(
+            trace.edges,
+            vec![
+                TraceEdge {
+                    time: Duration::from_micros(0),
+                    pin: "nsa".to_string(),
+                    value: true,
+                },
+                TraceEdge {
+                    time: Duration::from_micros(1500),
+                    pin: "nsi".to_string(),
+                    value: false,
+                },
+            ]
+        );
+        assert!(Trace::parse("0 nsa 2\n").is_err());
+        assert!(Trace::parse("0 nsa\n").is_err());
+
+        let trace = Trace::parse("10 nsa 1\n5 nsi 0\n10 nsi 1\n").unwrap();
+        let edges = trace
+            .edges
+            .iter()
+            .map(|edge| (edge.pin.as_str(), edge.value))
+            .collect::<Vec<_>>();
+        assert_eq!(edges, vec![("nsi", false), ("nsa", true), ("nsi", true)]);
+    }
+
+    /// Replays the trace of a real dial with jittery pulses.
+    #[test]
+    fn test_play() {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("traces/jittery_dial.trace");
+        let trace = Trace::load(&path).unwrap();
+
+        let env = SimEnvironment::with_virtual_time();
+        let nsa = env.create_input_pin(0, false);
+        let nsi = env.create_input_pin(1, false);
+        env.write_input(1, true);
+        let (send, recv) = channel();
+        let _dial = Dial::new(nsa, nsi, DialConfig::default(), env.clock(), send);
+        env.wait_for_listeners(1);
+
+        let start = env.clock().now();
+        trace.play(&env, &[("nsa", 0), ("nsi", 1)], 1.0);
+        env.advance(Duration::from_millis(100));
+        assert_eq!(env.clock().now() - start, Duration::from_micros(2_543_575));
+        assert_eq!(recv.try_recv(), Ok(Event::Dialed(4)));
+        assert_eq!(recv.try_recv(), Ok(Event::Dialed(2)));
+        assert!(recv.try_recv().is_err());
+
+        // Playing the trace faster in real time.
+        let env = SimEnvironment::new();
+        let nsa = env.create_input_pin(0, false);
+        let start = Instant::now();
+        trace.play(&env, &[("nsa", 0)], 10.0);
+        assert!(start.elapsed() >= Duration::from_millis(230));
+        assert!(!nsa.read());
+    }
+}
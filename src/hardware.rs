@@ -0,0 +1,583 @@
+//! Configuration of the GPIO pins connected to the phone hardware.
+//!
+//! Different board revisions and mods use different pins and wiring, so the
+//! pin assignment is read from the `gpio` section of the configuration and
+//! validated before any pin is opened.
+
+use super::dial::DialMode;
+use super::gpio::polarity::{Polarity, PolarityInputPin, PolarityOutputPin};
+use super::gpio::trace::{TraceInputPin, TraceRecorder};
+use super::gpio::{self, Bias, InputPin, OutputPin};
+use super::keypad;
+
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::path::Path;
+
+/// Kernel interface used to access the GPIOs.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum GpioBackend {
+    /// Deprecated sysfs interface (`/sys/class/gpio`).
+    #[default]
+    Sysfs,
+    /// GPIO character device interface (`/dev/gpiochipN`).
+    Cdev,
+    /// Simulated pins controlled via a Unix domain socket (see
+    /// `gpio::remote`), for integration tests.
+    Remote,
+}
+
+/// Assignment of a single pin.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct PinConfig {
+    /// GPIO chip of the pin if it differs from `GpioConfig::chip` (only used
+    /// by the `cdev` backend).
+    #[serde(default)]
+    pub chip: Option<String>,
+    /// Line offset on the chip (`cdev`) or global GPIO number (`sysfs`).
+    pub line: u32,
+    /// Level at which the pin is active. There is no default, as the wiring
+    /// differs between the functions and between phones.
+    pub polarity: Polarity,
+    /// Pull resistor (only supported by the `cdev` backend).
+    #[serde(default)]
+    pub bias: Bias,
+}
+
+#[cfg(test)]
+impl PinConfig {
+    fn new(line: u32, polarity: Polarity) -> Self {
+        Self {
+            chip: None,
+            line,
+            polarity,
+            bias: Bias::AsIs,
+        }
+    }
+}
+
+/// Pins connected to the phone hardware.
+///
+/// Only the pins required for the configured dial mode have to be present.
+/// The pins have no defaults, as every phone is wired differently, so
+/// `validate()` reports the pins which are missing.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct GpioConfig {
+    #[serde(default)]
+    pub backend: GpioBackend,
+    /// GPIO chip device used by the `cdev` backend.
+    #[serde(default = "default_chip")]
+    pub chip: String,
+    /// Socket on which the `remote` backend accepts clients.
+    #[serde(default = "default_socket")]
+    pub socket: String,
+    /// File to which the edges of all inputs are written (see
+    /// `gpio::trace`), e.g., to attach them to a bug report.
+    #[serde(default)]
+    pub trace: Option<String>,
+    /// Nsa contact of the dial (not used in `nsi_only` and `keypad` mode).
+    pub nsa: Option<PinConfig>,
+    /// Nsi contact of the dial (not used in `keypad` mode).
+    pub nsi: Option<PinConfig>,
+    /// Hook switch, active while the earpiece is picked up.
+    pub hook: Option<PinConfig>,
+    /// Earth key, if the phone has one.
+    #[serde(default)]
+    pub earth_key: Option<PinConfig>,
+    /// Output which rings the bell.
+    pub ring: Option<PinConfig>,
+    /// Row outputs of the keypad (only used in `keypad` mode).
+    pub keypad_rows: Option<Vec<PinConfig>>,
+    /// Column inputs of the keypad (only used in `keypad` mode).
+    pub keypad_columns: Option<Vec<PinConfig>>,
+}
+
+impl Default for GpioConfig {
+    fn default() -> Self {
+        Self {
+            backend: GpioBackend::default(),
+            chip: default_chip(),
+            socket: default_socket(),
+            trace: None,
+            nsa: None,
+            nsi: None,
+            hook: None,
+            earth_key: None,
+            ring: None,
+            keypad_rows: None,
+            keypad_columns: None,
+        }
+    }
+}
+
+fn default_chip() -> String {
+    gpio::cdev::DEFAULT_CHIP.into()
+}
+
+fn default_socket() -> String {
+    gpio::remote::DEFAULT_SOCKET.into()
+}
+
+/// Error in the GPIO configuration.
+#[derive(Debug)]
+pub enum GpioConfigError {
+    /// A pin required for the dial mode is not configured.
+    Missing(String),
+    /// The keypad has the wrong number of rows or columns.
+    WrongPinCount {
+        name: String,
+        expected: usize,
+        actual: usize,
+    },
+    /// Two functions are assigned to the same pin.
+    Duplicate(String, String),
+    /// Pins which are read together are on different chips.
+    DifferentChips(String, String),
+    /// A bias was configured for a backend which does not support it.
+    BiasNotSupported(String),
+    /// The pin could not be opened.
+    Open(String, io::Error),
+}
+
+impl fmt::Display for GpioConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            GpioConfigError::Missing(name) => write!(f, "{} is not configured", name),
+            GpioConfigError::WrongPinCount {
+                name,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "{} requires {} pins, but {} are configured",
+                name, expected, actual
+            ),
+            GpioConfigError::Duplicate(a, b) => {
+                write!(f, "{} and {} are assigned to the same pin", a, b)
+            }
+            GpioConfigError::DifferentChips(a, b) => {
+                write!(f, "{} and {} have to be on the same GPIO chip", a, b)
+            }
+            GpioConfigError::BiasNotSupported(name) => {
+                write!(f, "{}: bias is only supported by the cdev backend", name)
+            }
+            GpioConfigError::Open(name, e) => write!(f, "could not open {}: {}", name, e),
+        }
+    }
+}
+
+/// GPIO pins connected to the rotary dial or the keypad.
+pub enum DialPins<In: InputPin, Out: OutputPin> {
+    Rotary {
+        /// Nsa contact of the dial, unless the dial is used in `NsiOnly` mode.
+        nsa: Option<In>,
+        nsi: In,
+    },
+    Keypad {
+        rows: Vec<Out>,
+        columns: Vec<In>,
+    },
+}
+
+/// GPIO pins connected to the phone hardware.
+pub struct PhonePins<In: InputPin, Out: OutputPin> {
+    pub dial: DialPins<In, Out>,
+    pub hook: In,
+    /// Earth key, if the phone has one.
+    pub earth_key: Option<In>,
+    pub ring: Out,
+}
+
+impl GpioConfig {
+    /// Checks that all pins required for the dial mode are configured and
+    /// that no pin is used twice.
+    pub fn validate(&self, mode: DialMode) -> Result<(), GpioConfigError> {
+        let mut used = HashMap::new();
+        for (name, pin) in self.used_pins(mode)? {
+            if pin.bias != Bias::AsIs && self.backend != GpioBackend::Cdev {
+                return Err(GpioConfigError::BiasNotSupported(name));
+            }
+            let chip = match self.backend {
+                GpioBackend::Sysfs | GpioBackend::Remote => "",
+                GpioBackend::Cdev => self.chip_of(pin),
+            };
+            if let Some(other) = used.insert((chip, pin.line), name.clone()) {
+                return Err(GpioConfigError::Duplicate(other, name));
+            }
+        }
+
+        // Pins which are grouped have to be requested together.
+        if self.backend == GpioBackend::Cdev {
+            let groups = match mode {
+                DialMode::NsaNsi => {
+                    vec![self.named("nsa", &self.nsa)?, self.named("nsi", &self.nsi)?]
+                }
+                DialMode::NsiOnly => Vec::new(),
+                DialMode::Keypad => list(
+                    "keypad_columns",
+                    self.keypad_columns.as_deref().unwrap_or(&[]),
+                ),
+            };
+            if let Some((first, first_pin)) = groups.first() {
+                for (name, pin) in groups.iter() {
+                    if self.chip_of(pin) != self.chip_of(first_pin) {
+                        return Err(GpioConfigError::DifferentChips(first.clone(), name.clone()));
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Validates the configuration and opens the pins required for the dial
+    /// mode.
+    ///
+    /// The pins are opened with the specified functions, which receive the
+    /// chip and the configuration of the pin. They are wrapped according to
+    /// their polarity, so that the types using them only see whether a contact
+    /// is active. If `trace` is set, the logical values of the inputs are
+    /// recorded.
+    #[allow(clippy::type_complexity)]
+    pub fn open<In, Out, OpenIn, OpenOut>(
+        &self,
+        mode: DialMode,
+        open_input: OpenIn,
+        open_output: OpenOut,
+    ) -> Result<
+        PhonePins<TraceInputPin<PolarityInputPin<In>>, PolarityOutputPin<Out>>,
+        GpioConfigError,
+    >
+    where
+        In: InputPin,
+        Out: OutputPin,
+        OpenIn: Fn(&Path, &PinConfig) -> io::Result<In>,
+        OpenOut: Fn(&Path, &PinConfig) -> io::Result<Out>,
+    {
+        self.validate(mode)?;
+        let recorder = match &self.trace {
+            Some(path) => Some(
+                TraceRecorder::create(Path::new(path))
+                    .map_err(|e| GpioConfigError::Open("gpio.trace".into(), e))?,
+            ),
+            None => None,
+        };
+        let input = |name: &str, pin: &PinConfig| {
+            open_input(Path::new(self.chip_of(pin)), pin)
+                .map(|input| {
+                    let input = PolarityInputPin::new(input, pin.polarity);
+                    TraceInputPin::new(input, recorder.clone(), name)
+                })
+                .map_err(|e| GpioConfigError::Open(format!("gpio.{}", name), e))
+        };
+        let output = |name: &str, pin: &PinConfig| {
+            open_output(Path::new(self.chip_of(pin)), pin)
+                .map(|output| PolarityOutputPin::new(output, pin.polarity))
+                .map_err(|e| GpioConfigError::Open(format!("gpio.{}", name), e))
+        };
+
+        let dial = match mode {
+            DialMode::NsaNsi | DialMode::NsiOnly => {
+                let nsa = match (mode, &self.nsa) {
+                    (DialMode::NsaNsi, Some(nsa)) => Some(input("nsa", nsa)?),
+                    _ => None,
+                };
+                let nsi = self.nsi.as_ref().expect("validated");
+                DialPins::Rotary {
+                    nsa,
+                    nsi: input("nsi", nsi)?,
+                }
+            }
+            DialMode::Keypad => DialPins::Keypad {
+                rows: self
+                    .keypad_rows
+                    .as_ref()
+                    .expect("validated")
+                    .iter()
+                    .enumerate()
+                    .map(|(i, pin)| output(&format!("keypad_rows[{}]", i), pin))
+                    .collect::<Result<_, _>>()?,
+                columns: self
+                    .keypad_columns
+                    .as_ref()
+                    .expect("validated")
+                    .iter()
+                    .enumerate()
+                    .map(|(i, pin)| input(&format!("keypad_columns[{}]", i), pin))
+                    .collect::<Result<_, _>>()?,
+            },
+        };
+        let earth_key = match &self.earth_key {
+            Some(pin) => Some(input("earth_key", pin)?),
+            None => None,
+        };
+        Ok(PhonePins {
+            dial,
+            hook: input("hook", self.hook.as_ref().expect("validated"))?,
+            earth_key,
+            ring: output("ring", self.ring.as_ref().expect("validated"))?,
+        })
+    }
+
+    /// Returns the chip device of the pin.
+    fn chip_of<'a>(&'a self, pin: &'a PinConfig) -> &'a str {
+        pin.chip.as_deref().unwrap_or(&self.chip)
+    }
+
+    /// Returns the pins required for the dial mode along with their names.
+    fn used_pins(&self, mode: DialMode) -> Result<Vec<(String, &PinConfig)>, GpioConfigError> {
+        let mut pins = Vec::new();
+        match mode {
+            DialMode::NsaNsi => {
+                pins.push(self.named("nsa", &self.nsa)?);
+                pins.push(self.named("nsi", &self.nsi)?);
+            }
+            DialMode::NsiOnly => pins.push(self.named("nsi", &self.nsi)?),
+            DialMode::Keypad => {
+                let rows = check_count("keypad_rows", &self.keypad_rows, keypad::ROWS)?;
+                let columns = check_count("keypad_columns", &self.keypad_columns, keypad::COLUMNS)?;
+                pins.extend(list("keypad_rows", rows));
+                pins.extend(list("keypad_columns", columns));
+            }
+        }
+        pins.push(self.named("hook", &self.hook)?);
+        pins.push(self.named("ring", &self.ring)?);
+        if let Some(earth_key) = &self.earth_key {
+            pins.push(("gpio.earth_key".to_string(), earth_key));
+        }
+        Ok(pins)
+    }
+
+    fn named<'a>(
+        &self,
+        name: &str,
+        pin: &'a Option<PinConfig>,
+    ) -> Result<(String, &'a PinConfig), GpioConfigError> {
+        let name = format!("gpio.{}", name);
+        match pin {
+            Some(pin) => Ok((name, pin)),
+            None => Err(GpioConfigError::Missing(name)),
+        }
+    }
+}
+
+fn list<'a>(name: &str, pins: &'a [PinConfig]) -> Vec<(String, &'a PinConfig)> {
+    pins.iter()
+        .enumerate()
+        .map(|(i, pin)| (format!("gpio.{}[{}]", name, i), pin))
+        .collect()
+}
+
+/// Returns the pins of the list if it has the expected length.
+fn check_count<'a>(
+    name: &str,
+    pins: &'a Option<Vec<PinConfig>>,
+    expected: usize,
+) -> Result<&'a [PinConfig], GpioConfigError> {
+    let name = format!("gpio.{}", name);
+    let pins = pins
+        .as_ref()
+        .ok_or_else(|| GpioConfigError::Missing(name.clone()))?;
+    if pins.len() != expected {
+        return Err(GpioConfigError::WrongPinCount {
+            name,
+            expected,
+            actual: pins.len(),
+        });
+    }
+    Ok(pins)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gpio::sim::SimEnvironment;
+
+    /// Configuration file containing only the `gpio` section.
+    #[derive(Deserialize)]
+    struct Config {
+        gpio: GpioConfig,
+    }
+
+    /// Returns a configuration in which all pins are assigned.
+    fn all_pins() -> GpioConfig {
+        GpioConfig {
+            nsa: Some(PinConfig::new(1, Polarity::ActiveLow)),
+            nsi: Some(PinConfig::new(2, Polarity::ActiveLow)),
+            hook: Some(PinConfig::new(4, Polarity::ActiveHigh)),
+            ring: Some(PinConfig::new(3, Polarity::ActiveHigh)),
+            keypad_rows: Some(
+                (5..9)
+                    .map(|line| PinConfig::new(line, Polarity::ActiveHigh))
+                    .collect(),
+            ),
+            keypad_columns: Some(
+                (9..12)
+                    .map(|line| PinConfig::new(line, Polarity::ActiveHigh))
+                    .collect(),
+            ),
+            ..Default::default()
+        }
+    }
+
+    fn error(config: &GpioConfig, mode: DialMode) -> String {
+        config.validate(mode).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn test_validate() {
+        let config = all_pins();
+        config.validate(DialMode::NsaNsi).unwrap();
+        config.validate(DialMode::NsiOnly).unwrap();
+        config.validate(DialMode::Keypad).unwrap();
+
+        // Pins are only required if they are used in the dial mode.
+        let config = GpioConfig {
+            nsa: None,
+            keypad_rows: Some(Vec::new()),
+            ..all_pins()
+        };
+        assert_eq!(
+            error(&config, DialMode::NsaNsi),
+            "gpio.nsa is not configured"
+        );
+        config.validate(DialMode::NsiOnly).unwrap();
+        assert_eq!(
+            error(&config, DialMode::Keypad),
+            "gpio.keypad_rows requires 4 pins, but 0 are configured"
+        );
+
+        let mut config = GpioConfig {
+            earth_key: Some(PinConfig::new(2, Polarity::ActiveLow)),
+            ..all_pins()
+        };
+        assert_eq!(
+            error(&config, DialMode::NsaNsi),
+            "gpio.nsi and gpio.earth_key are assigned to the same pin"
+        );
+        config.validate(DialMode::Keypad).unwrap();
+        config.keypad_columns.as_mut().unwrap()[1].line = 8;
+        assert_eq!(
+            error(&config, DialMode::Keypad),
+            "gpio.keypad_rows[3] and gpio.keypad_columns[1] are assigned to the same pin"
+        );
+    }
+
+    #[test]
+    fn test_validate_cdev() {
+        let mut config = all_pins();
+        config.nsa.as_mut().unwrap().bias = Bias::PullUp;
+        assert_eq!(
+            error(&config, DialMode::NsaNsi),
+            "gpio.nsa: bias is only supported by the cdev backend"
+        );
+        config.backend = GpioBackend::Cdev;
+        config.validate(DialMode::NsaNsi).unwrap();
+
+        // The same line of different chips is a different pin.
+        config.earth_key = Some(PinConfig {
+            chip: Some("/dev/gpiochip1".to_string()),
+            ..PinConfig::new(2, Polarity::ActiveLow)
+        });
+        config.validate(DialMode::NsaNsi).unwrap();
+        config.earth_key.as_mut().unwrap().chip = Some(config.chip.clone());
+        assert!(config.validate(DialMode::NsaNsi).is_err());
+        config.earth_key = None;
+
+        // Grouped pins have to be on the same chip.
+        config.nsi.as_mut().unwrap().chip = Some("/dev/gpiochip1".to_string());
+        assert_eq!(
+            error(&config, DialMode::NsaNsi),
+            "gpio.nsa and gpio.nsi have to be on the same GPIO chip"
+        );
+        config.validate(DialMode::NsiOnly).unwrap();
+    }
+
+    #[test]
+    fn test_deserialize() {
+        // Without a polarity, an active-low dial contact would silently be
+        // treated as active-high.
+        let section = "[gpio.nsa]\nline = 17\n[gpio.nsi]\nline = 27\n";
+        let error = toml::from_str::<Config>(section).err().unwrap();
+        assert!(error.to_string().contains("missing field `polarity`"));
+
+        let section = "[gpio.nsa]\nline = 17\npolarity = \"active_low\"\n\
+                       [gpio.nsi]\nline = 27\npolarity = \"active_low\"\n";
+        let config = toml::from_str::<Config>(section).unwrap().gpio;
+        assert_eq!(config.nsa, Some(PinConfig::new(17, Polarity::ActiveLow)));
+        assert_eq!(config.nsi, Some(PinConfig::new(27, Polarity::ActiveLow)));
+        assert_eq!(config.backend, GpioBackend::Sysfs);
+        assert_eq!(config.chip, gpio::cdev::DEFAULT_CHIP);
+        // Pins which are not specified are not assigned.
+        assert_eq!(config.hook, None);
+        assert_eq!(config.keypad_rows, None);
+    }
+
+    #[test]
+    fn test_missing() {
+        let section = "[gpio.nsa]\nline = 17\npolarity = \"active_low\"\n\
+                       [gpio.nsi]\nline = 27\npolarity = \"active_low\"\n";
+        let mut config = toml::from_str::<Config>(section).unwrap().gpio;
+        match config.validate(DialMode::NsaNsi) {
+            Err(GpioConfigError::Missing(name)) => assert_eq!(name, "gpio.hook"),
+            result => panic!("expected gpio.hook to be missing, got {:?}", result),
+        }
+        config.hook = Some(PinConfig::new(22, Polarity::ActiveHigh));
+        match config.validate(DialMode::NsaNsi) {
+            Err(GpioConfigError::Missing(name)) => assert_eq!(name, "gpio.ring"),
+            result => panic!("expected gpio.ring to be missing, got {:?}", result),
+        }
+        assert_eq!(
+            error(&config, DialMode::Keypad),
+            "gpio.keypad_rows is not configured"
+        );
+        config.ring = Some(PinConfig::new(23, Polarity::ActiveHigh));
+        config.validate(DialMode::NsaNsi).unwrap();
+    }
+
+    #[test]
+    fn test_open() {
+        let env = SimEnvironment::new();
+        let config = all_pins();
+        let pins = config
+            .open(
+                DialMode::NsaNsi,
+                |chip, pin| {
+                    assert_eq!(chip, Path::new(gpio::cdev::DEFAULT_CHIP));
+                    Ok(env.create_input_pin(pin.line as usize, false))
+                },
+                |_, pin| Ok(env.create_output_pin(pin.line as usize, false)),
+            )
+            .unwrap();
+        let (nsa, nsi) = match pins.dial {
+            DialPins::Rotary { nsa, nsi } => (nsa.unwrap(), nsi),
+            DialPins::Keypad { .. } => panic!("expected rotary dial pins"),
+        };
+        assert!(pins.earth_key.is_none());
+
+        // The polarity of the pins is applied.
+        assert!(nsa.read());
+        assert!(nsi.read());
+        assert!(!pins.hook.read());
+        env.write_input(1, true);
+        env.write_input(4, true);
+        assert!(!nsa.read());
+        assert!(pins.hook.read());
+        pins.ring.write(true);
+        assert!(env.read_output(3));
+
+        let failed = config.open(
+            DialMode::NsiOnly,
+            |_, pin| match pin.line {
+                4 => Err(io::Error::new(io::ErrorKind::NotFound, "no such pin")),
+                line => Ok(env.create_input_pin(line as usize, false)),
+            },
+            |_, pin| Ok(env.create_output_pin(pin.line as usize, false)),
+        );
+        match failed {
+            Err(e) => assert_eq!(e.to_string(), "could not open gpio.hook: no such pin"),
+            Ok(_) => panic!("opening the hook pin should fail"),
+        }
+    }
+}
@@ -0,0 +1,273 @@
+//! Type which generates events when keys of a push-button keypad are pressed.
+
+use super::debounce::Debouncer;
+use super::gpio::{InputPinGroup, OutputPin};
+use super::Event;
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::Sender;
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// Number of rows of the key matrix.
+pub const ROWS: usize = 4;
+/// Number of columns of the key matrix.
+pub const COLUMNS: usize = 3;
+
+/// Time for which the column lines are allowed to settle after the active row
+/// has been changed.
+const SETTLE_TIME: Duration = Duration::from_millis(1);
+
+/// Key of the keypad.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Key {
+    Digit(u32),
+    Star,
+    Hash,
+}
+
+/// Layout of the key matrix, indexed by row and column.
+const LAYOUT: [[Key; COLUMNS]; ROWS] = [
+    [Key::Digit(1), Key::Digit(2), Key::Digit(3)],
+    [Key::Digit(4), Key::Digit(5), Key::Digit(6)],
+    [Key::Digit(7), Key::Digit(8), Key::Digit(9)],
+    [Key::Star, Key::Digit(0), Key::Hash],
+];
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(default)]
+pub struct KeypadConfig {
+    /// Time in milliseconds for which a key has to be stable before it is
+    /// reported.
+    pub debounce_ms: u64,
+    /// Interval in milliseconds in which the matrix is scanned while a key is
+    /// pressed.
+    pub scan_interval_ms: u64,
+}
+
+impl Default for KeypadConfig {
+    fn default() -> Self {
+        Self {
+            debounce_ms: 20,
+            scan_interval_ms: 10,
+        }
+    }
+}
+
+/// Activates the rows one after another and returns the first pressed key.
+///
+/// All rows are active again when the function returns, so that any key press
+/// causes a change of the column inputs.
+fn scan<Out: OutputPin, Group: InputPinGroup>(rows: &[Out], columns: &Group) -> Option<Key> {
+    let key = (0..rows.len()).find_map(|i| {
+        for (j, row) in rows.iter().enumerate() {
+            row.write(i == j);
+        }
+        thread::sleep(SETTLE_TIME);
+        match columns.read() {
+            0 => None,
+            pressed => Some(LAYOUT[i][pressed.trailing_zeros() as usize]),
+        }
+    });
+    for row in rows {
+        row.write(true);
+    }
+    key
+}
+
+/// Interface to a 4x3 key matrix as found in push-button phones (e.g., the
+/// FeTAp 791).
+///
+/// The rows are driven by output pins, where `true` activates the row. The
+/// columns are read by an input pin group, where `true` means that a key in
+/// the column and an active row is pressed. While no key is pressed, all rows
+/// are active and the implementation waits for a change of the columns.
+///
+/// Digits are reported as `Dialed` events, `*` and `#` as `StarPressed` and
+/// `HashPressed`. Every press is reported once, holding a key does not repeat
+/// it.
+pub struct Keypad {
+    thread: Option<JoinHandle<()>>,
+    stop_thread: Arc<AtomicBool>,
+}
+
+impl Keypad {
+    pub fn new<Out: OutputPin + Send + 'static, Group: InputPinGroup + Send + 'static>(
+        rows: Vec<Out>,
+        columns: Group,
+        config: KeypadConfig,
+        sender: Sender<Event>,
+    ) -> Self {
+        assert_eq!(rows.len(), ROWS, "keypad requires {} row pins", ROWS);
+        assert_eq!(
+            columns.len(),
+            COLUMNS,
+            "keypad requires {} column pins",
+            COLUMNS
+        );
+        let stop_thread = Arc::new(AtomicBool::new(false));
+        let stop_copy = stop_thread.clone();
+        let thread = thread::spawn(move || {
+            for row in rows.iter() {
+                row.write(true);
+            }
+            let scan_interval = Duration::from_millis(config.scan_interval_ms);
+            let mut debouncer = Debouncer::new(Duration::from_millis(config.debounce_ms), None);
+            let mut key_down = false;
+            loop {
+                // The matrix is only scanned while a key is pressed. Otherwise,
+                // wait with timeout to allow Drop to terminate the thread in a
+                // timely fashion.
+                let scanning = key_down || debouncer.deadline().is_some();
+                let timeout = if scanning {
+                    scan_interval
+                } else {
+                    Duration::from_millis(1000)
+                };
+                let changed = columns.wait_timeout(timeout).is_some();
+                if stop_thread.load(Ordering::SeqCst) {
+                    return;
+                }
+                if changed || scanning {
+                    debouncer.update(scan(&rows, &columns), Instant::now());
+                }
+                let key = match debouncer.poll(Instant::now()) {
+                    Some((key, _)) => key,
+                    None => continue,
+                };
+                key_down = key.is_some();
+                let event = match key {
+                    Some(Key::Digit(digit)) => Event::Dialed(digit),
+                    Some(Key::Star) => Event::StarPressed,
+                    Some(Key::Hash) => Event::HashPressed,
+                    None => continue,
+                };
+                if sender.send(event).is_err() {
+                    return;
+                }
+            }
+        });
+        Self {
+            thread: Some(thread),
+            stop_thread: stop_copy,
+        }
+    }
+}
+
+impl Drop for Keypad {
+    fn drop(&mut self) {
+        self.stop_thread.store(true, Ordering::SeqCst);
+        let thread = self.thread.take();
+        thread.unwrap().join().unwrap();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gpio::sim::{SimEnvironment, SimInputPin};
+    use crate::gpio::InputPin;
+
+    use std::sync::mpsc::channel;
+    use std::sync::Mutex;
+    use std::thread::sleep;
+
+    const ROW_PINS: [usize; ROWS] = [0, 1, 2, 3];
+    const COLUMN_PINS: [usize; COLUMNS] = [4, 5, 6];
+
+    /// Simulated key matrix which connects the column inputs to the active
+    /// rows while a key is pressed.
+    struct Matrix {
+        pressed: Arc<Mutex<Option<(usize, usize)>>>,
+        stop: Arc<AtomicBool>,
+        thread: Option<JoinHandle<()>>,
+    }
+
+    impl Matrix {
+        fn new(env: SimEnvironment) -> Self {
+            let pressed = Arc::new(Mutex::new(None));
+            let stop = Arc::new(AtomicBool::new(false));
+            let (pressed_copy, stop_copy) = (pressed.clone(), stop.clone());
+            let thread = thread::spawn(move || {
+                let mut columns = [false; COLUMNS];
+                while !stop_copy.load(Ordering::SeqCst) {
+                    let pressed = *pressed_copy.lock().unwrap();
+                    for (column, value) in columns.iter_mut().enumerate() {
+                        let new_value = match pressed {
+                            Some((row, c)) => c == column && env.read_output(ROW_PINS[row]),
+                            None => false,
+                        };
+                        if new_value != *value {
+                            *value = new_value;
+                            env.write_input(COLUMN_PINS[column], new_value);
+                        }
+                    }
+                    sleep(Duration::from_micros(50));
+                }
+            });
+            Self {
+                pressed,
+                stop,
+                thread: Some(thread),
+            }
+        }
+
+        fn press(&self, key: Key) {
+            for (row, keys) in LAYOUT.iter().enumerate() {
+                if let Some(column) = keys.iter().position(|&k| k == key) {
+                    *self.pressed.lock().unwrap() = Some((row, column));
+                }
+            }
+            sleep(Duration::from_millis(100));
+            *self.pressed.lock().unwrap() = None;
+            sleep(Duration::from_millis(100));
+        }
+    }
+
+    impl Drop for Matrix {
+        fn drop(&mut self) {
+            self.stop.store(true, Ordering::SeqCst);
+            self.thread.take().unwrap().join().unwrap();
+        }
+    }
+
+    #[test]
+    fn test_keypad() {
+        let env = SimEnvironment::new();
+        let rows = ROW_PINS
+            .iter()
+            .map(|&pin| env.create_output_pin(pin, false))
+            .collect::<Vec<_>>();
+        let columns = SimInputPin::create_group(
+            COLUMN_PINS
+                .iter()
+                .map(|&pin| Box::new(env.create_input_pin(pin, false)))
+                .collect(),
+        );
+        let (send, recv) = channel();
+        let _keypad = Keypad::new(rows, columns, KeypadConfig::default(), send);
+        sleep(Duration::from_millis(10));
+        assert!(ROW_PINS.iter().all(|&pin| env.read_output(pin)));
+
+        let matrix = Matrix::new(env);
+        for &key in [
+            Key::Digit(5),
+            Key::Star,
+            Key::Digit(0),
+            Key::Hash,
+            Key::Digit(1),
+        ]
+        .iter()
+        {
+            matrix.press(key);
+        }
+        drop(matrix);
+        assert_eq!(recv.try_recv(), Ok(Event::Dialed(5)));
+        assert_eq!(recv.try_recv(), Ok(Event::StarPressed));
+        assert_eq!(recv.try_recv(), Ok(Event::Dialed(0)));
+        assert_eq!(recv.try_recv(), Ok(Event::HashPressed));
+        assert_eq!(recv.try_recv(), Ok(Event::Dialed(1)));
+        assert!(recv.try_recv().is_err());
+    }
+}
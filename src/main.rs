@@ -10,39 +10,103 @@ extern crate serde;
 #[macro_use]
 extern crate serde_derive;
 
+mod calibration;
+mod clock;
 mod console;
+mod debounce;
 mod dial;
+mod dialplan;
 mod earpiece;
+mod earthkey;
 mod gpio;
+mod hardware;
+mod keypad;
+mod ringer;
+#[cfg(test)]
+mod scenario;
 mod sip;
 mod state;
+mod tones;
 
-use console::ConsoleInput;
-use dial::Dial;
-use earpiece::Earpiece;
+use calibration::CalibrationReport;
+use clock::SystemClock;
+use console::{ConsoleBell, ConsoleInput};
+use dial::{Dial, DialConfig, DialRejection};
+use dialplan::DialPlanConfig;
+use earpiece::{Earpiece, EarpieceConfig};
+use earthkey::{EarthKey, EarthKeyConfig};
+use gpio::cdev::{CdevInputPin, CdevOutputPin};
+use gpio::remote::RemoteEnvironment;
 use gpio::sysfs::{SysfsInputPin, SysfsOutputPin};
-use sip::Sip;
-use state::StateMachine;
+use gpio::{InputPin, OutputPin};
+use hardware::{DialPins, GpioBackend, GpioConfig, PhonePins};
+use keypad::{Keypad, KeypadConfig};
+use ringer::{Cadence, Ringer};
+use sip::{Call, Sip};
+use state::{CallConfig, StateMachine};
+use tones::ToneCountry;
 
 use serde::{Deserialize, Serialize};
 
-use std::sync::mpsc::channel;
+use std::path::Path;
+use std::sync::mpsc::{channel, Receiver, Sender};
 
 #[derive(Debug, PartialEq)]
 pub enum Event {
     Dialed(u32),
+    /// The `*` key of the keypad was pressed.
+    StarPressed,
+    /// The `#` key of the keypad was pressed.
+    HashPressed,
+    /// A digit was dialed, but the pulses were outside of the configured
+    /// tolerance.
+    DialRejected(DialRejection),
+    /// A digit was dialed in calibration mode.
+    DialCalibrated(CalibrationReport),
     EarpiecePickedUp,
     EarpiecePutDown,
+    /// The earpiece was put down only briefly (hook flash, "R key").
+    HookFlash,
+    /// The earth key ("Erdtaste") was pressed.
+    FlashKey,
     Registered,
-    Unregistered,
+    /// Registration at the SIP registrar failed or was lost. The value is the
+    /// SIP status code of the last registration attempt.
+    Unregistered(u32),
+    /// Incoming call from the specified remote URI.
+    IncomingCall(Call, String),
+    /// The remote phone of an outgoing call is ringing.
+    CallRinging(Call),
+    /// A call has been established.
+    CallConfirmed(Call),
+    /// A call has ended. The value is the last SIP status code of the call.
+    CallDisconnected(Call, u32),
+    /// The user requested the state of the phone to be printed.
+    StatusRequested,
 }
 
 #[derive(Serialize, Deserialize)]
+#[serde(default)]
 struct Config {
     domain: String,
     user: String,
     password: String,
     cli: bool,
+    /// Country whose call-progress tones are played in the earpiece.
+    tones: ToneCountry,
+    /// Pins connected to the phone hardware.
+    gpio: GpioConfig,
+    /// Timing limits of the rotary dial.
+    dial: DialConfig,
+    /// Debouncing of the keypad which replaces the dial in `keypad` mode.
+    keypad: KeypadConfig,
+    /// Thresholds for hook flashes and dialing via the hook switch.
+    earpiece: EarpieceConfig,
+    /// Debouncing of the earth key.
+    earth_key: EarthKeyConfig,
+    ring_cadence: Cadence,
+    call: CallConfig,
+    dial_plan: DialPlanConfig,
 }
 
 impl ::std::default::Default for Config {
@@ -52,41 +116,145 @@ impl ::std::default::Default for Config {
             user: "".into(),
             password: "".into(),
             cli: false,
+            tones: ToneCountry::default(),
+            gpio: GpioConfig::default(),
+            dial: DialConfig::default(),
+            keypad: KeypadConfig::default(),
+            earpiece: EarpieceConfig::default(),
+            earth_key: EarthKeyConfig::default(),
+            ring_cadence: Cadence::default(),
+            call: CallConfig::default(),
+            dial_plan: DialPlanConfig::default(),
         }
     }
 }
 
-// TODO: Correct GPIO numbers.
-const NSA_PIN: usize = 1;
-const NSI_PIN: usize = 2;
-const RING_PIN: usize = 3;
-const HOOK_PIN: usize = 4;
-
 fn main() {
-    let cfg: Config = confy::load("fernsprechapparat").unwrap();
+    let mut cfg: Config = confy::load("fernsprechapparat").unwrap();
     if cfg.password == "" {
         // Create config if it does not exist yet.
         confy::store("fernsprechapparat", cfg).ok();
         panic!("No valid configuration!");
     }
+    if std::env::args()
+        .skip(1)
+        .any(|arg| arg == "--calibrate-dial")
+    {
+        cfg.dial.calibration = true;
+    }
+    if cfg.dial.calibration {
+        println!("Dial calibration mode: dialed digits are measured, not dialed.");
+    }
+    if !cfg.cli {
+        if let Err(e) = cfg.gpio.validate(cfg.dial.mode) {
+            eprintln!("Invalid GPIO configuration: {}", e);
+            std::process::exit(1);
+        }
+    }
 
     let (input_send, input_recv) = channel();
 
-    let sip = Sip::new(&cfg.domain, &cfg.user, &cfg.password);
-
-    let mut state_machine = StateMachine::new(input_recv);
+    let sip = match Sip::new(
+        &cfg.domain,
+        &cfg.user,
+        &cfg.password,
+        cfg.tones,
+        input_send.clone(),
+    ) {
+        Ok(sip) => sip,
+        Err(e) => panic!("Could not initialize SIP: {}", e),
+    };
 
     if cfg.cli {
-        let _input = ConsoleInput::new(input_send);
+        let _input = ConsoleInput::new(input_send, cfg.dial.calibration);
+        let mut state_machine = StateMachine::new(
+            input_recv,
+            sip,
+            ConsoleBell,
+            cfg.call.clone(),
+            cfg.dial_plan.clone(),
+        );
         state_machine.run();
     } else {
-        let nsa = SysfsInputPin::open(NSA_PIN).unwrap();
-        let nsi = SysfsInputPin::open(NSI_PIN).unwrap();
-        let hook = SysfsInputPin::open(HOOK_PIN).unwrap();
-        let ring = SysfsOutputPin::open(RING_PIN).unwrap();
+        match cfg.gpio.backend {
+            GpioBackend::Sysfs => {
+                let pins = cfg.gpio.open(
+                    cfg.dial.mode,
+                    |_, pin| SysfsInputPin::open(pin.line as usize),
+                    |_, pin| SysfsOutputPin::open(pin.line as usize),
+                );
+                match pins {
+                    Ok(pins) => run_phone(&cfg, sip, pins, input_send, input_recv),
+                    Err(e) => panic!("Could not open GPIO pins: {}", e),
+                }
+            }
+            GpioBackend::Cdev => {
+                let pins = cfg.gpio.open(
+                    cfg.dial.mode,
+                    |chip, pin| CdevInputPin::open(chip, pin.line, pin.bias),
+                    |chip, pin| CdevOutputPin::open(chip, pin.line, pin.bias),
+                );
+                match pins {
+                    Ok(pins) => run_phone(&cfg, sip, pins, input_send, input_recv),
+                    Err(e) => panic!("Could not open GPIO pins: {}", e),
+                }
+            }
+            GpioBackend::Remote => {
+                let remote = match RemoteEnvironment::bind(Path::new(&cfg.gpio.socket)) {
+                    Ok(remote) => remote,
+                    Err(e) => panic!("Could not create {}: {}", cfg.gpio.socket, e),
+                };
+                let pins = cfg.gpio.open(
+                    cfg.dial.mode,
+                    |_, pin| remote.create_input_pin(pin.line),
+                    |_, pin| remote.create_output_pin(pin.line),
+                );
+                match pins {
+                    Ok(pins) => run_phone(&cfg, sip, pins, input_send, input_recv),
+                    Err(e) => panic!("Could not open GPIO pins: {}", e),
+                }
+            }
+        }
+    };
+}
 
-        let _dial = Dial::new::<SysfsInputPin>(nsa, nsi, input_send.clone());
-        let _earpiece = Earpiece::new::<SysfsInputPin>(hook, input_send);
-        state_machine.run();
+/// Runs the state machine with the phone hardware connected via the specified
+/// pins.
+fn run_phone<In: InputPin + Send + 'static, Out: OutputPin + Send + 'static>(
+    cfg: &Config,
+    sip: Sip,
+    pins: PhonePins<In, Out>,
+    sender: Sender<Event>,
+    receiver: Receiver<Event>,
+) -> !
+where
+    In::Group: Send + 'static,
+{
+    let (_dial, _keypad) = match pins.dial {
+        DialPins::Rotary { nsa, nsi } => {
+            let dial = match nsa {
+                Some(nsa) => Dial::new(nsa, nsi, cfg.dial.clone(), SystemClock, sender.clone()),
+                None => Dial::new_nsi_only(nsi, cfg.dial.clone(), SystemClock, sender.clone()),
+            };
+            (Some(dial), None)
+        }
+        DialPins::Keypad { rows, columns } => {
+            let columns = In::create_group(columns.into_iter().map(Box::new).collect());
+            let keypad = Keypad::new(rows, columns, cfg.keypad.clone(), sender.clone());
+            (None, Some(keypad))
+        }
     };
+    let _earth_key = pins
+        .earth_key
+        .map(|key| EarthKey::new(key, cfg.earth_key.clone(), sender.clone()));
+    let _earpiece = Earpiece::new(pins.hook, cfg.earpiece.clone(), SystemClock, sender);
+    let ringer = Ringer::new(pins.ring, cfg.ring_cadence.clone(), SystemClock);
+    let mut state_machine = StateMachine::new(
+        receiver,
+        sip,
+        ringer,
+        cfg.call.clone(),
+        cfg.dial_plan.clone(),
+    );
+    state_machine.run();
 }
@@ -0,0 +1,203 @@
+//! Type which rings the bell of the phone.
+
+use super::clock::{Clock, Timer};
+use super::gpio::OutputPin;
+use super::state::Bell;
+
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// Ring cadence, i.e., the pattern in which the bell is switched on and off.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Cadence {
+    /// Alternating on and off durations in milliseconds, starting with an "on"
+    /// period. The pattern is repeated until the bell is stopped.
+    pub pattern_ms: Vec<u64>,
+}
+
+impl Cadence {
+    /// German ring cadence (1s on, 4s off).
+    pub fn german() -> Self {
+        Self {
+            pattern_ms: vec![1000, 4000],
+        }
+    }
+}
+
+impl Default for Cadence {
+    fn default() -> Self {
+        Self::german()
+    }
+}
+
+struct RingerState {
+    ringing: bool,
+    stop_thread: bool,
+}
+
+/// Interface to the bell.
+///
+/// The bell is driven by a single output pin, where a value of `true` means
+/// that the bell is ringing. The cadence is generated by a separate thread and
+/// timed according to the specified clock.
+pub struct Ringer {
+    thread: Option<JoinHandle<()>>,
+    state: Arc<Mutex<RingerState>>,
+    timer: Arc<dyn Timer>,
+}
+
+impl Ringer {
+    pub fn new<Pin: OutputPin + Send + 'static, C: Clock>(
+        bell: Pin,
+        cadence: Cadence,
+        clock: C,
+    ) -> Self {
+        assert!(
+            cadence.pattern_ms.iter().any(|&duration| duration != 0),
+            "ring cadence must not be empty"
+        );
+        let state = Arc::new(Mutex::new(RingerState {
+            ringing: false,
+            stop_thread: false,
+        }));
+        let timer: Arc<dyn Timer> = Arc::new(clock.timer());
+        let state_copy = state.clone();
+        let timer_copy = timer.clone();
+        let thread = thread::spawn(move || {
+            bell.write(false);
+            loop {
+                // Wait until the bell shall ring.
+                loop {
+                    let state = state_copy.lock().unwrap();
+                    if state.stop_thread {
+                        return;
+                    }
+                    if state.ringing {
+                        break;
+                    }
+                    drop(state);
+                    timer_copy.wait_until(None);
+                }
+
+                // Play the cadence until the bell is stopped.
+                'cadence: loop {
+                    for (i, &duration) in cadence.pattern_ms.iter().enumerate() {
+                        bell.write(i % 2 == 0);
+                        let end = clock.now() + Duration::from_millis(duration);
+                        loop {
+                            {
+                                let state = state_copy.lock().unwrap();
+                                if !state.ringing || state.stop_thread {
+                                    break 'cadence;
+                                }
+                            }
+                            if clock.now() >= end {
+                                break;
+                            }
+                            timer_copy.wait_until(Some(end));
+                        }
+                    }
+                }
+                bell.write(false);
+            }
+        });
+        Self {
+            thread: Some(thread),
+            state,
+            timer,
+        }
+    }
+
+    /// Starts ringing the bell, beginning at the start of the cadence.
+    ///
+    /// The call has no effect if the bell is already ringing.
+    pub fn start(&self) {
+        self.set_ringing(true);
+    }
+
+    /// Stops ringing the bell.
+    pub fn stop(&self) {
+        self.set_ringing(false);
+    }
+
+    fn set_ringing(&self, ringing: bool) {
+        self.state.lock().unwrap().ringing = ringing;
+        self.timer.wake();
+    }
+}
+
+impl Bell for Ringer {
+    fn start_ringing(&mut self) {
+        self.start();
+    }
+    fn stop_ringing(&mut self) {
+        self.stop();
+    }
+}
+
+impl Drop for Ringer {
+    fn drop(&mut self) {
+        self.state.lock().unwrap().stop_thread = true;
+        self.timer.wake();
+        let thread = self.thread.take();
+        thread.unwrap().join().unwrap();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gpio::sim::SimEnvironment;
+
+    const BELL_PIN: usize = 0;
+
+    #[test]
+    fn test_ringer() {
+        let env = SimEnvironment::with_virtual_time();
+        let bell = env.create_output_pin(BELL_PIN, false);
+
+        let cadence = Cadence {
+            pattern_ms: vec![40, 60],
+        };
+        let ringer = Ringer::new(bell, cadence, env.clock());
+        env.wait_for_listeners(1);
+
+        env.advance(Duration::from_millis(20));
+        assert!(!env.read_output(BELL_PIN));
+
+        // The cadence starts immediately and is repeated.
+        ringer.start();
+        env.settle();
+        assert!(env.read_output(BELL_PIN));
+        env.advance(Duration::from_millis(39));
+        assert!(env.read_output(BELL_PIN));
+        env.advance(Duration::from_millis(1));
+        assert!(!env.read_output(BELL_PIN));
+        env.advance(Duration::from_millis(59));
+        assert!(!env.read_output(BELL_PIN));
+        env.advance(Duration::from_millis(1));
+        assert!(env.read_output(BELL_PIN));
+
+        // Stopping the bell immediately switches it off.
+        env.advance(Duration::from_millis(10));
+        ringer.stop();
+        env.settle();
+        assert!(!env.read_output(BELL_PIN));
+        env.advance(Duration::from_millis(200));
+        assert!(!env.read_output(BELL_PIN));
+
+        // Restarting the bell begins at the start of the cadence.
+        ringer.start();
+        env.settle();
+        assert!(env.read_output(BELL_PIN));
+        env.advance(Duration::from_millis(40));
+        assert!(!env.read_output(BELL_PIN));
+        env.advance(Duration::from_millis(60));
+        assert!(env.read_output(BELL_PIN));
+
+        // Dropping a ringing bell switches it off as well.
+        drop(ringer);
+        assert!(!env.read_output(BELL_PIN));
+    }
+}
@@ -0,0 +1,558 @@
+//! Scripted hardware scenarios which are replayed in a simulated environment.
+//!
+//! A scenario describes what the user does with the phone, e.g., "lift the
+//! handset, dial 030, hang up after 5s", and which events the application is
+//! expected to see. The runner drives the inputs of a `SimEnvironment` in
+//! virtual time, feeds them to `Dial` and `Earpiece`, and records their events
+//! as well as the output of the `Ringer`.
+//!
+//! Scenarios are text files with one command per line, `#` starts a comment:
+//!
+//! ```text
+//! pick_up                      # earpiece off the hook
+//! hang_up                      # earpiece on the hook
+//! wait <ms>
+//! dial <digits> [pulse <ms>] [pause <ms>] [gap <ms>]
+//! flash <ms>                   # earpiece on the hook for the specified time
+//! hook_dial <digits> [pulse <ms>] [pause <ms>] [gap <ms>]
+//! input <nsa|nsi|hook> <0|1>   # raw contact state, 1 means closed
+//! ring                         # start ringing the bell
+//! stop_ring
+//! expect <record> [at <ms>]
+//! ```
+//!
+//! `expect` checks the next record which has not been checked yet, optionally
+//! including the time since the start of the scenario. Records are
+//! `picked_up`, `put_down`, `hook_flash`, `dialed <digit>`,
+//! `rejected <reason> <value>` and `ring <on|off>`, where the bell follows the
+//! German cadence (1s on, 4s off). Once the scenario has ended, all records
+//! have to have been checked. Scenario files in the `scenarios` directory are
+//! run as part of the tests.
+
+use super::clock::Clock;
+use super::dial::{Dial, DialConfig, DialRejection};
+use super::earpiece::{Earpiece, EarpieceConfig};
+use super::gpio::sim::SimEnvironment;
+use super::ringer::{Cadence, Ringer};
+use super::Event;
+
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::mpsc::{channel, Receiver};
+use std::time::{Duration, Instant};
+
+const NSA_PIN: usize = 0;
+const NSI_PIN: usize = 1;
+const HOOK_PIN: usize = 2;
+const RING_PIN: usize = 3;
+
+/// Something the phone hardware did during a scenario.
+#[derive(Debug, PartialEq)]
+pub enum Record {
+    /// Event generated by `Dial` or `Earpiece`.
+    Event(Event),
+    /// The bell output was switched on or off.
+    Ring(bool),
+}
+
+impl fmt::Display for Record {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Record::Event(Event::EarpiecePickedUp) => write!(f, "picked_up"),
+            Record::Event(Event::EarpiecePutDown) => write!(f, "put_down"),
+            Record::Event(Event::HookFlash) => write!(f, "hook_flash"),
+            Record::Event(Event::Dialed(digit)) => write!(f, "dialed {}", digit),
+            Record::Event(Event::DialRejected(rejection)) => {
+                let (reason, value) = match rejection {
+                    DialRejection::PulseTooShort(d) => ("pulse_too_short", d.as_millis()),
+                    DialRejection::PulseTooLong(d) => ("pulse_too_long", d.as_millis()),
+                    DialRejection::PauseTooShort(d) => ("pause_too_short", d.as_millis()),
+                    DialRejection::PauseTooLong(d) => ("pause_too_long", d.as_millis()),
+                    DialRejection::TooManyPulses(count) => ("too_many_pulses", *count as u128),
+                };
+                write!(f, "rejected {} {}", reason, value)
+            }
+            Record::Event(event) => write!(f, "{:?}", event),
+            Record::Ring(on) => write!(f, "ring {}", if *on { "on" } else { "off" }),
+        }
+    }
+}
+
+/// Error in a scenario file or expectation which was not met.
+#[derive(Debug)]
+pub struct ScenarioError {
+    /// Line of the scenario file.
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ScenarioError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+/// Timing of a digit dialed via the rotary dial or the hook switch.
+#[derive(Debug, Clone, PartialEq)]
+struct PulseTiming {
+    pulse_ms: u64,
+    pause_ms: u64,
+    /// Time after the digit before the next one is dialed.
+    gap_ms: u64,
+}
+
+#[derive(Debug, PartialEq)]
+enum Step {
+    Input(usize, bool),
+    Wait(u64),
+    Dial(Vec<u32>, PulseTiming),
+    HookDial(Vec<u32>, PulseTiming),
+    Ring(bool),
+    Expect(Record, Option<u64>),
+}
+
+/// Parsed scenario.
+#[derive(Debug)]
+pub struct Scenario {
+    /// Steps along with their line numbers.
+    steps: Vec<(usize, Step)>,
+}
+
+impl Scenario {
+    pub fn load(path: &Path) -> Result<Self, io::Error> {
+        let text = fs::read_to_string(path)?;
+        Self::parse(&text).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))
+    }
+
+    pub fn parse(text: &str) -> Result<Self, ScenarioError> {
+        let mut steps = Vec::new();
+        for (i, line) in text.lines().enumerate() {
+            let line_number = i + 1;
+            let line = line.split('#').next().unwrap();
+            let words = line.split_whitespace().collect::<Vec<_>>();
+            if words.is_empty() {
+                continue;
+            }
+            let step = parse_step(&words).map_err(|message| ScenarioError {
+                line: line_number,
+                message,
+            })?;
+            steps.push((line_number, step));
+        }
+        Ok(Self { steps })
+    }
+
+    /// Replays the scenario and returns the records along with the time since
+    /// the start of the scenario.
+    ///
+    /// The function fails if an expectation is not met.
+    pub fn run(&self) -> Result<Vec<(Duration, Record)>, ScenarioError> {
+        let mut runner = Runner::new();
+        let mut checked = 0;
+        for (line, step) in self.steps.iter() {
+            let error = |message: String| ScenarioError {
+                line: *line,
+                message,
+            };
+            match step {
+                Step::Expect(expected, at) => {
+                    let (time, record) = match runner.records.get(checked) {
+                        Some(record) => record,
+                        None => return Err(error(format!("expected {}, got nothing", expected))),
+                    };
+                    if record != expected {
+                        return Err(error(format!("expected {}, got {}", expected, record)));
+                    }
+                    if let Some(at) = at {
+                        if time.as_millis() != *at as u128 {
+                            return Err(error(format!(
+                                "expected {} at {}ms, got it at {}ms",
+                                expected,
+                                at,
+                                time.as_millis()
+                            )));
+                        }
+                    }
+                    checked += 1;
+                }
+                step => runner.run_step(step).map_err(error)?,
+            }
+        }
+        let records = runner.finish();
+        if let Some((time, record)) = records.get(checked) {
+            return Err(ScenarioError {
+                line: self.steps.last().map_or(0, |(line, _)| *line),
+                message: format!("unexpected {} at {}ms", record, time.as_millis()),
+            });
+        }
+        Ok(records)
+    }
+}
+
+fn parse_step(words: &[&str]) -> Result<Step, String> {
+    let args = &words[1..];
+    let step = match words[0] {
+        "pick_up" => no_args(args, Step::Input(HOOK_PIN, true))?,
+        "hang_up" => no_args(args, Step::Input(HOOK_PIN, false))?,
+        "wait" => Step::Wait(parse_ms(single(args)?)?),
+        "input" => {
+            if args.len() != 2 {
+                return Err("input requires a contact and a value".to_string());
+            }
+            let pin = match args[0] {
+                "nsa" => NSA_PIN,
+                "nsi" => NSI_PIN,
+                "hook" => HOOK_PIN,
+                other => return Err(format!("unknown contact \"{}\"", other)),
+            };
+            let value = match args[1] {
+                "0" => false,
+                "1" => true,
+                other => return Err(format!("invalid contact state \"{}\"", other)),
+            };
+            Step::Input(pin, value)
+        }
+        "dial" => {
+            let (digits, timing) = parse_digits(args, 60, 40, 500)?;
+            Step::Dial(digits, timing)
+        }
+        "hook_dial" => {
+            let (digits, timing) = parse_digits(args, 60, 40, 500)?;
+            Step::HookDial(digits, timing)
+        }
+        "flash" => {
+            // A flash is a single on-hook period.
+            let timing = PulseTiming {
+                pulse_ms: parse_ms(single(args)?)?,
+                pause_ms: 0,
+                gap_ms: 0,
+            };
+            Step::HookDial(vec![1], timing)
+        }
+        "ring" => no_args(args, Step::Ring(true))?,
+        "stop_ring" => no_args(args, Step::Ring(false))?,
+        "expect" => {
+            let (record, at) = match args.iter().position(|&word| word == "at") {
+                Some(i) => (&args[..i], Some(parse_ms(single(&args[i + 1..])?)?)),
+                None => (args, None),
+            };
+            Step::Expect(parse_record(record)?, at)
+        }
+        other => return Err(format!("unknown command \"{}\"", other)),
+    };
+    Ok(step)
+}
+
+fn no_args(args: &[&str], step: Step) -> Result<Step, String> {
+    if args.is_empty() {
+        Ok(step)
+    } else {
+        Err("the command does not take arguments".to_string())
+    }
+}
+
+fn single<'a>(args: &[&'a str]) -> Result<&'a str, String> {
+    match args {
+        [arg] => Ok(arg),
+        _ => Err("expected a single argument".to_string()),
+    }
+}
+
+fn parse_ms(word: &str) -> Result<u64, String> {
+    word.parse()
+        .map_err(|_| format!("invalid number of milliseconds \"{}\"", word))
+}
+
+fn parse_digits(
+    args: &[&str],
+    pulse_ms: u64,
+    pause_ms: u64,
+    gap_ms: u64,
+) -> Result<(Vec<u32>, PulseTiming), String> {
+    let digits = match args.first() {
+        Some(digits) => digits
+            .chars()
+            .map(|c| c.to_digit(10).ok_or(format!("invalid digit '{}'", c)))
+            .collect::<Result<Vec<_>, _>>()?,
+        None => return Err("no digits specified".to_string()),
+    };
+    let mut timing = PulseTiming {
+        pulse_ms,
+        pause_ms,
+        gap_ms,
+    };
+    let mut options = args[1..].iter();
+    while let Some(&option) = options.next() {
+        let value = match options.next() {
+            Some(value) => parse_ms(value)?,
+            None => return Err(format!("missing value for \"{}\"", option)),
+        };
+        match option {
+            "pulse" => timing.pulse_ms = value,
+            "pause" => timing.pause_ms = value,
+            "gap" => timing.gap_ms = value,
+            other => return Err(format!("unknown option \"{}\"", other)),
+        }
+    }
+    Ok((digits, timing))
+}
+
+fn parse_record(words: &[&str]) -> Result<Record, String> {
+    let record = match words {
+        ["picked_up"] => Record::Event(Event::EarpiecePickedUp),
+        ["put_down"] => Record::Event(Event::EarpiecePutDown),
+        ["hook_flash"] => Record::Event(Event::HookFlash),
+        ["dialed", digit] => match digit.parse() {
+            Ok(digit) if digit < 10 => Record::Event(Event::Dialed(digit)),
+            _ => return Err(format!("invalid digit \"{}\"", digit)),
+        },
+        ["rejected", reason, value] => {
+            let value = parse_ms(value)?;
+            let duration = Duration::from_millis(value);
+            let rejection = match *reason {
+                "pulse_too_short" => DialRejection::PulseTooShort(duration),
+                "pulse_too_long" => DialRejection::PulseTooLong(duration),
+                "pause_too_short" => DialRejection::PauseTooShort(duration),
+                "pause_too_long" => DialRejection::PauseTooLong(duration),
+                "too_many_pulses" => DialRejection::TooManyPulses(value as u32),
+                other => return Err(format!("unknown rejection \"{}\"", other)),
+            };
+            Record::Event(Event::DialRejected(rejection))
+        }
+        ["ring", "on"] => Record::Ring(true),
+        ["ring", "off"] => Record::Ring(false),
+        _ => return Err(format!("invalid record \"{}\"", words.join(" "))),
+    };
+    Ok(record)
+}
+
+/// Simulated phone hardware which executes the steps of a scenario.
+struct Runner {
+    env: SimEnvironment,
+    start: Instant,
+    dial: Option<Dial>,
+    earpiece: Option<Earpiece>,
+    ringer: Ringer,
+    events: Receiver<Event>,
+    ringing: bool,
+    records: Vec<(Duration, Record)>,
+}
+
+impl Runner {
+    fn new() -> Self {
+        let env = SimEnvironment::with_virtual_time();
+        let nsa = env.create_input_pin(NSA_PIN, false);
+        let nsi = env.create_input_pin(NSI_PIN, false);
+        let hook = env.create_input_pin(HOOK_PIN, false);
+        let bell = env.create_output_pin(RING_PIN, false);
+        // The nsi contact is closed while the dial is at rest.
+        env.write_input(NSI_PIN, true);
+
+        let (sender, events) = channel();
+        let dial = Dial::new(nsa, nsi, DialConfig::default(), env.clock(), sender.clone());
+        let earpiece = Earpiece::new(hook, EarpieceConfig::default(), env.clock(), sender);
+        let ringer = Ringer::new(bell, Cadence::german(), env.clock());
+        env.wait_for_listeners(3);
+        Self {
+            start: env.clock().now(),
+            env,
+            dial: Some(dial),
+            earpiece: Some(earpiece),
+            ringer,
+            events,
+            ringing: false,
+            records: Vec::new(),
+        }
+    }
+
+    fn run_step(&mut self, step: &Step) -> Result<(), String> {
+        match step {
+            Step::Input(pin, value) => {
+                self.env.write_input(*pin, *value);
+                self.collect();
+            }
+            Step::Wait(ms) => self.wait(*ms),
+            Step::Dial(digits, timing) => {
+                for &digit in digits {
+                    self.env.write_input(NSA_PIN, true);
+                    self.wait(100);
+                    self.pulses(NSI_PIN, digit, timing);
+                    self.env.write_input(NSA_PIN, false);
+                    self.wait(timing.gap_ms);
+                }
+            }
+            Step::HookDial(digits, timing) => {
+                for &digit in digits {
+                    self.pulses(HOOK_PIN, digit, timing);
+                    self.wait(timing.gap_ms);
+                }
+            }
+            Step::Ring(ring) => {
+                if *ring {
+                    self.ringer.start();
+                } else {
+                    self.ringer.stop();
+                }
+                self.env.settle();
+                self.collect();
+            }
+            Step::Expect(_, _) => unreachable!(),
+        }
+        Ok(())
+    }
+
+    /// Opens a closed contact once for each pulse of the digit.
+    fn pulses(&mut self, pin: usize, digit: u32, timing: &PulseTiming) {
+        let count = if digit == 0 { 10 } else { digit };
+        for _ in 0..count {
+            self.env.write_input(pin, false);
+            self.wait(timing.pulse_ms);
+            self.env.write_input(pin, true);
+            self.wait(timing.pause_ms);
+        }
+    }
+
+    /// Advances the virtual time in steps of 1ms so that the records are
+    /// timestamped with millisecond precision.
+    fn wait(&mut self, ms: u64) {
+        for _ in 0..ms {
+            self.env.advance(Duration::from_millis(1));
+            self.collect();
+        }
+    }
+
+    fn collect(&mut self) {
+        let time = self.env.clock().now() - self.start;
+        while let Ok(event) = self.events.try_recv() {
+            self.records.push((time, Record::Event(event)));
+        }
+        let ringing = self.env.read_output(RING_PIN);
+        if ringing != self.ringing {
+            self.ringing = ringing;
+            self.records.push((time, Record::Ring(ringing)));
+        }
+    }
+
+    /// Stops the threads and returns all records.
+    fn finish(mut self) -> Vec<(Duration, Record)> {
+        self.collect();
+        self.dial.take();
+        self.earpiece.take();
+        self.records
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let scenario = Scenario::parse(
+            "# Comment\n\
+             \n\
+             pick_up\n\
+             dial 03 pulse 70 gap 300 # slow dial\n\
+             expect dialed 3 at 1250\n\
+             expect rejected pulse_too_long 120\n\
+             flash 300\n\
+             input nsa 1\n",
+        )
+        .unwrap();
+        let timing = PulseTiming {
+            pulse_ms: 70,
+            pause_ms: 40,
+            gap_ms: 300,
+        };
+        assert_eq!(
+            scenario.steps,
+            vec![
+                (3, Step::Input(HOOK_PIN, true)),
+                (4, Step::Dial(vec![0, 3], timing)),
+                (5, Step::Expect(Record::Event(Event::Dialed(3)), Some(1250))),
+                (
+                    6,
+                    Step::Expect(
+                        Record::Event(Event::DialRejected(DialRejection::PulseTooLong(
+                            Duration::from_millis(120)
+                        ))),
+                        None
+                    )
+                ),
+                (
+                    7,
+                    Step::HookDial(
+                        vec![1],
+                        PulseTiming {
+                            pulse_ms: 300,
+                            pause_ms: 0,
+                            gap_ms: 0
+                        }
+                    )
+                ),
+                (8, Step::Input(NSA_PIN, true)),
+            ]
+        );
+
+        let error = |text| Scenario::parse(text).unwrap_err().to_string();
+        assert_eq!(error("wait\n"), "line 1: expected a single argument");
+        assert_eq!(error("\ndial 0x\n"), "line 2: invalid digit 'x'");
+        assert_eq!(
+            error("pick_up now\n"),
+            "line 1: the command does not take arguments"
+        );
+        assert_eq!(
+            error("expect dialed\n"),
+            "line 1: invalid record \"dialed\""
+        );
+        assert_eq!(error("lift\n"), "line 1: unknown command \"lift\"");
+    }
+
+    #[test]
+    fn test_run() {
+        let scenario = Scenario::parse(
+            "pick_up\n\
+             wait 20\n\
+             expect picked_up at 10\n\
+             dial 2\n\
+             expect dialed 2 at 325\n",
+        )
+        .unwrap();
+        // The digit is reported once the nsa contact has been open for the
+        // debounce time: 20ms + 100ms + 2 * (60ms + 40ms) + 5ms.
+        let records = scenario.run().unwrap();
+        assert_eq!(records.len(), 2);
+
+        // Missing and unexpected records are reported.
+        let scenario = Scenario::parse("pick_up\nwait 20\nexpect put_down\n").unwrap();
+        assert_eq!(
+            scenario.run().unwrap_err().to_string(),
+            "line 3: expected put_down, got picked_up"
+        );
+        let scenario = Scenario::parse("pick_up\nwait 20\n").unwrap();
+        assert_eq!(
+            scenario.run().unwrap_err().to_string(),
+            "line 2: unexpected picked_up at 10ms"
+        );
+    }
+
+    #[test]
+    fn test_scenario_files() {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("scenarios");
+        let mut paths = fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| path.extension().is_some_and(|ext| ext == "scenario"))
+            .collect::<Vec<_>>();
+        paths.sort();
+        assert!(!paths.is_empty());
+        for path in paths {
+            let scenario = Scenario::load(&path).unwrap();
+            if let Err(e) = scenario.run() {
+                panic!("{}: {}", path.display(), e);
+            }
+        }
+    }
+}
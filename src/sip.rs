@@ -1,137 +1,534 @@
+use super::state::Telephony;
+use super::tones::{Tone, ToneCountry};
+use super::Event;
+
 use pjproject::*;
 
 use std::ffi::{CStr, CString};
 use std::fmt;
 use std::mem;
+use std::sync::mpsc::Sender;
+use std::sync::Mutex;
 
-pub struct Sip {
-    account_id: pjsua_acc_id,
+/// Channel to the state machine used by the pjsua callbacks.
+///
+/// The callbacks do not receive any user data, but there can only be a single
+/// pjsua instance per process, so a global is sufficient.
+static EVENT_SENDER: Mutex<Option<Sender<Event>>> = Mutex::new(None);
+
+/// Sends an event from a pjsua callback to the state machine.
+fn send_event(event: Event) {
+    // The callbacks must never panic, as unwinding into C code is not allowed.
+    if let Ok(sender) = EVENT_SENDER.lock() {
+        if let Some(sender) = sender.as_ref() {
+            sender.send(event).ok();
+        }
+    }
 }
 
-impl Sip {
-    pub fn new(domain: &str, user: &str, password: &str) -> Result<Sip, Error> {
-        let account_id = unsafe {
-            let status = pjsua_create();
+/// State of a call (see `pjsip_inv_state`).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CallState {
+    Null,
+    Calling,
+    Incoming,
+    Early,
+    Connecting,
+    Confirmed,
+    Disconnected,
+}
+
+impl CallState {
+    #[allow(non_upper_case_globals)]
+    fn from_pjsip(state: pjsip_inv_state) -> CallState {
+        match state {
+            pjsip_inv_state_PJSIP_INV_STATE_CALLING => CallState::Calling,
+            pjsip_inv_state_PJSIP_INV_STATE_INCOMING => CallState::Incoming,
+            pjsip_inv_state_PJSIP_INV_STATE_EARLY => CallState::Early,
+            pjsip_inv_state_PJSIP_INV_STATE_CONNECTING => CallState::Connecting,
+            pjsip_inv_state_PJSIP_INV_STATE_CONFIRMED => CallState::Confirmed,
+            pjsip_inv_state_PJSIP_INV_STATE_DISCONNECTED => CallState::Disconnected,
+            _ => CallState::Null,
+        }
+    }
+}
+
+/// Information about a call.
+#[derive(Debug, Clone)]
+pub struct CallInfo {
+    pub state: CallState,
+    /// Textual description of the state.
+    pub state_text: String,
+    /// URI of the remote party.
+    pub remote_info: String,
+    /// Last SIP status code of the call.
+    pub last_status: u32,
+}
+
+/// Handle to an incoming or outgoing call.
+///
+/// The handle is only an identifier, so it stays valid after the call has
+/// ended, but then all operations fail.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Call {
+    id: pjsua_call_id,
+}
+
+impl Call {
+    /// Wraps a pjsua call ID.
+    pub fn from_id(id: pjsua_call_id) -> Call {
+        Call { id }
+    }
+
+    pub fn id(&self) -> pjsua_call_id {
+        self.id
+    }
+
+    /// Returns information about the call.
+    pub fn info(&self) -> Result<CallInfo, Error> {
+        unsafe {
+            let mut call_info: pjsua_call_info = mem::zeroed();
+            let status = pjsua_call_get_info(self.id, &mut call_info);
             if status != pj_constants__PJ_SUCCESS as pj_status_t {
                 return Err(Error {
-                    message: "pjsua_create".to_string(),
+                    message: "pjsua_call_get_info".to_string(),
                     status,
                 });
             }
+            Ok(CallInfo {
+                state: CallState::from_pjsip(call_info.state),
+                state_text: pj_str_to_string(call_info.state_text),
+                remote_info: pj_str_to_string(call_info.remote_info),
+                last_status: call_info.last_status as u32,
+            })
+        }
+    }
 
-            // Initialize pjsua.
-            let mut config: pjsua_config = mem::uninitialized();
-            pjsua_config_default(&mut config);
+    pub fn state(&self) -> Result<CallState, Error> {
+        Ok(self.info()?.state)
+    }
 
-            config.cb.on_incoming_call = Some(Self::on_incoming_call);
-            config.cb.on_call_media_state = Some(Self::on_call_media_state);
-            config.cb.on_call_state = Some(Self::on_call_state);
-            config.cb.on_reg_state2 = Some(Self::on_reg_state);
+    /// Returns the URI of the remote party.
+    pub fn remote_info(&self) -> Result<String, Error> {
+        Ok(self.info()?.remote_info)
+    }
 
-            let mut log_config: pjsua_logging_config = mem::uninitialized();
-            pjsua_logging_config_default(&mut log_config);
-            log_config.console_level = 4;
+    /// Sends a response with the specified status code to an incoming call.
+    ///
+    /// Provisional responses (e.g., 180) can be followed by a final response
+    /// later.
+    pub fn answer(&self, code: u32) -> Result<(), Error> {
+        let status =
+            unsafe { pjsua_call_answer(self.id, code, std::ptr::null(), std::ptr::null()) };
+        if status != pj_constants__PJ_SUCCESS as pj_status_t {
+            return Err(Error {
+                message: "pjsua_call_answer".to_string(),
+                status,
+            });
+        }
+        Ok(())
+    }
 
-            let status = pjsua_init(&config, &log_config, std::ptr::null());
-            if status != pj_constants__PJ_SUCCESS as pj_status_t {
-                pjsua_destroy();
+    /// Puts the call on hold.
+    pub fn hold(&self) -> Result<(), Error> {
+        let status = unsafe { pjsua_call_set_hold(self.id, std::ptr::null()) };
+        if status != pj_constants__PJ_SUCCESS as pj_status_t {
+            return Err(Error {
+                message: "pjsua_call_set_hold".to_string(),
+                status,
+            });
+        }
+        Ok(())
+    }
+
+    /// Resumes a call which has been put on hold.
+    pub fn unhold(&self) -> Result<(), Error> {
+        let status = unsafe {
+            pjsua_call_reinvite(self.id, pjsua_call_flag_PJSUA_CALL_UNHOLD, std::ptr::null())
+        };
+        if status != pj_constants__PJ_SUCCESS as pj_status_t {
+            return Err(Error {
+                message: "pjsua_call_reinvite".to_string(),
+                status,
+            });
+        }
+        Ok(())
+    }
+
+    /// Connects the remote party of this call with the remote party of `dest`
+    /// (attended transfer). Both calls are ended by the remote parties
+    /// afterwards.
+    pub fn transfer_replaces(&self, dest: Call) -> Result<(), Error> {
+        let status = unsafe { pjsua_call_xfer_replaces(self.id, dest.id, 0, std::ptr::null()) };
+        if status != pj_constants__PJ_SUCCESS as pj_status_t {
+            return Err(Error {
+                message: "pjsua_call_xfer_replaces".to_string(),
+                status,
+            });
+        }
+        Ok(())
+    }
+
+    /// Terminates the call.
+    ///
+    /// If the call has not been answered yet, `code` is sent as the final
+    /// response. A value of 0 lets pjsua choose an appropriate code.
+    pub fn hangup(&self, code: u32) -> Result<(), Error> {
+        let status =
+            unsafe { pjsua_call_hangup(self.id, code, std::ptr::null(), std::ptr::null()) };
+        if status != pj_constants__PJ_SUCCESS as pj_status_t {
+            return Err(Error {
+                message: "pjsua_call_hangup".to_string(),
+                status,
+            });
+        }
+        Ok(())
+    }
+}
+
+/// Clock rate of the tone generator.
+const TONE_CLOCK_RATE: u32 = 16000;
+/// Samples per frame of the tone generator (20ms).
+const TONE_SAMPLES_PER_FRAME: u32 = TONE_CLOCK_RATE / 50;
+
+/// Tone generator port connected to the sound device via the conference
+/// bridge.
+struct ToneGenerator {
+    pool: *mut pj_pool_t,
+    port: *mut pjmedia_port,
+    slot: pjsua_conf_port_id,
+    country: ToneCountry,
+    /// Whether the port is currently connected to the sound device.
+    connected: bool,
+}
+
+impl ToneGenerator {
+    /// Creates the tone generator. pjsua has to be initialized.
+    fn new(country: ToneCountry) -> Result<ToneGenerator, Error> {
+        unsafe {
+            let name = CString::new("tonegen").unwrap();
+            let pool = pjsua_pool_create(name.as_ptr(), 512, 512);
+            if pool.is_null() {
                 return Err(Error {
-                    message: "pjsua_init".to_string(),
-                    status,
+                    message: "pjsua_pool_create".to_string(),
+                    status: PJ_ENOMEM as pj_status_t,
                 });
             }
-
-            // Add UDP transport.
-            let mut config: pjsua_transport_config = mem::uninitialized();
-            pjsua_transport_config_default(&mut config);
-            config.port = 5060;
-            let status = pjsua_transport_create(
-                pjsip_transport_type_e_PJSIP_TRANSPORT_UDP,
-                &config,
-                std::ptr::null::<i32>() as *mut _,
+            let mut port: *mut pjmedia_port = std::ptr::null_mut();
+            let status = pjmedia_tonegen_create(
+                pool,
+                TONE_CLOCK_RATE,
+                1,
+                TONE_SAMPLES_PER_FRAME,
+                16,
+                0,
+                &mut port,
             );
             if status != pj_constants__PJ_SUCCESS as pj_status_t {
-                pjsua_destroy();
+                pj_pool_release(pool);
                 return Err(Error {
-                    message: "pjsua_transport_create".to_string(),
+                    message: "pjmedia_tonegen_create".to_string(),
                     status,
                 });
             }
-
-            // Start pjsua.
-            let status = pjsua_start();
+            let mut slot: pjsua_conf_port_id = -1;
+            let status = pjsua_conf_add_port(pool, port, &mut slot);
             if status != pj_constants__PJ_SUCCESS as pj_status_t {
-                pjsua_destroy();
+                pjmedia_port_destroy(port);
+                pj_pool_release(pool);
                 return Err(Error {
-                    message: "pjsua_start".to_string(),
+                    message: "pjsua_conf_add_port".to_string(),
                     status,
                 });
             }
+            Ok(ToneGenerator {
+                pool,
+                port,
+                slot,
+                country,
+                connected: false,
+            })
+        }
+    }
 
-            let mut snd_devs: [pjmedia_snd_dev_info; 8] = mem::uninitialized();
-            let mut snd_dev_count = 8u32;
-            let status = pjsua_enum_snd_devs(snd_devs.as_mut_ptr(), &mut snd_dev_count);
+    /// Plays the tone in a loop until `stop()` is called.
+    fn play(&mut self, tone: Tone) -> Result<(), Error> {
+        self.stop();
+        let tones = self
+            .country
+            .segments(tone)
+            .iter()
+            .map(|segment| pjmedia_tone_desc {
+                freq1: segment.freq1 as i16,
+                freq2: segment.freq2 as i16,
+                on_msec: segment.on_ms as i16,
+                off_msec: segment.off_ms as i16,
+                volume: 0,
+                flags: 0,
+            })
+            .collect::<Vec<_>>();
+        unsafe {
+            let status = pjmedia_tonegen_play(
+                self.port,
+                tones.len() as u32,
+                tones.as_ptr(),
+                PJMEDIA_TONEGEN_LOOP,
+            );
             if status != pj_constants__PJ_SUCCESS as pj_status_t {
-                pjsua_destroy();
                 return Err(Error {
-                    message: "pjsua_enum_snd_devs".to_string(),
+                    message: "pjmedia_tonegen_play".to_string(),
                     status,
                 });
             }
-            println!("{} sound devices", snd_dev_count);
-            for i in 0..snd_dev_count {
-                println!(
-                    "sound device: {}",
-                    CStr::from_ptr(snd_devs[i as usize].name.as_ptr())
-                        .to_str()
-                        .unwrap()
-                );
-            }
-            //int dev_count;
-            //pjmedia_aud_dev_index dev_idx;
-            //pj_status_t status;
-            //dev_count = pjmedia_aud_dev_count();
-            //printf("Got %d audio devices\n", dev_count);
-            //for (dev_idx=0; dev_idx<dev_count; ++i) {
-            //pjmedia_aud_dev_info info;
-            //status = pjmedia_aud_dev_get_info(dev_idx, &info);
-            //printf("%d. %s (in=%d, out=%d)\n",
-            //dev_idx, info.name,
-            //info.input_count, info.output_count);
-            //}
-
-            // Register to the SIP server by creating an SIP account.
-            let mut config: pjsua_acc_config = mem::uninitialized();
-            pjsua_acc_config_default(&mut config);
-            let id = CString::new(format!("sip:{}@{}", user, domain)).unwrap();
-            config.id = c_str_to_pj_str(&id);
-            let reg_uri = CString::new(format!("sip:{}", domain)).unwrap();
-            config.reg_uri = c_str_to_pj_str(&reg_uri);
-            config.cred_count = 1;
-            let domain = CString::new("fritz.box").unwrap();
-            config.cred_info[0].realm = c_str_to_pj_str(&domain);
-            let scheme = CString::new("digest").unwrap();
-            config.cred_info[0].scheme = c_str_to_pj_str(&scheme);
-            let user = CString::new(user).unwrap();
-            config.cred_info[0].username = c_str_to_pj_str(&user);
-            config.cred_info[0].data_type =
-                pjsip_cred_data_type_PJSIP_CRED_DATA_PLAIN_PASSWD as i32;
-            let password = CString::new(password).unwrap();
-            config.cred_info[0].data = c_str_to_pj_str(&password);
-
-            let mut account_id: pjsua_acc_id = mem::uninitialized();
-            let status = pjsua_acc_add(&config, pj_constants__PJ_TRUE as i32, &mut account_id);
+            let status = pjsua_conf_connect(self.slot, 0);
             if status != pj_constants__PJ_SUCCESS as pj_status_t {
-                pjsua_destroy();
+                pjmedia_tonegen_stop(self.port);
                 return Err(Error {
-                    message: "pjsua_acc_add".to_string(),
+                    message: "pjsua_conf_connect".to_string(),
                     status,
                 });
             }
+        }
+        self.connected = true;
+        Ok(())
+    }
+
+    fn stop(&mut self) {
+        if !self.connected {
+            return;
+        }
+        unsafe {
+            pjsua_conf_disconnect(self.slot, 0);
+            pjmedia_tonegen_stop(self.port);
+        }
+        self.connected = false;
+    }
+}
+
+impl Drop for ToneGenerator {
+    fn drop(&mut self) {
+        self.stop();
+        unsafe {
+            pjsua_conf_remove_port(self.slot);
+            pjmedia_port_destroy(self.port);
+            pj_pool_release(self.pool);
+        }
+    }
+}
+
+pub struct Sip {
+    account_id: pjsua_acc_id,
+    domain: String,
+    /// Generator for call-progress tones. The option is only empty during
+    /// `drop()`, as the generator has to be destroyed before pjsua.
+    tone_generator: Option<ToneGenerator>,
+}
 
-            account_id
+impl Sip {
+    pub fn new(
+        domain: &str,
+        user: &str,
+        password: &str,
+        tones: ToneCountry,
+        sender: Sender<Event>,
+    ) -> Result<Sip, Error> {
+        *EVENT_SENDER.lock().unwrap() = Some(sender);
+        let result = unsafe { Self::start(domain, user, password, tones) };
+        if result.is_err() {
+            // No events can arrive, and a later instance sets its own sender.
+            *EVENT_SENDER.lock().unwrap() = None;
+        }
+        let (account_id, tone_generator) = result?;
+        Ok(Sip {
+            account_id,
+            domain: domain.to_string(),
+            tone_generator: Some(tone_generator),
+        })
+    }
+
+    /// Initializes and starts pjsua and registers the account.
+    ///
+    /// pjsua is destroyed again if any step fails.
+    unsafe fn start(
+        domain: &str,
+        user: &str,
+        password: &str,
+        tones: ToneCountry,
+    ) -> Result<(pjsua_acc_id, ToneGenerator), Error> {
+        let status = pjsua_create();
+        if status != pj_constants__PJ_SUCCESS as pj_status_t {
+            return Err(Error {
+                message: "pjsua_create".to_string(),
+                status,
+            });
+        }
+
+        // Initialize pjsua.
+        let mut config: pjsua_config = mem::uninitialized();
+        pjsua_config_default(&mut config);
+
+        config.cb.on_incoming_call = Some(Self::on_incoming_call);
+        config.cb.on_call_media_state = Some(Self::on_call_media_state);
+        config.cb.on_call_state = Some(Self::on_call_state);
+        config.cb.on_reg_state2 = Some(Self::on_reg_state);
+
+        let mut log_config: pjsua_logging_config = mem::uninitialized();
+        pjsua_logging_config_default(&mut log_config);
+        log_config.console_level = 4;
+
+        let status = pjsua_init(&config, &log_config, std::ptr::null());
+        if status != pj_constants__PJ_SUCCESS as pj_status_t {
+            pjsua_destroy();
+            return Err(Error {
+                message: "pjsua_init".to_string(),
+                status,
+            });
+        }
+
+        // Add UDP transport.
+        let mut config: pjsua_transport_config = mem::uninitialized();
+        pjsua_transport_config_default(&mut config);
+        config.port = 5060;
+        let status = pjsua_transport_create(
+            pjsip_transport_type_e_PJSIP_TRANSPORT_UDP,
+            &config,
+            std::ptr::null::<i32>() as *mut _,
+        );
+        if status != pj_constants__PJ_SUCCESS as pj_status_t {
+            pjsua_destroy();
+            return Err(Error {
+                message: "pjsua_transport_create".to_string(),
+                status,
+            });
+        }
+
+        // Start pjsua.
+        let status = pjsua_start();
+        if status != pj_constants__PJ_SUCCESS as pj_status_t {
+            pjsua_destroy();
+            return Err(Error {
+                message: "pjsua_start".to_string(),
+                status,
+            });
+        }
+
+        let mut snd_devs: [pjmedia_snd_dev_info; 8] = mem::uninitialized();
+        let mut snd_dev_count = 8u32;
+        let status = pjsua_enum_snd_devs(snd_devs.as_mut_ptr(), &mut snd_dev_count);
+        if status != pj_constants__PJ_SUCCESS as pj_status_t {
+            pjsua_destroy();
+            return Err(Error {
+                message: "pjsua_enum_snd_devs".to_string(),
+                status,
+            });
+        }
+        println!("{} sound devices", snd_dev_count);
+        for i in 0..snd_dev_count {
+            println!(
+                "sound device: {}",
+                CStr::from_ptr(snd_devs[i as usize].name.as_ptr())
+                    .to_str()
+                    .unwrap()
+            );
+        }
+        //int dev_count;
+        //pjmedia_aud_dev_index dev_idx;
+        //pj_status_t status;
+        //dev_count = pjmedia_aud_dev_count();
+        //printf("Got %d audio devices\n", dev_count);
+        //for (dev_idx=0; dev_idx<dev_count; ++i) {
+        //pjmedia_aud_dev_info info;
+        //status = pjmedia_aud_dev_get_info(dev_idx, &info);
+        //printf("%d. %s (in=%d, out=%d)\n",
+        //dev_idx, info.name,
+        //info.input_count, info.output_count);
+        //}
+
+        let tone_generator = match ToneGenerator::new(tones) {
+            Ok(tone_generator) => tone_generator,
+            Err(e) => {
+                pjsua_destroy();
+                return Err(e);
+            }
+        };
+
+        // Register to the SIP server by creating an SIP account.
+        let mut config: pjsua_acc_config = mem::uninitialized();
+        pjsua_acc_config_default(&mut config);
+        let id = CString::new(format!("sip:{}@{}", user, domain)).unwrap();
+        config.id = c_str_to_pj_str(&id);
+        let reg_uri = CString::new(format!("sip:{}", domain)).unwrap();
+        config.reg_uri = c_str_to_pj_str(&reg_uri);
+        config.cred_count = 1;
+        let domain = CString::new("fritz.box").unwrap();
+        config.cred_info[0].realm = c_str_to_pj_str(&domain);
+        let scheme = CString::new("digest").unwrap();
+        config.cred_info[0].scheme = c_str_to_pj_str(&scheme);
+        let user = CString::new(user).unwrap();
+        config.cred_info[0].username = c_str_to_pj_str(&user);
+        config.cred_info[0].data_type = pjsip_cred_data_type_PJSIP_CRED_DATA_PLAIN_PASSWD as i32;
+        let password = CString::new(password).unwrap();
+        config.cred_info[0].data = c_str_to_pj_str(&password);
+
+        let mut account_id: pjsua_acc_id = mem::uninitialized();
+        let status = pjsua_acc_add(&config, pj_constants__PJ_TRUE as i32, &mut account_id);
+        if status != pj_constants__PJ_SUCCESS as pj_status_t {
+            drop(tone_generator);
+            pjsua_destroy();
+            return Err(Error {
+                message: "pjsua_acc_add".to_string(),
+                status,
+            });
+        }
+
+        Ok((account_id, tone_generator))
+    }
+
+    /// Places a call to a number at the configured domain.
+    pub fn make_call(&self, number: &str) -> Result<Call, Error> {
+        let uri = CString::new(format!("sip:{}@{}", number, self.domain)).unwrap();
+        let uri = c_str_to_pj_str(&uri);
+        let mut call_id: pjsua_call_id = -1;
+        let status = unsafe {
+            pjsua_call_make_call(
+                self.account_id,
+                &uri,
+                std::ptr::null(),
+                std::ptr::null_mut(),
+                std::ptr::null(),
+                &mut call_id,
+            )
         };
-        Ok(Sip { account_id })
+        if status != pj_constants__PJ_SUCCESS as pj_status_t {
+            return Err(Error {
+                message: "pjsua_call_make_call".to_string(),
+                status,
+            });
+        }
+        Ok(Call::from_id(call_id))
+    }
+
+    /// Answers an incoming call with 200 OK.
+    pub fn answer(&self, call: Call) -> Result<(), Error> {
+        call.answer(200)
+    }
+
+    /// Terminates a call.
+    pub fn hangup(&self, call: Call) -> Result<(), Error> {
+        call.hangup(0)
+    }
+
+    /// Plays a call-progress tone on the sound device, or stops the current
+    /// tone if `tone` is `None`.
+    pub fn play_tone(&mut self, tone: Option<Tone>) -> Result<(), Error> {
+        let tone_generator = self.tone_generator.as_mut().unwrap();
+        match tone {
+            Some(tone) => tone_generator.play(tone),
+            None => {
+                tone_generator.stop();
+                Ok(())
+            }
+        }
     }
 
     extern "C" fn on_incoming_call(
@@ -139,32 +536,31 @@ impl Sip {
         call_id: pjsua_call_id,
         _rdata: *mut pjsip_rx_data,
     ) {
-        unsafe {
-            let mut call_info: pjsua_call_info = mem::uninitialized();
-            pjsua_call_get_info(call_id, &mut call_info as *mut _);
-
-            let caller = String::from_utf8_lossy(std::slice::from_raw_parts(
-                call_info.remote_info.ptr as *const u8,
-                call_info.remote_info.slen as usize,
-            ));
-            println!("Incoming call from {}!", caller);
-            // TODO
-            //    /* Automatically answer incoming calls with 200/OK */
-            pjsua_call_answer(call_id, 200, std::ptr::null(), std::ptr::null());
+        let call = Call::from_id(call_id);
+        let caller = call.remote_info().unwrap_or_default();
+        println!("Incoming call from {}!", caller);
+        // The call is only answered with 200 when the earpiece is picked up,
+        // until then the caller hears the ringback tone.
+        if let Err(e) = call.answer(180) {
+            println!("Could not answer call: {}", e);
         }
+        send_event(Event::IncomingCall(call, caller));
     }
 
     extern "C" fn on_call_state(call_id: pjsua_call_id, _e: *mut pjsip_event) {
-        unsafe {
-            let mut call_info: pjsua_call_info = mem::uninitialized();
-            pjsua_call_get_info(call_id, &mut call_info as *mut _);
-
-            let call_state = String::from_utf8_lossy(std::slice::from_raw_parts(
-                call_info.state_text.ptr as *const u8,
-                call_info.state_text.slen as usize,
-            ));
-            println!("Call state {}: {}", call_id, call_state);
-            // TODO
+        let call = Call::from_id(call_id);
+        let info = match call.info() {
+            Ok(info) => info,
+            Err(_) => return,
+        };
+        println!("Call state {}: {}", call_id, info.state_text);
+        match info.state {
+            CallState::Early => send_event(Event::CallRinging(call)),
+            CallState::Confirmed => send_event(Event::CallConfirmed(call)),
+            CallState::Disconnected => {
+                send_event(Event::CallDisconnected(call, info.last_status));
+            }
+            _ => {}
         }
     }
 
@@ -182,19 +578,80 @@ impl Sip {
         }
     }
 
-    extern "C" fn on_reg_state(acc_id: pjsua_acc_id, info: *mut pjsua_reg_info) {
+    extern "C" fn on_reg_state(_acc_id: pjsua_acc_id, info: *mut pjsua_reg_info) {
         unsafe {
             println!("on_reg_state: {}", (*info).renew);
-            // TODO
+            let param = &*(*info).cbparam;
+            let code = param.code as u32;
+            if param.status == pj_constants__PJ_SUCCESS as pj_status_t
+                && code / 100 == 2
+                && param.expiration > 0
+            {
+                send_event(Event::Registered);
+            } else {
+                send_event(Event::Unregistered(code));
+            }
+        }
+    }
+}
+
+impl Telephony for Sip {
+    type Error = Error;
+
+    fn make_call(&mut self, number: &str) -> Result<Call, Error> {
+        Sip::make_call(self, number)
+    }
+
+    fn answer(&mut self, call: Call) {
+        if let Err(e) = Sip::answer(self, call) {
+            println!("Could not answer call: {}", e);
+        }
+    }
+
+    fn reject(&mut self, call: Call, code: u32) {
+        if let Err(e) = call.hangup(code) {
+            println!("Could not reject call: {}", e);
+        }
+    }
+
+    fn hangup(&mut self, call: Call) {
+        if let Err(e) = Sip::hangup(self, call) {
+            println!("Could not hang up: {}", e);
+        }
+    }
+
+    fn hold(&mut self, call: Call) {
+        if let Err(e) = call.hold() {
+            println!("Could not put call on hold: {}", e);
+        }
+    }
+
+    fn unhold(&mut self, call: Call) {
+        if let Err(e) = call.unhold() {
+            println!("Could not resume call: {}", e);
+        }
+    }
+
+    fn transfer(&mut self, call: Call, target: Call) {
+        if let Err(e) = call.transfer_replaces(target) {
+            println!("Could not transfer call: {}", e);
+        }
+    }
+
+    fn play_tone(&mut self, tone: Option<Tone>) {
+        if let Err(e) = Sip::play_tone(self, tone) {
+            println!("Could not play tone: {}", e);
         }
     }
 }
 
 impl Drop for Sip {
     fn drop(&mut self) {
+        self.tone_generator = None;
         unsafe {
             pjsua_destroy();
         }
+        *EVENT_SENDER.lock().unwrap() = None;
     }
 }
 
@@ -1,11 +1,72 @@
 //! Main application state machine.
 
+use super::dialplan::{DialPlan, DialPlanConfig};
+use super::sip::Call;
+use super::tones::{self, Tone};
 use super::Event;
 
-use std::sync::mpsc::Receiver;
-use std::thread;
-use std::time::Duration;
+use std::fmt;
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
+use std::time::{Duration, Instant};
 
+/// Interface to the SIP stack.
+pub trait Telephony {
+    type Error: fmt::Display;
+
+    /// Places an outgoing call to the specified number.
+    fn make_call(&mut self, number: &str) -> Result<Call, Self::Error>;
+    /// Answers an incoming call.
+    fn answer(&mut self, call: Call);
+    /// Rejects an incoming call with the specified SIP status code.
+    fn reject(&mut self, call: Call, code: u32);
+    /// Terminates a call.
+    fn hangup(&mut self, call: Call);
+    /// Puts a call on hold.
+    fn hold(&mut self, call: Call);
+    /// Resumes a call which has been put on hold.
+    fn unhold(&mut self, call: Call);
+    /// Connects the remote party of `call` with the remote party of `target`
+    /// (attended transfer).
+    fn transfer(&mut self, call: Call, target: Call);
+    /// Plays a call-progress tone in the earpiece, or stops the current tone
+    /// if `tone` is `None`.
+    fn play_tone(&mut self, tone: Option<Tone>);
+}
+
+/// Configuration of call handling.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(default)]
+pub struct CallConfig {
+    /// SIP status code used to reject incoming calls when the phone is busy or
+    /// when nobody answers (usually 486 "Busy Here" or 603 "Decline").
+    pub reject_code: u32,
+    /// Time in seconds after which an unanswered incoming call is rejected.
+    pub ring_timeout_secs: u64,
+    /// Whether a second incoming call is signalled with the call waiting tone
+    /// during a call instead of being rejected. The second call is answered
+    /// with a hook flash and rejected after `ring_timeout_secs` otherwise.
+    pub call_waiting: bool,
+}
+
+impl Default for CallConfig {
+    fn default() -> Self {
+        Self {
+            reject_code: 486,
+            ring_timeout_secs: 60,
+            call_waiting: false,
+        }
+    }
+}
+
+/// Interface to the bell.
+pub trait Bell {
+    /// Starts ringing the bell.
+    fn start_ringing(&mut self);
+    /// Stops ringing the bell.
+    fn stop_ringing(&mut self);
+}
+
+#[derive(Debug, PartialEq)]
 enum State {
     /// No connection to a SIP registrar.
     Unregistered,
@@ -19,23 +80,1115 @@ enum State {
     CallRejected,
 }
 
-pub struct StateMachine {
+pub struct StateMachine<T: Telephony, B: Bell> {
     input: Receiver<Event>,
     state: State,
+    sip: T,
+    bell: B,
+    config: CallConfig,
+    /// Whether the SIP account is registered.
+    registered: bool,
+    /// Whether the earpiece has been picked up.
+    off_hook: bool,
+    /// Current incoming or outgoing call.
+    call: Option<Call>,
+    /// Whether the current call has been answered by either side.
+    confirmed: bool,
+    /// Whether the remote phone of the current outgoing call is ringing.
+    remote_ringing: bool,
+    /// Call which has been put on hold with a hook flash.
+    held_call: Option<Call>,
+    /// Incoming call which arrived during the current call.
+    waiting_call: Option<Call>,
+    /// Time at which the waiting call is rejected if it has not been answered.
+    waiting_timeout: Option<Instant>,
+    /// Tone played in the `CallRejected` state.
+    failure_tone: Tone,
+    /// Tone which is currently played.
+    tone: Option<Tone>,
+    /// Digits dialed so far in the `Dialing` state.
+    dial_plan: DialPlan,
+    /// Time at which the dialed number is considered complete or at which an
+    /// unanswered incoming call is rejected, depending on the state.
+    timeout: Option<Instant>,
 }
 
-impl StateMachine {
-    pub fn new(input: Receiver<Event>) -> StateMachine {
+impl<T: Telephony, B: Bell> StateMachine<T, B> {
+    pub fn new(
+        input: Receiver<Event>,
+        sip: T,
+        bell: B,
+        config: CallConfig,
+        dial_plan: DialPlanConfig,
+    ) -> StateMachine<T, B> {
         StateMachine {
             input,
-            state: State::Ready,
+            state: State::Unregistered,
+            sip,
+            bell,
+            config,
+            registered: false,
+            off_hook: false,
+            call: None,
+            confirmed: false,
+            remote_ringing: false,
+            held_call: None,
+            waiting_call: None,
+            waiting_timeout: None,
+            failure_tone: Tone::Busy,
+            tone: None,
+            dial_plan: DialPlan::new(dial_plan),
+            timeout: None,
         }
     }
 
     pub fn run(&mut self) -> ! {
         loop {
-            thread::sleep(Duration::from_millis(100));
-            // TODO
+            let deadline = self.timeout.into_iter().chain(self.waiting_timeout).min();
+            let event = match deadline {
+                Some(deadline) => {
+                    let timeout = deadline.saturating_duration_since(Instant::now());
+                    self.input.recv_timeout(timeout)
+                }
+                None => self
+                    .input
+                    .recv()
+                    .map_err(|_| RecvTimeoutError::Disconnected),
+            };
+            match event {
+                Ok(event) => self.handle_event(event, Instant::now()),
+                Err(RecvTimeoutError::Timeout) => self.handle_timeout(Instant::now()),
+                Err(RecvTimeoutError::Disconnected) => panic!("All event sources are gone."),
+            }
+        }
+    }
+
+    fn handle_event(&mut self, event: Event, now: Instant) {
+        self.process_event(event, now);
+        self.update_tone();
+    }
+
+    fn process_event(&mut self, event: Event, now: Instant) {
+        match event {
+            Event::Dialed(digit) => {
+                if let Some(c) = std::char::from_digit(digit, 10) {
+                    self.dial_symbol(c, now);
+                }
+            }
+            Event::StarPressed => self.dial_symbol('*', now),
+            Event::HashPressed => {
+                // "#" dials the number without waiting for the timeout.
+                if self.off_hook && self.state == State::Dialing {
+                    if let Some(number) = self.dial_plan.complete() {
+                        self.place_call(&number);
+                    }
+                }
+            }
+            Event::DialRejected(rejection) => {
+                println!("Ignoring invalidly dialed digit: {:?}", rejection);
+            }
+            Event::DialCalibrated(report) => {
+                println!("Dial calibration: {}", report);
+            }
+            Event::EarpiecePickedUp => {
+                self.off_hook = true;
+                if self.state == State::IncomingCall {
+                    if let Some(call) = self.call {
+                        self.sip.answer(call);
+                        self.confirmed = true;
+                    }
+                    self.set_state(State::ActiveCall);
+                }
+            }
+            Event::EarpiecePutDown => {
+                self.off_hook = false;
+                if self.state == State::IncomingCall {
+                    return;
+                }
+                self.dial_plan.reset();
+                self.end_calls();
+                match self.take_waiting_call() {
+                    // The waiting call rings once the other calls have ended.
+                    Some(call) => self.ring(call, now),
+                    None => self.set_idle_state(),
+                }
+            }
+            Event::HookFlash | Event::FlashKey => {
+                if self.off_hook {
+                    self.flash();
+                }
+            }
+            Event::Registered => {
+                self.registered = true;
+                match self.state {
+                    State::Unregistered => self.set_state(State::Ready),
+                    State::ActiveCallRegistrationFailed => self.set_state(State::ActiveCall),
+                    _ => {}
+                }
+            }
+            Event::Unregistered(code) => {
+                println!("Not registered (status {}).", code);
+                self.registered = false;
+                match self.state {
+                    State::Ready | State::Dialing | State::CallRejected => {
+                        self.set_state(State::Unregistered)
+                    }
+                    State::ActiveCall => self.set_state(State::ActiveCallRegistrationFailed),
+                    _ => {}
+                }
+            }
+            Event::IncomingCall(call, caller) => {
+                if self.state == State::Ready && !self.off_hook && self.call.is_none() {
+                    println!("Incoming call from {}.", caller);
+                    self.ring(call, now);
+                } else if self.config.call_waiting
+                    && self.state == State::ActiveCall
+                    && self.confirmed
+                    && self.held_call.is_none()
+                    && self.waiting_call.is_none()
+                {
+                    println!("Call waiting from {}.", caller);
+                    self.waiting_call = Some(call);
+                    self.waiting_timeout =
+                        Some(now + Duration::from_secs(self.config.ring_timeout_secs));
+                } else {
+                    println!("Rejecting call from {}, the phone is busy.", caller);
+                    self.sip.reject(call, self.config.reject_code);
+                }
+            }
+            Event::CallRinging(call) => {
+                if self.call == Some(call) && self.state == State::ActiveCall {
+                    self.remote_ringing = true;
+                }
+            }
+            Event::CallConfirmed(call) => {
+                // The SIP stack connects the audio itself, and outgoing calls
+                // already are in `ActiveCall`.
+                if self.call == Some(call) {
+                    self.confirmed = true;
+                    self.remote_ringing = false;
+                }
+            }
+            Event::CallDisconnected(call, code) => {
+                if self.held_call == Some(call) {
+                    println!("Held call ended (status {}).", code);
+                    self.held_call = None;
+                    return;
+                }
+                if self.waiting_call == Some(call) {
+                    println!("Waiting call ended (status {}).", code);
+                    self.take_waiting_call();
+                    return;
+                }
+                if self.call != Some(call) {
+                    return;
+                }
+                println!("Call ended (status {}).", code);
+                self.call = None;
+                self.failure_tone = tones::tone_for_status(code);
+                match self.state {
+                    State::IncomingCall => self.set_idle_state(),
+                    State::ActiveCall | State::ActiveCallRegistrationFailed => {
+                        if self.off_hook {
+                            // Wait until the earpiece is put down.
+                            self.set_state(State::CallRejected);
+                        } else {
+                            self.set_idle_state();
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            Event::StatusRequested => {
+                println!(
+                    "State: {:?}, registered: {}, off hook: {}, call: {:?}, held: {:?}, \
+                     waiting: {:?}, dialed: \"{}\"",
+                    self.state,
+                    self.registered,
+                    self.off_hook,
+                    self.call,
+                    self.held_call,
+                    self.waiting_call,
+                    self.dial_plan.digits()
+                );
+            }
+        }
+    }
+
+    /// Called when the timeout of the current state or of the waiting call has
+    /// expired.
+    fn handle_timeout(&mut self, now: Instant) {
+        if self.waiting_timeout.is_some_and(|deadline| deadline <= now) {
+            if let Some(call) = self.take_waiting_call() {
+                println!("Nobody answered the waiting call.");
+                self.sip.reject(call, self.config.reject_code);
+            }
+        }
+        // Only the timeout of the waiting call may have expired.
+        if self.timeout.is_none_or(|deadline| deadline <= now) {
+            self.timeout = None;
+            match self.state {
+                State::Dialing => match self.dial_plan.poll(now) {
+                    Some(number) => self.place_call(&number),
+                    None => self.timeout = self.dial_plan.deadline(),
+                },
+                State::IncomingCall => {
+                    println!("Nobody answered the call.");
+                    if let Some(call) = self.call.take() {
+                        self.sip.reject(call, self.config.reject_code);
+                    }
+                    self.set_idle_state();
+                }
+                _ => {}
+            }
+        }
+        self.update_tone();
+    }
+
+    fn place_call(&mut self, number: &str) {
+        println!("Calling {}.", number);
+        match self.sip.make_call(number) {
+            Ok(call) => {
+                self.call = Some(call);
+                self.confirmed = false;
+                self.set_state(State::ActiveCall);
+            }
+            Err(e) => {
+                println!("Could not place call: {}", e);
+                self.failure_tone = Tone::Congestion;
+                self.set_state(State::CallRejected);
+            }
+        }
+    }
+
+    /// Rings the bell for an incoming call.
+    fn ring(&mut self, call: Call, now: Instant) {
+        self.call = Some(call);
+        self.confirmed = false;
+        self.set_state(State::IncomingCall);
+        self.timeout = Some(now + Duration::from_secs(self.config.ring_timeout_secs));
+    }
+
+    /// Handles a hook flash or a press of the earth key while the earpiece is
+    /// picked up.
+    ///
+    /// A flash answers a waiting call, swaps between the current and the held
+    /// call, or puts the current call on hold and presents the dial tone for a
+    /// consultation call.
+    fn flash(&mut self) {
+        let in_call = matches!(
+            self.state,
+            State::ActiveCall | State::ActiveCallRegistrationFailed
+        );
+        if let Some(waiting) = self.take_waiting_call() {
+            if let Some(call) = self.call.take() {
+                self.sip.hold(call);
+                self.held_call = Some(call);
+            }
+            println!("Answering waiting call.");
+            self.sip.answer(waiting);
+            self.call = Some(waiting);
+            self.confirmed = true;
+            self.set_active_state();
+        } else if let Some(held) = self.held_call.take() {
+            if let Some(call) = self.call.take() {
+                if in_call && self.confirmed {
+                    self.sip.hold(call);
+                    self.held_call = Some(call);
+                } else {
+                    // Unanswered consultation calls are cancelled.
+                    self.sip.hangup(call);
+                }
+            }
+            println!("Resuming held call.");
+            self.dial_plan.reset();
+            self.sip.unhold(held);
+            self.call = Some(held);
+            self.confirmed = true;
+            self.set_active_state();
+        } else if let (Some(call), true) = (self.call, in_call && self.confirmed) {
+            println!("Putting call on hold.");
+            self.sip.hold(call);
+            self.held_call = Some(call);
+            self.call = None;
+            self.set_idle_state();
+        }
+    }
+
+    /// Removes the waiting call along with its timeout.
+    fn take_waiting_call(&mut self) -> Option<Call> {
+        self.waiting_timeout = None;
+        self.waiting_call.take()
+    }
+
+    /// Terminates the current and the held call when the earpiece is put
+    /// down. If there are both, the two remote parties are connected instead.
+    fn end_calls(&mut self) {
+        match (self.call.take(), self.held_call.take()) {
+            (Some(call), Some(held)) if self.confirmed => {
+                println!("Transferring call.");
+                self.sip.transfer(held, call);
+            }
+            (call, held) => {
+                for call in call.into_iter().chain(held) {
+                    self.sip.hangup(call);
+                }
+            }
+        }
+    }
+
+    /// Adds a digit or `*` to the number if the phone is ready for dialing.
+    fn dial_symbol(&mut self, c: char, now: Instant) {
+        if !self.off_hook {
+            return;
+        }
+        match self.state {
+            State::Ready => {
+                self.dial_plan.reset();
+                self.set_state(State::Dialing);
+                self.add_digit(c, now);
+            }
+            State::Dialing => self.add_digit(c, now),
+            _ => {}
+        }
+    }
+
+    fn add_digit(&mut self, c: char, now: Instant) {
+        match self.dial_plan.push_digit(c, now) {
+            Some(number) => self.place_call(&number),
+            None => self.timeout = self.dial_plan.deadline(),
+        }
+    }
+
+    /// Returns the tone which shall be played in the current state.
+    fn expected_tone(&self) -> Option<Tone> {
+        if !self.off_hook {
+            return None;
+        }
+        match self.state {
+            State::Unregistered => Some(Tone::Unregistered),
+            State::Ready => Some(Tone::Dial),
+            State::Dialing | State::IncomingCall => None,
+            State::ActiveCall | State::ActiveCallRegistrationFailed => {
+                if self.waiting_call.is_some() {
+                    Some(Tone::CallWaiting)
+                } else if self.remote_ringing {
+                    Some(Tone::Ringback)
+                } else {
+                    None
+                }
+            }
+            State::CallRejected => Some(self.failure_tone),
         }
     }
+
+    /// Starts or stops tones after the state has changed.
+    fn update_tone(&mut self) {
+        let tone = self.expected_tone();
+        if tone != self.tone {
+            self.tone = tone;
+            self.sip.play_tone(tone);
+        }
+    }
+
+    /// Switches to the state without a call, which depends on whether the SIP
+    /// account is registered.
+    fn set_idle_state(&mut self) {
+        if self.registered {
+            self.set_state(State::Ready);
+        } else {
+            self.set_state(State::Unregistered);
+        }
+    }
+
+    /// Switches to the state with an active call, which depends on whether the
+    /// SIP account is registered.
+    fn set_active_state(&mut self) {
+        if self.registered {
+            self.set_state(State::ActiveCall);
+        } else {
+            self.set_state(State::ActiveCallRegistrationFailed);
+        }
+    }
+
+    /// Switches to a new state, ringing the bell while in `IncomingCall`.
+    ///
+    /// Any pending timeout is cancelled.
+    fn set_state(&mut self, state: State) {
+        self.timeout = None;
+        self.remote_ringing = false;
+        if self.state != State::IncomingCall && state == State::IncomingCall {
+            self.bell.start_ringing();
+        } else if self.state == State::IncomingCall && state != State::IncomingCall {
+            self.bell.stop_ringing();
+        }
+        self.state = state;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dialplan::{FixedLengthRule, RewriteRule};
+
+    use std::sync::mpsc::{channel, Sender};
+
+    #[derive(Default)]
+    struct MockSip {
+        calls: Vec<String>,
+        answered: Vec<Call>,
+        rejected: Vec<(Call, u32)>,
+        hung_up: Vec<Call>,
+        held: Vec<Call>,
+        unheld: Vec<Call>,
+        transferred: Vec<(Call, Call)>,
+        fail_calls: bool,
+        tone: Option<Tone>,
+    }
+
+    impl Telephony for MockSip {
+        type Error = String;
+
+        fn make_call(&mut self, number: &str) -> Result<Call, String> {
+            if self.fail_calls {
+                return Err("failed".to_string());
+            }
+            self.calls.push(number.to_string());
+            Ok(call(100 + self.calls.len() as i32))
+        }
+        fn answer(&mut self, call: Call) {
+            self.answered.push(call);
+        }
+        fn reject(&mut self, call: Call, code: u32) {
+            self.rejected.push((call, code));
+        }
+        fn hangup(&mut self, call: Call) {
+            self.hung_up.push(call);
+        }
+        fn hold(&mut self, call: Call) {
+            self.held.push(call);
+        }
+        fn unhold(&mut self, call: Call) {
+            self.unheld.push(call);
+        }
+        fn transfer(&mut self, call: Call, target: Call) {
+            self.transferred.push((call, target));
+        }
+        fn play_tone(&mut self, tone: Option<Tone>) {
+            self.tone = tone;
+        }
+    }
+
+    #[derive(Default)]
+    struct MockBell {
+        ringing: bool,
+    }
+
+    impl Bell for MockBell {
+        fn start_ringing(&mut self) {
+            self.ringing = true;
+        }
+        fn stop_ringing(&mut self) {
+            self.ringing = false;
+        }
+    }
+
+    type TestStateMachine = StateMachine<MockSip, MockBell>;
+
+    fn call(id: i32) -> Call {
+        Call::from_id(id)
+    }
+
+    /// Creates a state machine which is already registered.
+    fn create_test_state_machine() -> (Sender<Event>, TestStateMachine, Instant) {
+        let (send, recv) = channel();
+        let mut state_machine = StateMachine::new(
+            recv,
+            MockSip::default(),
+            MockBell::default(),
+            CallConfig::default(),
+            DialPlanConfig::default(),
+        );
+        let now = Instant::now();
+        state_machine.handle_event(Event::Registered, now);
+        assert_eq!(state_machine.state, State::Ready);
+        (send, state_machine, now)
+    }
+
+    fn dial(state_machine: &mut TestStateMachine, number: &str, now: Instant) {
+        for c in number.chars() {
+            state_machine.handle_event(Event::Dialed(c.to_digit(10).unwrap()), now);
+        }
+    }
+
+    /// Lets the pending timeout of the state machine expire.
+    fn expire_timeout(state_machine: &mut TestStateMachine) {
+        let deadline = state_machine.timeout.unwrap_or_else(Instant::now);
+        state_machine.handle_timeout(deadline);
+    }
+
+    #[test]
+    fn test_outgoing_call() {
+        let (_send, mut sm, now) = create_test_state_machine();
+
+        // Digits are ignored while the earpiece is on the hook.
+        dial(&mut sm, "12", now);
+        assert_eq!(sm.state, State::Ready);
+
+        sm.handle_event(Event::EarpiecePickedUp, now);
+        dial(&mut sm, "0301234", now);
+        assert_eq!(sm.state, State::Dialing);
+        assert_eq!(sm.dial_plan.digits(), "0301234");
+        assert_eq!(sm.timeout, Some(now + Duration::from_secs(3)));
+
+        expire_timeout(&mut sm);
+        assert_eq!(sm.state, State::ActiveCall);
+        assert_eq!(sm.sip.calls, vec!["0301234".to_string()]);
+        assert_eq!(sm.timeout, None);
+
+        // Further digits do not start a new call.
+        dial(&mut sm, "5", now);
+        sm.handle_event(Event::CallConfirmed(call(101)), now);
+        assert_eq!(sm.state, State::ActiveCall);
+
+        sm.handle_event(Event::EarpiecePutDown, now);
+        assert_eq!(sm.state, State::Ready);
+        assert_eq!(sm.sip.hung_up, vec![call(101)]);
+        assert_eq!(sm.call, None);
+    }
+
+    #[test]
+    fn test_abort_dialing() {
+        let (_send, mut sm, now) = create_test_state_machine();
+
+        sm.handle_event(Event::EarpiecePickedUp, now);
+        dial(&mut sm, "030", now);
+        sm.handle_event(Event::EarpiecePutDown, now);
+        assert_eq!(sm.state, State::Ready);
+        assert_eq!(sm.timeout, None);
+
+        expire_timeout(&mut sm);
+        assert!(sm.sip.calls.is_empty());
+        assert!(sm.sip.hung_up.is_empty());
+
+        // The next number starts from scratch.
+        sm.handle_event(Event::EarpiecePickedUp, now);
+        dial(&mut sm, "42", now);
+        expire_timeout(&mut sm);
+        assert_eq!(sm.sip.calls, vec!["42".to_string()]);
+    }
+
+    #[test]
+    fn test_dial_plan() {
+        let (_send, recv) = channel();
+        let dial_plan = DialPlanConfig {
+            fixed_length: vec![FixedLengthRule {
+                prefix: "Z".to_string(),
+                length: 4,
+            }],
+            rewrites: vec![RewriteRule {
+                prefix: "Z".to_string(),
+                strip: 0,
+                prepend: "030".to_string(),
+            }],
+            ..DialPlanConfig::default()
+        };
+        let mut sm = StateMachine::new(
+            recv,
+            MockSip::default(),
+            MockBell::default(),
+            CallConfig::default(),
+            dial_plan,
+        );
+        let now = Instant::now();
+        sm.handle_event(Event::Registered, now);
+
+        // Emergency numbers are dialed without waiting for the timeout.
+        sm.handle_event(Event::EarpiecePickedUp, now);
+        dial(&mut sm, "112", now);
+        assert_eq!(sm.state, State::ActiveCall);
+        assert_eq!(sm.sip.calls, vec!["112".to_string()]);
+        sm.handle_event(Event::EarpiecePutDown, now);
+
+        // Complete numbers are rewritten and dialed immediately as well.
+        sm.handle_event(Event::EarpiecePickedUp, now);
+        dial(&mut sm, "123", now);
+        assert_eq!(sm.state, State::Dialing);
+        dial(&mut sm, "4", now);
+        assert_eq!(sm.state, State::ActiveCall);
+        assert_eq!(sm.sip.calls[1], "0301234");
+
+        // An early timeout does not place the call.
+        sm.handle_event(Event::EarpiecePutDown, now);
+        sm.handle_event(Event::EarpiecePickedUp, now);
+        dial(&mut sm, "0", now);
+        sm.handle_timeout(now + Duration::from_secs(1));
+        assert_eq!(sm.state, State::Dialing);
+        assert_eq!(sm.timeout, Some(now + Duration::from_secs(3)));
+        sm.handle_timeout(now + Duration::from_secs(3));
+        assert_eq!(sm.sip.calls[2], "0");
+    }
+
+    #[test]
+    fn test_keypad() {
+        let (_send, mut sm, now) = create_test_state_machine();
+
+        // "#" without a number is ignored.
+        sm.handle_event(Event::EarpiecePickedUp, now);
+        sm.handle_event(Event::HashPressed, now);
+        assert_eq!(sm.state, State::Ready);
+
+        // "*" is part of the number, "#" dials immediately.
+        sm.handle_event(Event::StarPressed, now);
+        dial(&mut sm, "31", now);
+        assert_eq!(sm.state, State::Dialing);
+        assert_eq!(sm.dial_plan.digits(), "*31");
+        sm.handle_event(Event::HashPressed, now);
+        assert_eq!(sm.state, State::ActiveCall);
+        assert_eq!(sm.sip.calls, vec!["*31".to_string()]);
+        assert_eq!(sm.timeout, None);
+
+        // Keys are ignored during the call.
+        sm.handle_event(Event::StarPressed, now);
+        sm.handle_event(Event::HashPressed, now);
+        assert_eq!(sm.sip.calls.len(), 1);
+    }
+
+    #[test]
+    fn test_failed_call() {
+        let (_send, mut sm, now) = create_test_state_machine();
+        sm.sip.fail_calls = true;
+
+        sm.handle_event(Event::EarpiecePickedUp, now);
+        dial(&mut sm, "42", now);
+        expire_timeout(&mut sm);
+        assert_eq!(sm.state, State::CallRejected);
+
+        // Dialing is not possible until the earpiece has been put down.
+        dial(&mut sm, "1", now);
+        assert_eq!(sm.state, State::CallRejected);
+        sm.handle_event(Event::EarpiecePutDown, now);
+        assert_eq!(sm.state, State::Ready);
+        assert!(sm.sip.hung_up.is_empty());
+    }
+
+    #[test]
+    fn test_remote_hangup() {
+        let (_send, mut sm, now) = create_test_state_machine();
+
+        sm.handle_event(Event::EarpiecePickedUp, now);
+        dial(&mut sm, "42", now);
+        expire_timeout(&mut sm);
+
+        // Events of other calls are ignored.
+        sm.handle_event(Event::CallDisconnected(call(7), 486), now);
+        assert_eq!(sm.state, State::ActiveCall);
+
+        sm.handle_event(Event::CallDisconnected(call(101), 486), now);
+        assert_eq!(sm.state, State::CallRejected);
+        assert_eq!(sm.call, None);
+        sm.handle_event(Event::EarpiecePutDown, now);
+        assert_eq!(sm.state, State::Ready);
+        assert!(sm.sip.hung_up.is_empty());
+    }
+
+    #[test]
+    fn test_incoming_call() {
+        let (_send, mut sm, now) = create_test_state_machine();
+
+        sm.handle_event(
+            Event::IncomingCall(call(3), "sip:alice@example.com".into()),
+            now,
+        );
+        assert_eq!(sm.state, State::IncomingCall);
+        assert!(sm.bell.ringing);
+
+        // A second call is rejected while the phone is ringing.
+        sm.handle_event(
+            Event::IncomingCall(call(4), "sip:bob@example.com".into()),
+            now,
+        );
+        assert_eq!(sm.sip.rejected, vec![(call(4), 486)]);
+        assert_eq!(sm.state, State::IncomingCall);
+
+        sm.handle_event(Event::EarpiecePickedUp, now);
+        assert_eq!(sm.state, State::ActiveCall);
+        assert_eq!(sm.sip.answered, vec![call(3)]);
+        assert!(!sm.bell.ringing);
+
+        sm.handle_event(Event::EarpiecePutDown, now);
+        assert_eq!(sm.state, State::Ready);
+        assert_eq!(sm.sip.hung_up, vec![call(3)]);
+    }
+
+    #[test]
+    fn test_missed_call() {
+        let (_send, mut sm, now) = create_test_state_machine();
+
+        sm.handle_event(
+            Event::IncomingCall(call(3), "sip:alice@example.com".into()),
+            now,
+        );
+        assert!(sm.bell.ringing);
+        sm.handle_event(Event::CallDisconnected(call(3), 487), now);
+        assert_eq!(sm.state, State::Ready);
+        assert!(!sm.bell.ringing);
+        assert!(sm.sip.answered.is_empty());
+
+        // Calls are rejected while the earpiece is off the hook.
+        sm.handle_event(Event::EarpiecePickedUp, now);
+        sm.handle_event(
+            Event::IncomingCall(call(5), "sip:alice@example.com".into()),
+            now,
+        );
+        assert_eq!(sm.state, State::Ready);
+        assert_eq!(sm.sip.rejected, vec![(call(5), 486)]);
+        assert!(!sm.bell.ringing);
+        assert!(sm.sip.hung_up.is_empty());
+    }
+
+    #[test]
+    fn test_ring_timeout() {
+        let (_send, recv) = channel();
+        let config = CallConfig {
+            reject_code: 603,
+            ring_timeout_secs: 30,
+            ..CallConfig::default()
+        };
+        let mut sm = StateMachine::new(
+            recv,
+            MockSip::default(),
+            MockBell::default(),
+            config,
+            DialPlanConfig::default(),
+        );
+        let now = Instant::now();
+        sm.handle_event(Event::Registered, now);
+
+        sm.handle_event(
+            Event::IncomingCall(call(3), "sip:alice@example.com".into()),
+            now,
+        );
+        assert_eq!(sm.timeout, Some(now + Duration::from_secs(30)));
+        expire_timeout(&mut sm);
+        assert_eq!(sm.state, State::Ready);
+        assert_eq!(sm.sip.rejected, vec![(call(3), 603)]);
+        assert!(!sm.bell.ringing);
+        assert_eq!(sm.call, None);
+
+        // Picking up after the call was rejected does not answer it.
+        sm.handle_event(Event::EarpiecePickedUp, now);
+        assert!(sm.sip.answered.is_empty());
+    }
+
+    #[test]
+    fn test_hold_and_swap() {
+        let (_send, mut sm, now) = create_test_state_machine();
+
+        // Flashes are ignored without a call.
+        sm.handle_event(Event::HookFlash, now);
+        sm.handle_event(Event::EarpiecePickedUp, now);
+        sm.handle_event(Event::HookFlash, now);
+        assert_eq!(sm.state, State::Ready);
+
+        dial(&mut sm, "42", now);
+        expire_timeout(&mut sm);
+        sm.handle_event(Event::CallConfirmed(call(101)), now);
+
+        // The first call is put on hold for a consultation call.
+        sm.handle_event(Event::HookFlash, now);
+        assert_eq!(sm.sip.held, vec![call(101)]);
+        assert_eq!(sm.state, State::Ready);
+        assert_eq!(sm.sip.tone, Some(Tone::Dial));
+        dial(&mut sm, "43", now);
+        expire_timeout(&mut sm);
+        assert_eq!(sm.call, Some(call(102)));
+
+        // An unanswered consultation call is cancelled by a flash.
+        sm.handle_event(Event::HookFlash, now);
+        assert_eq!(sm.sip.hung_up, vec![call(102)]);
+        assert_eq!(sm.sip.unheld, vec![call(101)]);
+        assert_eq!(sm.call, Some(call(101)));
+        assert_eq!(sm.held_call, None);
+        assert_eq!(sm.state, State::ActiveCall);
+
+        // Once answered, further flashes swap between the calls.
+        sm.handle_event(Event::HookFlash, now);
+        dial(&mut sm, "44", now);
+        expire_timeout(&mut sm);
+        sm.handle_event(Event::CallConfirmed(call(103)), now);
+        sm.handle_event(Event::HookFlash, now);
+        assert_eq!(sm.call, Some(call(101)));
+        assert_eq!(sm.held_call, Some(call(103)));
+        sm.handle_event(Event::HookFlash, now);
+        assert_eq!(sm.call, Some(call(103)));
+        assert_eq!(sm.held_call, Some(call(101)));
+        assert_eq!(
+            sm.sip.held,
+            vec![call(101), call(101), call(103), call(101)]
+        );
+
+        // When the held party hangs up, the current call continues.
+        sm.handle_event(Event::CallDisconnected(call(101), 200), now);
+        assert_eq!(sm.held_call, None);
+        assert_eq!(sm.state, State::ActiveCall);
+        sm.handle_event(Event::EarpiecePutDown, now);
+        assert_eq!(sm.sip.hung_up, vec![call(102), call(103)]);
+        assert!(sm.sip.transferred.is_empty());
+    }
+
+    #[test]
+    fn test_flash_key() {
+        let (_send, mut sm, now) = create_test_state_machine();
+
+        sm.handle_event(Event::FlashKey, now);
+        sm.handle_event(Event::EarpiecePickedUp, now);
+        dial(&mut sm, "42", now);
+        expire_timeout(&mut sm);
+        sm.handle_event(Event::CallConfirmed(call(101)), now);
+
+        // The earth key toggles hold.
+        sm.handle_event(Event::FlashKey, now);
+        assert_eq!(sm.held_call, Some(call(101)));
+        assert_eq!(sm.state, State::Ready);
+        sm.handle_event(Event::FlashKey, now);
+        assert_eq!(sm.sip.unheld, vec![call(101)]);
+        assert_eq!(sm.call, Some(call(101)));
+        assert_eq!(sm.state, State::ActiveCall);
+
+        // With a second call, it swaps between the calls.
+        sm.handle_event(Event::FlashKey, now);
+        dial(&mut sm, "43", now);
+        expire_timeout(&mut sm);
+        sm.handle_event(Event::CallConfirmed(call(102)), now);
+        sm.handle_event(Event::FlashKey, now);
+        assert_eq!(sm.call, Some(call(101)));
+        assert_eq!(sm.held_call, Some(call(102)));
+        sm.handle_event(Event::FlashKey, now);
+        assert_eq!(sm.call, Some(call(102)));
+        assert_eq!(sm.held_call, Some(call(101)));
+    }
+
+    #[test]
+    fn test_transfer() {
+        let (_send, mut sm, now) = create_test_state_machine();
+
+        sm.handle_event(Event::EarpiecePickedUp, now);
+        dial(&mut sm, "42", now);
+        expire_timeout(&mut sm);
+        sm.handle_event(Event::CallConfirmed(call(101)), now);
+        sm.handle_event(Event::HookFlash, now);
+        dial(&mut sm, "43", now);
+        expire_timeout(&mut sm);
+        sm.handle_event(Event::CallConfirmed(call(102)), now);
+
+        // Hanging up connects the held party with the current call.
+        sm.handle_event(Event::EarpiecePutDown, now);
+        assert_eq!(sm.sip.transferred, vec![(call(101), call(102))]);
+        assert!(sm.sip.hung_up.is_empty());
+        assert_eq!(sm.state, State::Ready);
+        assert_eq!(sm.call, None);
+        assert_eq!(sm.held_call, None);
+
+        // A held call is ended if the consultation call failed.
+        sm.handle_event(Event::EarpiecePickedUp, now);
+        dial(&mut sm, "44", now);
+        expire_timeout(&mut sm);
+        sm.handle_event(Event::CallConfirmed(call(103)), now);
+        sm.handle_event(Event::HookFlash, now);
+        dial(&mut sm, "45", now);
+        expire_timeout(&mut sm);
+        sm.handle_event(Event::CallDisconnected(call(104), 486), now);
+        assert_eq!(sm.state, State::CallRejected);
+        sm.handle_event(Event::EarpiecePutDown, now);
+        assert_eq!(sm.sip.hung_up, vec![call(103)]);
+        assert_eq!(sm.sip.transferred.len(), 1);
+    }
+
+    #[test]
+    fn test_call_waiting() {
+        let (_send, mut sm, now) = create_test_state_machine();
+        sm.config.call_waiting = true;
+
+        sm.handle_event(
+            Event::IncomingCall(call(3), "sip:alice@example.com".into()),
+            now,
+        );
+        sm.handle_event(Event::EarpiecePickedUp, now);
+
+        // A second call is signalled with the call waiting tone.
+        sm.handle_event(
+            Event::IncomingCall(call(4), "sip:bob@example.com".into()),
+            now,
+        );
+        assert_eq!(sm.waiting_call, Some(call(4)));
+        assert_eq!(sm.sip.tone, Some(Tone::CallWaiting));
+        assert!(!sm.bell.ringing);
+
+        // Only one call can wait.
+        sm.handle_event(
+            Event::IncomingCall(call(5), "sip:carol@example.com".into()),
+            now,
+        );
+        assert_eq!(sm.sip.rejected, vec![(call(5), 486)]);
+
+        // The waiting call is answered with a flash.
+        sm.handle_event(Event::HookFlash, now);
+        assert_eq!(sm.sip.answered, vec![call(3), call(4)]);
+        assert_eq!(sm.sip.held, vec![call(3)]);
+        assert_eq!(sm.call, Some(call(4)));
+        assert_eq!(sm.held_call, Some(call(3)));
+        assert_eq!(sm.sip.tone, None);
+
+        sm.handle_event(Event::HookFlash, now);
+        assert_eq!(sm.call, Some(call(3)));
+        sm.handle_event(Event::EarpiecePutDown, now);
+        assert_eq!(sm.sip.transferred, vec![(call(4), call(3))]);
+
+        // A waiting call rings after the current call has been ended.
+        sm.handle_event(
+            Event::IncomingCall(call(6), "sip:alice@example.com".into()),
+            now,
+        );
+        sm.handle_event(Event::EarpiecePickedUp, now);
+        sm.handle_event(
+            Event::IncomingCall(call(7), "sip:bob@example.com".into()),
+            now,
+        );
+        sm.handle_event(Event::EarpiecePutDown, now);
+        assert_eq!(sm.sip.hung_up, vec![call(6)]);
+        assert_eq!(sm.state, State::IncomingCall);
+        assert_eq!(sm.call, Some(call(7)));
+        assert!(sm.bell.ringing);
+
+        // Waiting calls are disabled by default.
+        sm.handle_event(Event::EarpiecePickedUp, now);
+        sm.config.call_waiting = false;
+        sm.handle_event(
+            Event::IncomingCall(call(8), "sip:carol@example.com".into()),
+            now,
+        );
+        assert_eq!(sm.sip.rejected, vec![(call(5), 486), (call(8), 486)]);
+        assert_eq!(sm.waiting_call, None);
+    }
+
+    #[test]
+    fn test_waiting_call_timeout() {
+        let (_send, mut sm, now) = create_test_state_machine();
+        sm.config.call_waiting = true;
+        sm.handle_event(Event::EarpiecePickedUp, now);
+        dial(&mut sm, "110", now);
+        sm.handle_event(Event::CallConfirmed(call(101)), now);
+
+        sm.handle_event(
+            Event::IncomingCall(call(2), "sip:alice@example.com".into()),
+            now,
+        );
+        assert_eq!(sm.waiting_call, Some(call(2)));
+        assert_eq!(sm.waiting_timeout, Some(now + Duration::from_secs(60)));
+
+        // An early timeout does not reject the call.
+        sm.handle_timeout(now + Duration::from_secs(59));
+        assert_eq!(sm.waiting_call, Some(call(2)));
+
+        // The unanswered waiting call is rejected, the current call continues.
+        sm.handle_timeout(now + Duration::from_secs(60));
+        assert_eq!(sm.sip.rejected, vec![(call(2), 486)]);
+        assert_eq!(sm.waiting_call, None);
+        assert_eq!(sm.waiting_timeout, None);
+        assert_eq!(sm.state, State::ActiveCall);
+        assert_eq!(sm.call, Some(call(101)));
+        assert_eq!(sm.sip.tone, None);
+    }
+
+    #[test]
+    fn test_tones() {
+        let (_send, recv) = channel();
+        let mut sm = StateMachine::new(
+            recv,
+            MockSip::default(),
+            MockBell::default(),
+            CallConfig::default(),
+            DialPlanConfig::default(),
+        );
+        let now = Instant::now();
+
+        // Tones are only played while the earpiece is picked up.
+        sm.handle_event(Event::EarpiecePickedUp, now);
+        assert_eq!(sm.sip.tone, Some(Tone::Unregistered));
+        sm.handle_event(Event::Registered, now);
+        assert_eq!(sm.sip.tone, Some(Tone::Dial));
+        sm.handle_event(Event::EarpiecePutDown, now);
+        assert_eq!(sm.sip.tone, None);
+
+        sm.handle_event(Event::EarpiecePickedUp, now);
+        dial(&mut sm, "42", now);
+        assert_eq!(sm.sip.tone, None);
+        expire_timeout(&mut sm);
+        assert_eq!(sm.sip.tone, None);
+        sm.handle_event(Event::CallRinging(call(7)), now);
+        assert_eq!(sm.sip.tone, None);
+        sm.handle_event(Event::CallRinging(call(101)), now);
+        assert_eq!(sm.sip.tone, Some(Tone::Ringback));
+        sm.handle_event(Event::CallConfirmed(call(101)), now);
+        assert_eq!(sm.sip.tone, None);
+        sm.handle_event(Event::CallDisconnected(call(101), 200), now);
+        assert_eq!(sm.sip.tone, Some(Tone::Busy));
+        sm.handle_event(Event::EarpiecePutDown, now);
+        assert_eq!(sm.sip.tone, None);
+
+        // Failed calls result in the congestion tone.
+        sm.handle_event(Event::EarpiecePickedUp, now);
+        dial(&mut sm, "42", now);
+        expire_timeout(&mut sm);
+        sm.handle_event(Event::CallRinging(call(102)), now);
+        sm.handle_event(Event::CallDisconnected(call(102), 503), now);
+        assert_eq!(sm.sip.tone, Some(Tone::Congestion));
+        sm.handle_event(Event::EarpiecePutDown, now);
+
+        sm.sip.fail_calls = true;
+        sm.handle_event(Event::EarpiecePickedUp, now);
+        dial(&mut sm, "42", now);
+        expire_timeout(&mut sm);
+        assert_eq!(sm.state, State::CallRejected);
+        assert_eq!(sm.sip.tone, Some(Tone::Congestion));
+        sm.handle_event(Event::Unregistered(408), now);
+        assert_eq!(sm.sip.tone, Some(Tone::Unregistered));
+    }
+
+    #[test]
+    fn test_registration() {
+        let (_send, recv) = channel();
+        let mut sm = StateMachine::new(
+            recv,
+            MockSip::default(),
+            MockBell::default(),
+            CallConfig::default(),
+            DialPlanConfig::default(),
+        );
+        let now = Instant::now();
+        assert_eq!(sm.state, State::Unregistered);
+
+        // No calls are possible without a registrar.
+        sm.handle_event(Event::EarpiecePickedUp, now);
+        dial(&mut sm, "1", now);
+        assert_eq!(sm.state, State::Unregistered);
+        sm.handle_event(Event::EarpiecePutDown, now);
+
+        sm.handle_event(Event::Registered, now);
+        assert_eq!(sm.state, State::Ready);
+
+        // Losing the registration during a call does not end the call.
+        sm.handle_event(Event::EarpiecePickedUp, now);
+        dial(&mut sm, "1", now);
+        expire_timeout(&mut sm);
+        sm.handle_event(Event::Unregistered(408), now);
+        assert_eq!(sm.state, State::ActiveCallRegistrationFailed);
+        sm.handle_event(Event::Registered, now);
+        assert_eq!(sm.state, State::ActiveCall);
+        sm.handle_event(Event::Unregistered(408), now);
+        sm.handle_event(Event::EarpiecePutDown, now);
+        assert_eq!(sm.state, State::Unregistered);
+        assert_eq!(sm.sip.hung_up, vec![call(101)]);
+    }
 }
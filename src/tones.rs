@@ -0,0 +1,182 @@
+//! Call-progress tones played in the earpiece.
+//!
+//! The tables only describe the tones. They are played by the SIP stack (see
+//! `Telephony::play_tone`).
+
+/// Call-progress tone.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Tone {
+    /// The phone is ready for dialing.
+    Dial,
+    /// The remote phone is ringing.
+    Ringback,
+    /// The remote party is busy or has hung up.
+    Busy,
+    /// The call could not be placed (e.g., network or server failure).
+    Congestion,
+    /// Another call is waiting while a call is active.
+    CallWaiting,
+    /// The phone is not registered and cannot place calls.
+    Unregistered,
+}
+
+/// Part of a tone, i.e., one or two frequencies played for `on_ms`,
+/// followed by `off_ms` of silence.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ToneSegment {
+    /// First frequency in Hz.
+    pub freq1: u16,
+    /// Second frequency in Hz, or 0 for a single-frequency tone.
+    pub freq2: u16,
+    pub on_ms: u16,
+    pub off_ms: u16,
+}
+
+impl ToneSegment {
+    const fn new(freq1: u16, freq2: u16, on_ms: u16, off_ms: u16) -> Self {
+        Self {
+            freq1,
+            freq2,
+            on_ms,
+            off_ms,
+        }
+    }
+}
+
+/// Country whose tones are used.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ToneCountry {
+    /// 425 Hz tones as used by the Deutsche Bundespost/Telekom.
+    #[default]
+    Germany,
+    /// Tones of the British Telecom network.
+    Uk,
+    /// Tones of the North American "precise tone plan".
+    Us,
+}
+
+/// "Special information tone", the three-tone sequence which indicates that a
+/// service is not available.
+const SPECIAL_INFORMATION: &[ToneSegment] = &[
+    ToneSegment::new(950, 0, 330, 0),
+    ToneSegment::new(1400, 0, 330, 0),
+    ToneSegment::new(1800, 0, 330, 1000),
+];
+
+const GERMANY_DIAL: &[ToneSegment] = &[ToneSegment::new(425, 0, 1000, 0)];
+const GERMANY_RINGBACK: &[ToneSegment] = &[ToneSegment::new(425, 0, 1000, 4000)];
+const GERMANY_BUSY: &[ToneSegment] = &[ToneSegment::new(425, 0, 480, 480)];
+const GERMANY_CONGESTION: &[ToneSegment] = &[ToneSegment::new(425, 0, 240, 240)];
+const GERMANY_CALL_WAITING: &[ToneSegment] = &[
+    ToneSegment::new(425, 0, 200, 200),
+    ToneSegment::new(425, 0, 200, 5000),
+];
+
+const UK_DIAL: &[ToneSegment] = &[ToneSegment::new(350, 450, 1000, 0)];
+const UK_RINGBACK: &[ToneSegment] = &[
+    ToneSegment::new(400, 450, 400, 200),
+    ToneSegment::new(400, 450, 400, 2000),
+];
+const UK_BUSY: &[ToneSegment] = &[ToneSegment::new(400, 0, 375, 375)];
+const UK_CONGESTION: &[ToneSegment] = &[
+    ToneSegment::new(400, 0, 400, 350),
+    ToneSegment::new(400, 0, 225, 525),
+];
+const UK_CALL_WAITING: &[ToneSegment] = &[ToneSegment::new(400, 0, 100, 2500)];
+
+const US_DIAL: &[ToneSegment] = &[ToneSegment::new(350, 440, 1000, 0)];
+const US_RINGBACK: &[ToneSegment] = &[ToneSegment::new(440, 480, 2000, 4000)];
+const US_BUSY: &[ToneSegment] = &[ToneSegment::new(480, 620, 500, 500)];
+const US_CONGESTION: &[ToneSegment] = &[ToneSegment::new(480, 620, 250, 250)];
+const US_CALL_WAITING: &[ToneSegment] = &[ToneSegment::new(440, 0, 300, 9700)];
+
+impl ToneCountry {
+    /// Returns the segments of the tone, which are repeated while the tone is
+    /// played.
+    pub fn segments(self, tone: Tone) -> &'static [ToneSegment] {
+        match (self, tone) {
+            (_, Tone::Unregistered) => SPECIAL_INFORMATION,
+            (ToneCountry::Germany, Tone::Dial) => GERMANY_DIAL,
+            (ToneCountry::Germany, Tone::Ringback) => GERMANY_RINGBACK,
+            (ToneCountry::Germany, Tone::Busy) => GERMANY_BUSY,
+            (ToneCountry::Germany, Tone::Congestion) => GERMANY_CONGESTION,
+            (ToneCountry::Germany, Tone::CallWaiting) => GERMANY_CALL_WAITING,
+            (ToneCountry::Uk, Tone::Dial) => UK_DIAL,
+            (ToneCountry::Uk, Tone::Ringback) => UK_RINGBACK,
+            (ToneCountry::Uk, Tone::Busy) => UK_BUSY,
+            (ToneCountry::Uk, Tone::Congestion) => UK_CONGESTION,
+            (ToneCountry::Uk, Tone::CallWaiting) => UK_CALL_WAITING,
+            (ToneCountry::Us, Tone::Dial) => US_DIAL,
+            (ToneCountry::Us, Tone::Ringback) => US_RINGBACK,
+            (ToneCountry::Us, Tone::Busy) => US_BUSY,
+            (ToneCountry::Us, Tone::Congestion) => US_CONGESTION,
+            (ToneCountry::Us, Tone::CallWaiting) => US_CALL_WAITING,
+        }
+    }
+}
+
+/// Returns the tone played after a call has ended with the SIP status code.
+pub fn tone_for_status(code: u32) -> Tone {
+    match code {
+        // The remote party hung up after the call, or is busy.
+        200..=299 | 486 | 600 | 603 => Tone::Busy,
+        _ => Tone::Congestion,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TONES: [Tone; 6] = [
+        Tone::Dial,
+        Tone::Ringback,
+        Tone::Busy,
+        Tone::Congestion,
+        Tone::CallWaiting,
+        Tone::Unregistered,
+    ];
+    const COUNTRIES: [ToneCountry; 3] = [ToneCountry::Germany, ToneCountry::Uk, ToneCountry::Us];
+
+    #[test]
+    fn test_tables() {
+        for &country in COUNTRIES.iter() {
+            for &tone in TONES.iter() {
+                let segments = country.segments(tone);
+                assert!(!segments.is_empty());
+                // pjmedia can only play a limited number of tones at once.
+                assert!(segments.len() <= 32);
+                assert!(segments.iter().all(|s| s.freq1 != 0 && s.on_ms != 0));
+            }
+            // All tones must be distinguishable.
+            for (i, &a) in TONES.iter().enumerate() {
+                for &b in TONES[i + 1..].iter() {
+                    assert_ne!(country.segments(a), country.segments(b));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_german_tones() {
+        let germany = ToneCountry::default();
+        assert_eq!(germany, ToneCountry::Germany);
+        for &tone in TONES[..5].iter() {
+            assert!(germany.segments(tone).iter().all(|s| s.freq1 == 425));
+        }
+        assert_eq!(
+            germany.segments(Tone::Busy),
+            &[ToneSegment::new(425, 0, 480, 480)]
+        );
+    }
+
+    #[test]
+    fn test_tone_for_status() {
+        assert_eq!(tone_for_status(200), Tone::Busy);
+        assert_eq!(tone_for_status(486), Tone::Busy);
+        assert_eq!(tone_for_status(603), Tone::Busy);
+        assert_eq!(tone_for_status(404), Tone::Congestion);
+        assert_eq!(tone_for_status(503), Tone::Congestion);
+    }
+}